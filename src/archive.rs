@@ -0,0 +1,132 @@
+//! Unpacks `/convert/archive` uploads: a zip with an `index.md` and the files it refers to.
+//!
+//! The archive is extracted into a directory of its own under `TEMP_DIR`, which becomes the
+//! base URL of the rendered page, so relative image and stylesheet paths in the markdown
+//! resolve to the bundled files.
+
+use anyhow::Context;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+/// Entry holding the markdown, at the root of the archive
+pub const INDEX_FILE: &str = "index.md";
+/// Maximum combined size of the extracted files, checked against the bytes actually written
+/// rather than the sizes the archive declares
+const MAX_EXTRACTED_BYTES: u64 = 100 * 1024 * 1024;
+/// Maximum number of entries in an archive
+const MAX_ENTRIES: usize = 1000;
+
+/// Returned when the upload is not a usable archive
+#[derive(Debug)]
+pub struct InvalidArchive(pub String);
+
+impl std::fmt::Display for InvalidArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidArchive {}
+
+/// An extracted archive, deleted from disk when dropped
+#[derive(Debug)]
+pub struct Extracted {
+    dir: PathBuf,
+    /// Contents of [`INDEX_FILE`]
+    pub markdown: String,
+}
+
+impl Extracted {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Extracted {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!(path = %self.dir.display(), error = %e, "Failed to remove extracted archive");
+        }
+    }
+}
+
+/// Extracts the zip archive into a new directory under `temp_dir`.
+///
+/// Entries whose path is absolute or leaves the directory, and symbolic links, fail the
+/// whole archive with [`InvalidArchive`] instead of being skipped.
+pub fn extract(bytes: &[u8], temp_dir: &Path) -> anyhow::Result<Extracted> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| InvalidArchive(format!("Upload is not a valid zip archive: {}", e)))?;
+    if archive.len() > MAX_ENTRIES {
+        return Err(InvalidArchive(format!(
+            "Archive has {} entries, more than the {} allowed",
+            archive.len(),
+            MAX_ENTRIES
+        ))
+        .into());
+    }
+
+    let dir = temp_dir.join(format!("archive-{}", Uuid::new_v4()));
+    fs::create_dir(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    // From here on the directory is removed again on every exit path
+    let mut extracted = Extracted {
+        dir,
+        markdown: String::new(),
+    };
+
+    let mut remaining = MAX_EXTRACTED_BYTES;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .map_err(|e| InvalidArchive(format!("Failed to read archive entry: {}", e)))?;
+        let name = entry.name().unwrap_or_default().to_string();
+        let Some(relative_path) = entry.enclosed_name() else {
+            return Err(InvalidArchive(format!(
+                "Archive entry '{}' has a path outside the archive",
+                name
+            ))
+            .into());
+        };
+        if entry.is_symlink() {
+            return Err(
+                InvalidArchive(format!("Archive entry '{}' is a symbolic link", name)).into(),
+            );
+        }
+
+        let path = extracted.dir.join(relative_path);
+        if entry.is_dir() {
+            fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        // Read one byte past the budget so an archive lying about its sizes is still caught
+        let mut file = fs::File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let written = io::copy(&mut (&mut entry).take(remaining + 1), &mut file)
+            .map_err(|e| InvalidArchive(format!("Failed to extract '{}': {}", name, e)))?;
+        if written > remaining {
+            return Err(InvalidArchive(format!(
+                "Archive contents exceed the {} byte limit",
+                MAX_EXTRACTED_BYTES
+            ))
+            .into());
+        }
+        remaining -= written;
+    }
+
+    let index_path = extracted.dir.join(INDEX_FILE);
+    let index = fs::read(&index_path)
+        .map_err(|_| InvalidArchive(format!("Archive has no {} at its root", INDEX_FILE)))?;
+    extracted.markdown = String::from_utf8(index)
+        .map_err(|_| InvalidArchive(format!("{} is not valid UTF-8", INDEX_FILE)))?;
+    Ok(extracted)
+}
@@ -0,0 +1,57 @@
+use crate::config::Config;
+use crate::ErrorResponse;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+
+/// Rejects requests without a valid API key when `API_KEY` is configured.
+///
+/// The key may be sent as `Authorization: Bearer <key>` or `X-API-Key: <key>`.
+pub async fn require_api_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let expected = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.api_key.clone());
+
+    if let Some(expected) = expected {
+        let authorized = provided_key(&req).is_some_and(|key| constant_time_eq(key, &expected));
+        if !authorized {
+            let response = HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(ErrorResponse::new("Missing or invalid API key"));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Extracts the API key from the Authorization or X-API-Key header
+fn provided_key(req: &ServiceRequest) -> Option<&str> {
+    let headers = req.headers();
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get("X-API-Key")
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+}
+
+/// Compares two keys without short-circuiting on the first differing byte
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
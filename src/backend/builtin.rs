@@ -0,0 +1,593 @@
+//! Pure-Rust backend that lays out the document text directly with lopdf.
+//!
+//! It understands headings, paragraphs, list items, block quotes and preformatted code.
+//! CSS, images and table layout are ignored, so output is plainer than wkhtmltopdf's, but
+//! it needs no external binaries.
+
+use super::PdfBackend;
+use crate::{length_to_points, MarkdownRequest, DEFAULT_MARGIN_SIDE};
+use anyhow::Context;
+use futures::future::BoxFuture;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, ObjectId, Stream, StringFormat};
+
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.5;
+const LINE_SPACING: f32 = 1.4;
+const LIST_INDENT: f32 = 18.0;
+
+pub struct BuiltinBackend;
+
+impl PdfBackend for BuiltinBackend {
+    fn name(&self) -> &'static str {
+        "builtin"
+    }
+
+    fn check(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn render<'a>(
+        &'a self,
+        html: &'a str,
+        request: &'a MarkdownRequest,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move { render_document(html, request) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Body,
+    Heading(u8),
+    Code,
+}
+
+impl Style {
+    fn font(self) -> &'static str {
+        match self {
+            Style::Body => "F1",
+            Style::Heading(_) => "F2",
+            Style::Code => "F3",
+        }
+    }
+
+    fn size(self) -> f32 {
+        match self {
+            Style::Body => BODY_SIZE,
+            Style::Heading(1) => 20.0,
+            Style::Heading(2) => 16.0,
+            Style::Heading(3) => 14.0,
+            Style::Heading(_) => 12.0,
+            Style::Code => CODE_SIZE,
+        }
+    }
+
+    /// Approximate average glyph width as a fraction of the font size
+    fn char_width(self) -> f32 {
+        match self {
+            Style::Body => 0.5,
+            Style::Heading(_) => 0.55,
+            Style::Code => 0.6,
+        }
+    }
+}
+
+/// A run of text laid out as one paragraph-like unit
+#[derive(Debug)]
+struct Block {
+    style: Style,
+    indent: f32,
+    text: String,
+    /// Continue on a new page after this block, as requested by a page break element
+    page_break_after: bool,
+}
+
+/// Splits the HTML body into text blocks using a minimal tag scanner
+fn extract_blocks(html: &str) -> Vec<Block> {
+    let body = html
+        .find("<body")
+        .and_then(|start| html[start..].find('>').map(|end| start + end + 1))
+        .map_or(html, |start| &html[start..]);
+
+    let mut blocks = Vec::new();
+    let mut text = String::new();
+    let mut style = Style::Body;
+    // Open lists, innermost last, with the next item number of ordered ones
+    let mut lists: Vec<Option<u32>> = Vec::new();
+    let mut quote_depth: usize = 0;
+    let mut rest = body;
+
+    let flush = |text: &mut String, style: Style, indent: f32, blocks: &mut Vec<Block>| {
+        let content = if style == Style::Code {
+            text.trim_end().to_string()
+        } else {
+            text.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+        if !content.is_empty() {
+            blocks.push(Block {
+                style,
+                indent,
+                text: content,
+                page_break_after: false,
+            });
+        }
+        text.clear();
+    };
+
+    while let Some(tag_start) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..tag_start]));
+        let Some(tag_end) = rest[tag_start..].find('>') else {
+            break;
+        };
+        let tag = &rest[tag_start + 1..tag_start + tag_end];
+        rest = &rest[tag_start + tag_end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let indent = (lists.len() + quote_depth) as f32 * LIST_INDENT;
+
+        match name.as_str() {
+            "body" if closing => break,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                flush(&mut text, style, indent, &mut blocks);
+                style = if closing {
+                    Style::Body
+                } else {
+                    Style::Heading(name.as_bytes()[1] - b'0')
+                };
+            }
+            "pre" => {
+                flush(&mut text, style, indent, &mut blocks);
+                style = if closing { Style::Body } else { Style::Code };
+            }
+            "ul" | "ol" => {
+                flush(&mut text, style, indent, &mut blocks);
+                if closing {
+                    lists.pop();
+                } else if name == "ol" {
+                    lists.push(Some(
+                        attribute(tag, "start")
+                            .and_then(|start| start.parse().ok())
+                            .unwrap_or(1),
+                    ));
+                } else {
+                    lists.push(None);
+                }
+            }
+            "blockquote" => {
+                flush(&mut text, style, indent, &mut blocks);
+                quote_depth = if closing {
+                    quote_depth.saturating_sub(1)
+                } else {
+                    quote_depth + 1
+                };
+            }
+            "li" => {
+                flush(&mut text, style, indent, &mut blocks);
+                if !closing {
+                    let ordered_depth = lists.iter().flatten().count();
+                    match lists.last_mut() {
+                        Some(Some(number)) => {
+                            text.push_str(&list_marker(*number, ordered_depth));
+                            *number = number.saturating_add(1);
+                        }
+                        _ => text.push_str("\u{2022} "),
+                    }
+                }
+            }
+            "br" if style == Style::Code => text.push('\n'),
+            "div" if !closing && tag.contains("page-break") => {
+                flush(&mut text, style, indent, &mut blocks);
+                if let Some(block) = blocks.last_mut() {
+                    block.page_break_after = true;
+                }
+            }
+            "p" | "div" | "br" | "hr" | "tr" | "table" | "dt" | "dd" | "nav" => {
+                flush(&mut text, style, indent, &mut blocks);
+            }
+            "td" | "th" if !closing => text.push(' '),
+            _ => {}
+        }
+    }
+    flush(&mut text, style, 0.0, &mut blocks);
+
+    blocks
+}
+
+/// Returns the value of a double-quoted attribute in an opening tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    tag[start..].find('"').map(|end| &tag[start..start + end])
+}
+
+/// Numbers an ordered list item like the wkhtmltopdf template: decimal, then lower-alpha,
+/// then lower-roman for lists nested inside other ordered lists
+fn list_marker(number: u32, ordered_depth: usize) -> String {
+    let label = match ordered_depth {
+        2 if number > 0 => {
+            let mut label = String::new();
+            let mut rest = number;
+            while rest > 0 {
+                rest -= 1;
+                label.insert(0, char::from(b'a' + (rest % 26) as u8));
+                rest /= 26;
+            }
+            label
+        }
+        3.. if number > 0 && number < 4000 => {
+            const NUMERALS: &[(u32, &str)] = &[
+                (1000, "m"),
+                (900, "cm"),
+                (500, "d"),
+                (400, "cd"),
+                (100, "c"),
+                (90, "xc"),
+                (50, "l"),
+                (40, "xl"),
+                (10, "x"),
+                (9, "ix"),
+                (5, "v"),
+                (4, "iv"),
+                (1, "i"),
+            ];
+            let mut label = String::new();
+            let mut rest = number;
+            for &(value, numeral) in NUMERALS {
+                while rest >= value {
+                    label.push_str(numeral);
+                    rest -= value;
+                }
+            }
+            label
+        }
+        _ => number.to_string(),
+    };
+    format!("{}. ", label)
+}
+
+/// Decodes the HTML entities comrak emits
+fn decode_entities(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|end| *end <= 10) else {
+            output.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(c) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Encodes text for the standard Type 1 fonts, replacing unsupported characters
+fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '\u{20}'..='\u{7e}' | '\u{a0}'..='\u{ff}' => c as u8,
+            '\t' => b' ',
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2026}' => 0x85,
+            '\u{20ac}' => 0x80,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Wraps text into lines that fit the available width
+fn wrap(text: &str, style: Style, width: f32) -> Vec<String> {
+    let max_chars = ((width / (style.size() * style.char_width())) as usize).max(1);
+    let mut lines = Vec::new();
+
+    for paragraph in text.split('\n') {
+        // Preformatted text keeps its spacing and is only broken at the line limit
+        if style == Style::Code {
+            let chars: Vec<char> = paragraph.chars().collect();
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(chars.chunks(max_chars).map(|chunk| chunk.iter().collect()));
+            continue;
+        }
+
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            if !line.is_empty() && line.chars().count() + word.chars().count() + 1 > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+
+            // Hard-break words that are longer than a full line
+            while line.chars().count() > max_chars {
+                let head: String = line.chars().take(max_chars).collect();
+                line = line.chars().skip(max_chars).collect();
+                lines.push(head);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn render_document(html: &str, request: &MarkdownRequest) -> anyhow::Result<Vec<u8>> {
+    let (page_width, page_height) = request.page_dimensions();
+    let (page_width, page_height) = (length_to_points(page_width), length_to_points(page_height));
+
+    let margins = request.margins.as_ref();
+    let margin = |value: Option<&str>, default: &str| length_to_points(value.unwrap_or(default));
+    let top = margin(
+        margins.and_then(|m| m.top.as_deref()),
+        crate::DEFAULT_MARGIN_TOP,
+    );
+    let bottom = margin(
+        margins.and_then(|m| m.bottom.as_deref()),
+        crate::DEFAULT_MARGIN_BOTTOM,
+    );
+    let left = margin(margins.and_then(|m| m.left.as_deref()), DEFAULT_MARGIN_SIDE);
+    let right = margin(
+        margins.and_then(|m| m.right.as_deref()),
+        DEFAULT_MARGIN_SIDE,
+    );
+
+    // Lay out every block into pages of positioned text operations
+    let mut pages: Vec<Vec<Operation>> = vec![Vec::new()];
+    let mut y = page_height - top;
+    let mut page_break = false;
+
+    for block in extract_blocks(html) {
+        let size = block.style.size();
+        let line_height = size * LINE_SPACING;
+        let x = left + block.indent;
+
+        // Breaks only take effect once more content follows, so none cause a blank last page
+        if std::mem::take(&mut page_break) {
+            pages.push(Vec::new());
+            y = page_height - top;
+        }
+
+        if matches!(block.style, Style::Heading(_)) {
+            y -= size * 0.5;
+        }
+
+        for line in wrap(&block.text, block.style, page_width - x - right) {
+            if y - line_height < bottom {
+                pages.push(Vec::new());
+                y = page_height - top;
+            }
+            y -= line_height;
+
+            let operations = pages.last_mut().expect("at least one page");
+            operations.push(Operation::new("BT", vec![]));
+            operations.push(Operation::new(
+                "Tf",
+                vec![block.style.font().into(), size.into()],
+            ));
+            operations.push(Operation::new("Td", vec![x.into(), y.into()]));
+            operations.push(Operation::new(
+                "Tj",
+                vec![Object::String(
+                    encode_win_ansi(&line),
+                    StringFormat::Literal,
+                )],
+            ));
+            operations.push(Operation::new("ET", vec![]));
+        }
+
+        y -= BODY_SIZE * 0.6;
+        page_break = block.page_break_after;
+    }
+
+    write_pdf(pages, page_width, page_height)
+}
+
+fn write_pdf(
+    pages: Vec<Vec<Operation>>,
+    page_width: f32,
+    page_height: f32,
+) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+
+    let font = |document: &mut Document, base_font: &str| -> ObjectId {
+        document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => base_font,
+            "Encoding" => "WinAnsiEncoding",
+        })
+    };
+    let regular = font(&mut document, "Helvetica");
+    let bold = font(&mut document, "Helvetica-Bold");
+    let mono = font(&mut document, "Courier");
+    let resources_id = document.add_object(dictionary! {
+        "Font" => dictionary! {
+            "F1" => regular,
+            "F2" => bold,
+            "F3" => mono,
+        },
+    });
+
+    let mut kids = Vec::new();
+    for operations in pages {
+        let content = Content { operations }
+            .encode()
+            .context("Failed to encode page content")?;
+        let content_id = document.add_object(Stream::new(dictionary! {}, content));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        });
+        kids.push(page_id.into());
+    }
+
+    let count = kids.len() as i64;
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), page_width.into(), page_height.into()],
+        }),
+    );
+
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+    document.compress();
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .context("Failed to write PDF")?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf;
+
+    fn request(markdown: &str) -> MarkdownRequest {
+        crate::tests::request(markdown, &[])
+    }
+
+    fn page_count(markdown: &str) -> usize {
+        let request = request(markdown);
+        let html = crate::markdown_to_html_converter(&request, "");
+        let pdf_bytes = render_document(&html, &request).unwrap();
+        pdf::page_count(&pdf_bytes, None).unwrap()
+    }
+
+    /// Text of each laid out block with its indentation level
+    fn list_lines(markdown: &str) -> Vec<(usize, String)> {
+        let html = crate::markdown_to_html_converter(&request(markdown), "");
+        extract_blocks(&html)
+            .into_iter()
+            .map(|block| ((block.indent / LIST_INDENT) as usize, block.text))
+            .collect()
+    }
+
+    #[test]
+    fn page_break_directive_starts_a_new_page() {
+        assert_eq!(page_count("# One\n\nText.\n"), 1);
+        assert_eq!(page_count("# One\n\n<!-- pagebreak -->\n\n# Two\n"), 2);
+        assert_eq!(
+            page_count("# One\n\n---pagebreak---\n\n# Two\n\n<!-- pagebreak -->\n\n# Three\n"),
+            3
+        );
+    }
+
+    #[test]
+    fn page_break_at_the_end_adds_no_blank_page() {
+        assert_eq!(page_count("# One\n\nText.\n\n<!-- pagebreak -->\n"), 1);
+    }
+
+    #[test]
+    fn ordered_lists_keep_their_start_value() {
+        let html = crate::markdown_to_html_converter(&request("5. Five\n6. Six\n"), "");
+        assert!(html.contains(r#"<ol start="5">"#), "{html}");
+
+        assert_eq!(
+            list_lines("5. Five\n6. Six\n"),
+            [(1, "5. Five".to_string()), (1, "6. Six".to_string())]
+        );
+
+        let pdf_bytes = render_document(&html, &request("")).unwrap();
+        let document = lopdf::Document::load_mem(&pdf_bytes).unwrap();
+        let page = *document.get_pages().get(&1).unwrap();
+        let content = String::from_utf8_lossy(&document.get_page_content(page)).into_owned();
+        assert!(content.contains("(5. Five)"), "{content}");
+    }
+
+    #[test]
+    fn list_numbers_stop_at_the_largest_start_value() {
+        let html = r#"<ol start="4294967295"><li>Last</li><li>Next</li></ol>"#;
+        let lines: Vec<_> = extract_blocks(html)
+            .into_iter()
+            .map(|block| block.text)
+            .collect();
+        assert_eq!(lines, ["4294967295. Last", "4294967295. Next"]);
+    }
+
+    #[test]
+    fn mixed_nested_lists_are_numbered_by_ordered_depth() {
+        let markdown = "\
+3. Three
+   - Bullet
+     1. Alpha
+     2. Beta
+        1. Roman one
+        2. Roman two
+4. Four
+";
+        let expected = [
+            (1, "3. Three"),
+            (2, "\u{2022} Bullet"),
+            (3, "a. Alpha"),
+            (3, "b. Beta"),
+            (4, "i. Roman one"),
+            (4, "ii. Roman two"),
+            (1, "4. Four"),
+        ];
+        let lines = list_lines(markdown);
+        let lines: Vec<(usize, &str)> = lines
+            .iter()
+            .map(|(depth, text)| (*depth, text.as_str()))
+            .collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn tall_table_spans_several_pages() {
+        assert!(page_count(&crate::tests::tall_table(120)) >= 2);
+    }
+}
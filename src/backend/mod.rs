@@ -0,0 +1,124 @@
+use crate::config::Config;
+use crate::MarkdownRequest;
+use futures::future::BoxFuture;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::Arc;
+
+mod builtin;
+mod wkhtmltopdf;
+
+pub use wkhtmltopdf::ConversionTimeout;
+
+/// Returned when the renderer ran but could not produce a PDF
+#[derive(Debug)]
+pub struct RenderFailed {
+    /// Client-safe summary of the renderer's error output
+    pub detail: String,
+}
+
+impl std::fmt::Display for RenderFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PDF rendering failed: {}", self.detail)
+    }
+}
+
+impl std::error::Error for RenderFailed {}
+
+/// Returned when the active backend cannot produce the requested output format
+#[derive(Debug)]
+pub struct Unsupported {
+    pub backend: &'static str,
+    pub output: &'static str,
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The {} backend cannot render {}",
+            self.backend, self.output
+        )
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+tokio::task_local! {
+    /// Warnings reported by renderers that succeeded, while [`collect_warnings`] is running
+    static WARNINGS: RefCell<Vec<String>>;
+}
+
+/// Runs `future` and returns its output with the warnings renderers reported meanwhile
+pub async fn collect_warnings<F: Future>(future: F) -> (F::Output, Vec<String>) {
+    WARNINGS
+        .scope(RefCell::new(Vec::new()), async {
+            let output = future.await;
+            (output, WARNINGS.with(RefCell::take))
+        })
+        .await
+}
+
+/// Records warnings for the surrounding [`collect_warnings`] call, if there is one
+pub fn report_warnings(warnings: Vec<String>) {
+    let _ = WARNINGS.try_with(|collected| collected.borrow_mut().extend(warnings));
+}
+
+/// Backends that can be selected with `PDF_BACKEND`
+pub const BACKENDS: &[&str] = &["wkhtmltopdf", "builtin"];
+
+/// Renders a complete HTML document into PDF bytes
+pub trait PdfBackend: Send + Sync {
+    /// Identifier used in `PDF_BACKEND` and reported by the health check
+    fn name(&self) -> &'static str;
+
+    /// Verifies that the backend's external dependencies are usable
+    fn check(&self) -> Result<(), String>;
+
+    /// Version of the external renderer, if the backend uses one, as read at startup
+    fn version(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether [`render`](PdfBackend::render) draws in gray when the request asks for
+    /// `grayscale`; otherwise the PDF is converted with Ghostscript afterwards
+    fn renders_grayscale(&self) -> bool {
+        false
+    }
+
+    fn render<'a>(
+        &'a self,
+        html: &'a str,
+        request: &'a MarkdownRequest,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>>;
+
+    /// Renders the first page as a PNG that is `width` pixels wide
+    fn render_png<'a>(
+        &'a self,
+        _html: &'a str,
+        _request: &'a MarkdownRequest,
+        _width: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        let backend = self.name();
+        Box::pin(async move {
+            Err(Unsupported {
+                backend,
+                output: "PNG previews",
+            }
+            .into())
+        })
+    }
+}
+
+/// Creates the backend selected by the configuration
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn PdfBackend>> {
+    match config.pdf_backend.as_str() {
+        "wkhtmltopdf" => Ok(Arc::new(wkhtmltopdf::WkhtmltopdfBackend::new(config))),
+        "builtin" => Ok(Arc::new(builtin::BuiltinBackend)),
+        other => anyhow::bail!(
+            "Unsupported PDF_BACKEND '{}'. Supported values: {}",
+            other,
+            BACKENDS.join(", ")
+        ),
+    }
+}
@@ -0,0 +1,729 @@
+use super::{report_warnings, PdfBackend, RenderFailed};
+use crate::config::Config;
+use crate::{logo, pagenumbers, MarkdownRequest};
+use anyhow::Context;
+use futures::future::BoxFuture;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Oldest release without known rendering bugs; older builds are still used, with a warning
+const MIN_VERSION: (u32, u32, u32) = (0, 12, 6);
+/// Maximum length of the error summary returned to clients
+const MAX_DETAIL_CHARS: usize = 500;
+/// Wait before the first retry of a failed run, growing with each further attempt
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// Errors that recur on every run, so retrying cannot help
+const DETERMINISTIC_ERRORS: &[&str] = &[
+    "Unknown long argument",
+    "Unknown switch",
+    "due to network error",
+    "Failed loading page",
+];
+
+/// Returned when wkhtmltopdf runs longer than the configured timeout
+#[derive(Debug)]
+pub struct ConversionTimeout(Duration);
+
+impl std::fmt::Display for ConversionTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "wkhtmltopdf did not finish within {} seconds and was stopped",
+            self.0.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for ConversionTimeout {}
+
+/// Creates a temporary file with the given content in `temp_dir` and returns its path
+fn create_temp_file(temp_dir: &Path, content: &str, extension: &str) -> anyhow::Result<PathBuf> {
+    let file_name = format!("{}.{}", Uuid::new_v4(), extension);
+    let file_path = temp_dir.join(file_name);
+
+    fs::write(&file_path, content)?;
+    Ok(file_path)
+}
+
+/// Deletes the tracked temporary files when dropped, so every exit path cleans up
+#[derive(Debug)]
+struct TempFiles {
+    paths: Vec<PathBuf>,
+    /// Keep the files and log where they are instead, for `DEBUG_KEEP_TEMP`
+    keep: bool,
+}
+
+impl TempFiles {
+    fn new(keep: bool) -> Self {
+        Self {
+            paths: Vec::new(),
+            keep,
+        }
+    }
+
+    fn track(&mut self, path: &Path) {
+        self.paths.push(path.to_path_buf());
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            if self.keep {
+                if path.exists() {
+                    info!(path = %path.display(), "Kept temporary file because DEBUG_KEEP_TEMP is set");
+                }
+                continue;
+            }
+            // The file may never have been created, e.g. when wkhtmltopdf failed
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Renders HTML by shelling out to the wkhtmltopdf command line tool
+pub struct WkhtmltopdfBackend {
+    /// wkhtmltopdf binary, from `WKHTMLTOPDF_PATH`
+    program: PathBuf,
+    /// wkhtmltoimage binary, expected next to wkhtmltopdf
+    image_program: PathBuf,
+    /// Maximum time a single wkhtmltopdf run may take before it is killed
+    timeout: Duration,
+    /// Directory for the input and output files
+    temp_dir: PathBuf,
+    /// Leave the input and output files in place after each run
+    keep_temp: bool,
+    /// Extra attempts after a PDF run that looks like a transient crash
+    retries: u32,
+    /// Output of `wkhtmltopdf --version` at startup, unless it could not be run
+    version: Option<String>,
+    /// Whether the binary accepts `--grayscale`, as listed by `--extended-help` at startup
+    grayscale: bool,
+}
+
+impl WkhtmltopdfBackend {
+    pub fn new(config: &Config) -> Self {
+        if config.debug_keep_temp {
+            warn!(
+                temp_dir = %config.temp_dir.display(),
+                "DEBUG_KEEP_TEMP is enabled: the HTML and PDF of every conversion are kept and \
+                 never deleted, so documents accumulate on disk. Disable it once done debugging"
+            );
+        }
+        let version = read_version(&config.wkhtmltopdf_path);
+        if let Some(version) = &version {
+            match parse_version(version) {
+                Some(number) if number < MIN_VERSION => warn!(
+                    version = %version,
+                    "wkhtmltopdf is older than {}.{}.{}, which fixes known rendering bugs; \
+                     documents may render differently than with current releases",
+                    MIN_VERSION.0,
+                    MIN_VERSION.1,
+                    MIN_VERSION.2
+                ),
+                Some(_) => info!(version = %version, "Found wkhtmltopdf"),
+                None => warn!(version = %version, "Could not read the wkhtmltopdf version"),
+            }
+        }
+        let grayscale = supports_option(&config.wkhtmltopdf_path, "--grayscale");
+        if version.is_some() && !grayscale {
+            warn!("wkhtmltopdf does not support --grayscale; grayscale PDFs are converted with Ghostscript");
+        }
+        Self {
+            program: config.wkhtmltopdf_path.clone(),
+            image_program: config.wkhtmltopdf_path.with_file_name("wkhtmltoimage"),
+            timeout: config.conversion_timeout,
+            temp_dir: config.temp_dir.clone(),
+            keep_temp: config.debug_keep_temp,
+            retries: config.wkhtmltopdf_retries,
+            version,
+            grayscale,
+        }
+    }
+}
+
+/// Runs `wkhtmltopdf --version`, returning `None` when the binary cannot be run
+fn read_version(program: &Path) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!version.is_empty()).then_some(version)
+}
+
+/// Runs `wkhtmltopdf --extended-help` and looks for `option` among the options it lists
+fn supports_option(program: &Path, option: &str) -> bool {
+    Command::new(program)
+        .arg("--extended-help")
+        .output()
+        .is_ok_and(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .any(|word| word == option)
+        })
+}
+
+/// Extracts the release number from output such as "wkhtmltopdf 0.12.6 (with patched qt)"
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    version.split_whitespace().find_map(|word| {
+        let parts: Vec<u32> = word
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        match parts[..] {
+            [major, minor, patch, ..] => Some((major, minor, patch)),
+            _ => None,
+        }
+    })
+}
+
+impl PdfBackend for WkhtmltopdfBackend {
+    fn name(&self) -> &'static str {
+        "wkhtmltopdf"
+    }
+
+    fn check(&self) -> Result<(), String> {
+        Command::new(&self.program)
+            .arg("--version")
+            .output()
+            .map(|_| ())
+            .map_err(|_| format!("wkhtmltopdf not found at '{}'", self.program.display()))
+    }
+
+    fn version(&self) -> Option<String> {
+        self.version.clone()
+    }
+
+    fn renders_grayscale(&self) -> bool {
+        self.grayscale
+    }
+
+    fn render<'a>(
+        &'a self,
+        html: &'a str,
+        request: &'a MarkdownRequest,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(self.html_to_pdf(html, request))
+    }
+
+    fn render_png<'a>(
+        &'a self,
+        html: &'a str,
+        request: &'a MarkdownRequest,
+        width: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(html_to_png(
+            &self.image_program,
+            html,
+            request,
+            width,
+            self.timeout,
+            &self.temp_dir,
+            self.keep_temp,
+        ))
+    }
+}
+
+impl WkhtmltopdfBackend {
+    /// Converts HTML to PDF using wkhtmltopdf command line tool
+    async fn html_to_pdf(&self, html: &str, request: &MarkdownRequest) -> anyhow::Result<Vec<u8>> {
+        // Create temporary HTML file
+        let mut temp_files = TempFiles::new(self.keep_temp);
+        let html_path = create_temp_file(&self.temp_dir, html, "html")
+            .context("Failed to create temporary HTML file")?;
+        temp_files.track(&html_path);
+
+        // Create temporary PDF file path
+        let pdf_path = html_path.with_extension("pdf");
+        temp_files.track(&pdf_path);
+
+        // Run wkhtmltopdf with page and margin settings
+        let mut command = tokio::process::Command::new(&self.program);
+        command
+            // Qt keeps its own scratch files in TMPDIR, which should share the configured space
+            .env("TMPDIR", &self.temp_dir)
+            .arg("--page-size")
+            .arg(request.page_size())
+            .arg("--orientation")
+            .arg(request.orientation())
+            .arg("--dpi")
+            .arg(request.dpi().to_string())
+            // Images are recompressed as JPEG at this quality and downsampled to this resolution
+            .arg("--image-quality")
+            .arg(request.image_quality().to_string())
+            .arg("--image-dpi")
+            .arg(request.image_dpi().to_string());
+
+        let margins = request.margins.as_ref();
+        command
+            .arg("--margin-top")
+            .arg(
+                margins
+                    .and_then(|m| m.top.as_deref())
+                    .unwrap_or(crate::DEFAULT_MARGIN_TOP),
+            )
+            .arg("--margin-bottom")
+            .arg(
+                margins
+                    .and_then(|m| m.bottom.as_deref())
+                    .unwrap_or(crate::DEFAULT_MARGIN_BOTTOM),
+            );
+        if let Some(left) = margins.and_then(|m| m.left.as_deref()) {
+            command.arg("--margin-left").arg(left);
+        }
+        if let Some(right) = margins.and_then(|m| m.right.as_deref()) {
+            command.arg("--margin-right").arg(right);
+        }
+
+        if let Some(title) = request.title() {
+            command.arg("--title").arg(title);
+        }
+
+        let draws_page_numbers = pagenumbers::is_drawn(request);
+        // The logo header is a separate page that wkhtmltopdf renders into each top margin
+        let header_html = match &request.header_logo {
+            Some(header_logo) => logo::header_html(header_logo).await,
+            None => None,
+        };
+        if let Some(header_html) = header_html {
+            let header_path = create_temp_file(&self.temp_dir, &header_html, "html")
+                .context("Failed to create temporary header file")?;
+            temp_files.track(&header_path);
+            command.arg("--header-html").arg(&header_path);
+        }
+        if let Some(header) = &request.header {
+            header.apply(&mut command, "header", draws_page_numbers);
+        }
+        if let Some(footer) = &request.footer {
+            footer.apply(&mut command, "footer", draws_page_numbers);
+        }
+        if request.page_offset() != 0 {
+            command
+                .arg("--page-offset")
+                .arg(request.page_offset().to_string());
+        }
+        if let Some(timestamp) = request.footer_timestamp() {
+            command.arg("--footer-right").arg(timestamp);
+        }
+
+        if !request.smart_shrinking {
+            command.arg("--disable-smart-shrinking");
+        }
+        apply_javascript(&mut command, request);
+        apply_local_file_access(&mut command, request);
+        command
+            .arg("--zoom")
+            .arg(request.zoom().to_string())
+            .arg("--print-media-type");
+        if !request.print_background() {
+            command.arg("--no-background");
+        }
+        // Without support the PDF is converted with Ghostscript afterwards
+        if request.grayscale && self.grayscale {
+            command.arg("--grayscale");
+        }
+        command.arg(&html_path).arg(&pdf_path);
+
+        // WebKit occasionally crashes on a document that renders fine the next time
+        let attempts = self.retries + 1;
+        for attempt in 1..=attempts {
+            match run(&mut command, self.timeout).await {
+                Ok(()) => {
+                    debug!(attempts = attempt, "wkhtmltopdf succeeded");
+                    break;
+                }
+                Err(e) if attempt < attempts && is_transient(&e, &pdf_path) => {
+                    warn!(
+                        attempt,
+                        retries = self.retries,
+                        error = %e,
+                        "wkhtmltopdf failed without writing a complete PDF; retrying"
+                    );
+                    let _ = fs::remove_file(&pdf_path);
+                    tokio::time::sleep(RETRY_BACKOFF * attempt).await;
+                }
+                Err(e) => {
+                    debug!(attempts = attempt, "wkhtmltopdf failed");
+                    return Err(e);
+                }
+            }
+        }
+
+        // Read the generated PDF
+        let pdf_content = fs::read(&pdf_path).context("Failed to read generated PDF")?;
+
+        if draws_page_numbers {
+            return pagenumbers::stamp(pdf_content, request);
+        }
+        Ok(pdf_content)
+    }
+}
+
+/// Renders the first page of the HTML to PNG using wkhtmltoimage.
+///
+/// The page is laid out at its print width and zoomed to `width`, then cropped to the
+/// page's aspect ratio so the image matches the first page of the PDF.
+async fn html_to_png(
+    program: &Path,
+    html: &str,
+    request: &MarkdownRequest,
+    width: u32,
+    timeout: Duration,
+    temp_dir: &Path,
+    keep_temp: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let mut temp_files = TempFiles::new(keep_temp);
+    let html_path =
+        create_temp_file(temp_dir, html, "html").context("Failed to create temporary HTML file")?;
+    temp_files.track(&html_path);
+    let png_path = html_path.with_extension("png");
+    temp_files.track(&png_path);
+
+    let (page_width, page_height) = request.page_dimensions();
+    let zoom = f64::from(width) / css_pixels(page_width);
+    let height = (css_pixels(page_height) * zoom).round() as u32;
+
+    let mut command = tokio::process::Command::new(program);
+    command
+        // Qt keeps its own scratch files in TMPDIR, which should share the configured space
+        .env("TMPDIR", temp_dir)
+        .arg("--format")
+        .arg("png")
+        .arg("--width")
+        .arg(width.to_string())
+        .arg("--height")
+        .arg(height.to_string())
+        .arg("--zoom")
+        .arg(zoom.to_string())
+        .arg("--disable-smart-width");
+    apply_javascript(&mut command, request);
+    apply_local_file_access(&mut command, request);
+    command.arg(&html_path).arg(&png_path);
+    run(&mut command, timeout).await?;
+
+    fs::read(&png_path).context("Failed to read generated PNG")
+}
+
+/// Turns scripts on or off, since wkhtmlto* runs them unless told otherwise
+fn apply_javascript(command: &mut tokio::process::Command, request: &MarkdownRequest) {
+    if !request.enable_javascript {
+        command.arg("--disable-javascript");
+        return;
+    }
+    command.arg("--enable-javascript");
+    if let Some(render_delay_ms) = request.render_delay_ms {
+        command
+            .arg("--javascript-delay")
+            .arg(render_delay_ms.to_string());
+    }
+}
+
+/// Keeps the page from reading local files other than those of the archive it was extracted
+/// from, so raw HTML cannot pull other files on the server into the output. Older wkhtmlto*
+/// versions allow local file access unless told otherwise, and `--allow` only narrows it
+/// while it is disabled.
+fn apply_local_file_access(command: &mut tokio::process::Command, request: &MarkdownRequest) {
+    command.arg("--disable-local-file-access");
+    if let Some(base_dir) = &request.base_dir {
+        command.arg("--allow").arg(base_dir);
+    }
+}
+
+/// Runs a wkhtmlto* command, killing it when it exceeds the timeout
+async fn run(command: &mut tokio::process::Command, timeout: Duration) -> anyhow::Result<()> {
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    command.kill_on_drop(true);
+
+    // Dropping the future on timeout kills the child process
+    let output = match tokio::time::timeout(timeout, command.output()).await {
+        Ok(output) => output.with_context(|| format!("Failed to execute {}", program))?,
+        Err(_) => return Err(ConversionTimeout(timeout).into()),
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(program = %program, status = %output.status, stderr = %stderr, "Renderer failed");
+        return Err(RenderFailed {
+            detail: summarize_stderr(&stderr),
+        }
+        .into());
+    }
+
+    // Missing images, stylesheets and fonts only produce warnings, and the PDF is still written
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!(program = %program, stderr = %stderr, "Renderer finished");
+    let warnings = collect_stderr_warnings(&stderr);
+    if !warnings.is_empty() {
+        warn!(program = %program, warnings = ?warnings, "Renderer reported warnings");
+        report_warnings(warnings);
+    }
+    Ok(())
+}
+
+/// Returns whether a failed run is worth retrying: the renderer exited with an error that
+/// is not known to recur, and left no PDF or only part of one
+fn is_transient(error: &anyhow::Error, pdf_path: &Path) -> bool {
+    let Some(failed) = error.downcast_ref::<RenderFailed>() else {
+        // Timeouts and a missing binary would fail the same way again
+        return false;
+    };
+    if DETERMINISTIC_ERRORS
+        .iter()
+        .any(|marker| failed.detail.contains(marker))
+    {
+        return false;
+    }
+    let output = fs::read(pdf_path).unwrap_or_default();
+    !output.trim_ascii_end().ends_with(b"%%EOF")
+}
+
+/// Converts a page dimension such as `210mm` or `8.5in` to CSS pixels at 96 DPI
+fn css_pixels(length: &str) -> f64 {
+    let (value, per_inch) = match length.strip_suffix("mm") {
+        Some(value) => (value, 25.4),
+        None => (length.trim_end_matches("in"), 1.0),
+    };
+    value.parse::<f64>().unwrap_or_default() / per_inch * 96.0
+}
+
+/// Picks the error lines out of wkhtmltopdf's output, which is mostly progress bars
+fn summarize_stderr(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let errors: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| line.to_lowercase().contains("error"))
+        .collect();
+
+    let summary = match (errors.is_empty(), lines.last()) {
+        (false, _) => errors.join("; "),
+        (true, Some(last)) => last.to_string(),
+        (true, None) => "wkhtmltopdf exited without output".to_string(),
+    };
+    truncate(&crate::redact_paths(&summary))
+}
+
+/// Picks the warning lines out of the output of a successful run
+fn collect_stderr_warnings(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.to_lowercase().starts_with("warning"))
+        .map(|line| truncate(&crate::redact_paths(line)))
+        .collect()
+}
+
+/// Shortens client-facing renderer output to at most `MAX_DETAIL_CHARS` characters
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_DETAIL_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Creates an empty directory that only this test uses
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("md-to-pdf-{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes an executable shell script named `wkhtmltopdf` into `dir`
+    fn stand_in(dir: &Path, script: &str) -> PathBuf {
+        let program = dir.join("wkhtmltopdf");
+        fs::write(&program, format!("#!/bin/sh\n{script}")).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        program
+    }
+
+    /// A stand-in that starts the output file, as a crashing run would, and then fails
+    fn failing_program(dir: &Path) -> PathBuf {
+        stand_in(
+            dir,
+            "for output; do :; done\nprintf '%%PDF-1.4\\n' > \"$output\"\n\
+             echo 'Error: Failed to load page' >&2\nexit 1\n",
+        )
+    }
+
+    fn backend(program: PathBuf, temp_dir: &Path, grayscale: bool) -> WkhtmltopdfBackend {
+        WkhtmltopdfBackend {
+            image_program: program.with_file_name("wkhtmltoimage"),
+            program,
+            timeout: Duration::from_secs(10),
+            temp_dir: temp_dir.to_path_buf(),
+            keep_temp: false,
+            retries: 0,
+            version: None,
+            grayscale,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_conversion_removes_its_temp_files() {
+        let bin_dir = scratch_dir("bin");
+        let temp_dir = scratch_dir("temp");
+        let backend = backend(failing_program(&bin_dir), &temp_dir, true);
+
+        let result = backend
+            .html_to_pdf("<p>Hello</p>", &MarkdownRequest::default())
+            .await;
+
+        let error = result.expect_err("the stand-in always fails");
+        assert!(error.downcast_ref::<RenderFailed>().is_some(), "{error:#}");
+        let left: Vec<_> = fs::read_dir(&temp_dir).unwrap().collect();
+        assert!(left.is_empty(), "temp files left behind: {left:?}");
+
+        fs::remove_dir_all(bin_dir).unwrap();
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn grayscale_support_is_read_from_the_extended_help() {
+        let with = scratch_dir("with-grayscale");
+        let program = stand_in(
+            &with,
+            "echo 'Global Options:'\necho '  -g, --grayscale      PDF will be generated in grayscale'\n",
+        );
+        assert!(supports_option(&program, "--grayscale"));
+
+        let without = scratch_dir("without-grayscale");
+        let program = stand_in(
+            &without,
+            "echo 'Global Options:'\necho '      --grayscale-ish  not the same option'\n",
+        );
+        assert!(!supports_option(&program, "--grayscale"));
+        assert!(!supports_option(&without.join("missing"), "--grayscale"));
+
+        fs::remove_dir_all(with).unwrap();
+        fs::remove_dir_all(without).unwrap();
+    }
+
+    /// A stand-in that writes its arguments to `args.txt` in `dir` and a PDF to its output
+    fn recording_program(dir: &Path) -> (PathBuf, PathBuf) {
+        let args_path = dir.join("args.txt");
+        let program = stand_in(
+            dir,
+            &format!(
+                "printf '%s\\n' \"$@\" > '{}'\nfor output; do :; done\nprintf '%%PDF-1.4\\n' > \"$output\"\n",
+                args_path.display()
+            ),
+        );
+        (program, args_path)
+    }
+
+    #[tokio::test]
+    async fn grayscale_is_only_passed_when_supported() {
+        let bin_dir = scratch_dir("args");
+        let temp_dir = scratch_dir("args-temp");
+        let (program, args_path) = recording_program(&bin_dir);
+        let request = MarkdownRequest {
+            grayscale: true,
+            ..Default::default()
+        };
+
+        for supported in [true, false] {
+            let backend = backend(program.clone(), &temp_dir, supported);
+            assert_eq!(backend.renders_grayscale(), supported);
+            backend.html_to_pdf("<p>Hello</p>", &request).await.unwrap();
+            let args = fs::read_to_string(&args_path).unwrap();
+            assert_eq!(
+                args.lines().any(|arg| arg == "--grayscale"),
+                supported,
+                "{args}"
+            );
+        }
+
+        fs::remove_dir_all(bin_dir).unwrap();
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_files_are_only_read_from_the_archive_directory() {
+        let bin_dir = scratch_dir("file-access");
+        let temp_dir = scratch_dir("file-access-temp");
+        let archive_dir = scratch_dir("file-access-archive");
+        let (program, args_path) = recording_program(&bin_dir);
+        let backend = backend(program, &temp_dir, true);
+        let html = r#"<iframe src="file:///etc/passwd"></iframe><img src="file:///etc/hostname">"#;
+
+        // Only the page itself is loaded, so the references to /etc stay blank
+        backend
+            .html_to_pdf(html, &MarkdownRequest::default())
+            .await
+            .unwrap();
+        let args = fs::read_to_string(&args_path).unwrap();
+        assert!(args.lines().any(|arg| arg == "--disable-local-file-access"));
+        assert!(!args.contains("--enable-local-file-access"), "{args}");
+        assert!(!args.lines().any(|arg| arg == "--allow"), "{args}");
+
+        let request = MarkdownRequest {
+            base_dir: Some(archive_dir.clone()),
+            ..Default::default()
+        };
+        backend.html_to_pdf(html, &request).await.unwrap();
+        let args = fs::read_to_string(&args_path).unwrap();
+        assert!(args.lines().any(|arg| arg == "--disable-local-file-access"));
+        assert!(!args.contains("--enable-local-file-access"), "{args}");
+        let allowed: Vec<_> = args
+            .lines()
+            .skip_while(|arg| *arg != "--allow")
+            .take(2)
+            .collect();
+        assert_eq!(
+            allowed,
+            ["--allow", archive_dir.to_str().unwrap()],
+            "{args}"
+        );
+
+        fs::remove_dir_all(bin_dir).unwrap();
+        fs::remove_dir_all(temp_dir).unwrap();
+        fs::remove_dir_all(archive_dir).unwrap();
+    }
+
+    #[test]
+    fn temp_files_are_removed_when_the_conversion_panics() {
+        let temp_dir = scratch_dir("panic");
+        let html_path = create_temp_file(&temp_dir, "<p>Hello</p>", "html").unwrap();
+
+        let outcome = std::panic::catch_unwind(|| {
+            let mut temp_files = TempFiles::new(false);
+            temp_files.track(&html_path);
+            // Never created, which must not stop the other file from being removed
+            temp_files.track(&html_path.with_extension("pdf"));
+            panic!("conversion panicked");
+        });
+
+        assert!(outcome.is_err());
+        assert!(!html_path.exists());
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn debug_keep_temp_leaves_files_in_place() {
+        let temp_dir = scratch_dir("keep");
+        let html_path = create_temp_file(&temp_dir, "<p>Hello</p>", "html").unwrap();
+
+        let mut temp_files = TempFiles::new(true);
+        temp_files.track(&html_path);
+        drop(temp_files);
+
+        assert!(html_path.exists());
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use crate::MarkdownRequest;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// A single named document inside a batch request; accepts every `/convert` option
+#[derive(Debug, Deserialize)]
+pub struct BatchDocument {
+    pub name: String,
+    #[serde(flatten)]
+    pub request: MarkdownRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub documents: Vec<BatchDocument>,
+}
+
+/// Packs the converted documents into a zip archive.
+///
+/// Each successful document becomes `<name>.pdf`; failures are listed in `errors.txt`
+/// instead of aborting the whole batch.
+pub fn write_zip(
+    documents: &[BatchDocument],
+    results: Vec<anyhow::Result<Vec<u8>>>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut used_names = HashSet::new();
+    let mut errors = Vec::new();
+
+    for (index, (document, result)) in documents.iter().zip(results).enumerate() {
+        match result {
+            Ok(pdf_bytes) => {
+                let name = unique_name(&document.name, index, &mut used_names);
+                zip.start_file(name, options)
+                    .context("Failed to add PDF to zip archive")?;
+                zip.write_all(&pdf_bytes)
+                    .context("Failed to write PDF to zip archive")?;
+            }
+            Err(e) => errors.push(format!("{}: {:#}", document.name, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        zip.start_file("errors.txt", options)
+            .context("Failed to add errors.txt to zip archive")?;
+        zip.write_all(errors.join("\n").as_bytes())
+            .context("Failed to write errors.txt to zip archive")?;
+    }
+
+    Ok(zip
+        .finish()
+        .context("Failed to finish zip archive")?
+        .into_inner())
+}
+
+/// Derives a safe `.pdf` entry name, suffixing duplicates so no entry is overwritten
+fn unique_name(name: &str, index: usize, used_names: &mut HashSet<String>) -> String {
+    let base =
+        crate::sanitize_pdf_filename(name).unwrap_or_else(|| format!("document-{}.pdf", index + 1));
+    let stem = base[..base.len() - ".pdf".len()].to_string();
+
+    let mut candidate = base;
+    let mut suffix = 2;
+    while !used_names.insert(candidate.clone()) {
+        candidate = format!("{}-{}.pdf", stem, suffix);
+        suffix += 1;
+    }
+    candidate
+}
@@ -0,0 +1,195 @@
+use crate::backend::PdfBackend;
+use crate::coalesce::InFlight;
+use crate::{template, MarkdownRequest};
+use actix_web::http::header::EntityTag;
+use lru::LruCache;
+use prometheus::{IntCounter, Registry};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// SHA-256 of everything that affects the rendered output
+pub type CacheKey = [u8; 32];
+
+/// In-memory LRU cache of rendered PDFs, disabled when created with a capacity of zero.
+///
+/// Identical conversions running at the same time are coalesced even when it is disabled.
+pub struct PdfCache {
+    entries: Option<Mutex<LruCache<CacheKey, Vec<u8>>>>,
+    in_flight: InFlight,
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+impl PdfCache {
+    pub fn new(capacity: usize) -> prometheus::Result<Self> {
+        Ok(Self {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            in_flight: InFlight::new()?,
+            hits: IntCounter::new(
+                "pdf_cache_hits_total",
+                "Total number of conversions served from the PDF cache",
+            )?,
+            misses: IntCounter::new(
+                "pdf_cache_misses_total",
+                "Total number of conversions that were not in the PDF cache",
+            )?,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    /// Exposes the coalescing counter, and the hit and miss counters when the cache is
+    /// enabled, through the given registry
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        self.in_flight.register(registry)?;
+        if self.is_enabled() {
+            registry.register(Box::new(self.hits.clone()))?;
+            registry.register(Box::new(self.misses.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// Identifies requests that produce the same PDF, so cached PDFs are found and identical
+    /// conversions are coalesced before a conversion slot is taken or anything is rendered.
+    ///
+    /// Besides the serialized options this covers the directory an archive upload was
+    /// extracted to, since its bundled files are part of the document. Every upload gets a
+    /// directory of its own, so archive requests are never shared with another client.
+    /// Remote images and fonts are not covered, so a cached PDF keeps the versions served
+    /// when it was first rendered.
+    pub fn key(request: &MarkdownRequest) -> CacheKey {
+        let options = serde_json::to_vec(request).expect("requests always serialize");
+        let mut hasher = Sha256::new();
+        hasher.update((options.len() as u64).to_le_bytes());
+        hasher.update(&options);
+        if let Some(base_dir) = &request.base_dir {
+            hasher.update(base_dir.as_os_str().as_encoded_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Derives the ETag of the PDF a request produces from every request option, including the
+    /// markdown, the service version, the backend and its version, and the page template and
+    /// stylesheet installed at startup.
+    ///
+    /// The tag is weak because wkhtmltopdf stamps each PDF with its creation time, so the
+    /// same request yields equivalent rather than byte-identical files. Like the cache key it
+    /// does not cover the generated HTML, so it can be checked before anything is rendered.
+    pub fn entity_tag(request: &MarkdownRequest, backend: &dyn PdfBackend) -> EntityTag {
+        let options = serde_json::to_vec(request).expect("requests always serialize");
+        let mut hasher = Sha256::new();
+        let server_inputs = [
+            env!("CARGO_PKG_VERSION"),
+            backend.name(),
+            &backend.version().unwrap_or_default(),
+            template::custom().unwrap_or_default(),
+            template::default_css(),
+        ];
+        for input in server_inputs {
+            hasher.update((input.len() as u64).to_le_bytes());
+            hasher.update(input.as_bytes());
+        }
+        hasher.update(&options);
+        let digest: String = hasher.finalize()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        EntityTag::new_weak(digest)
+    }
+
+    /// Runs `render`, unless an identical conversion is running already and can be shared
+    pub async fn coalesce(
+        &self,
+        key: CacheKey,
+        render: impl Future<Output = anyhow::Result<Vec<u8>>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.in_flight.run(key, render).await
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let entries = self.entries.as_ref()?;
+        let pdf_bytes = entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned();
+
+        match &pdf_bytes {
+            Some(_) => self.hits.inc(),
+            None => self.misses.inc(),
+        }
+        pdf_bytes
+    }
+
+    pub fn insert(&self, key: CacheKey, pdf_bytes: &[u8]) {
+        if let Some(entries) = &self.entries {
+            entries
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .put(key, pdf_bytes.to_vec());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::BoxFuture;
+
+    struct Renderer {
+        name: &'static str,
+        version: Option<&'static str>,
+    }
+
+    impl PdfBackend for Renderer {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn check(&self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn version(&self) -> Option<String> {
+            self.version.map(str::to_string)
+        }
+
+        fn render<'a>(
+            &'a self,
+            _html: &'a str,
+            _request: &'a MarkdownRequest,
+        ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+            unreachable!("entity tags are derived without rendering")
+        }
+    }
+
+    #[test]
+    fn entity_tag_changes_with_the_backend_and_its_version() {
+        let request = MarkdownRequest {
+            markdown: Some("# Report".to_string()),
+            ..Default::default()
+        };
+        let old = Renderer {
+            name: "wkhtmltopdf",
+            version: Some("0.12.5"),
+        };
+        let new = Renderer {
+            name: "wkhtmltopdf",
+            version: Some("0.12.6"),
+        };
+        let builtin = Renderer {
+            name: "builtin",
+            version: None,
+        };
+
+        let tag = PdfCache::entity_tag(&request, &old);
+        assert!(tag.weak_eq(&PdfCache::entity_tag(&request, &old)));
+        assert!(!tag.weak_eq(&PdfCache::entity_tag(&request, &new)));
+        assert!(!tag.weak_eq(&PdfCache::entity_tag(&request, &builtin)));
+    }
+}
@@ -0,0 +1,116 @@
+//! GitHub-style alerts: block quotes that start with `[!NOTE]`, `[!TIP]`, `[!IMPORTANT]`,
+//! `[!WARNING]` or `[!CAUTION]` on a line of their own, enabled with the `alerts` extension.
+//!
+//! Matching quotes get placeholder paragraphs as their first and last child, which
+//! [`insert`] turns into the opening and closing tags of a callout `<div>` once comrak has
+//! rendered the document.
+
+use comrak::arena_tree::Node;
+use comrak::nodes::{Ast, AstNode, LineColumn, NodeValue};
+use comrak::Arena;
+use regex::{Captures, Regex};
+use std::cell::RefCell;
+use std::sync::LazyLock;
+
+/// Alert kinds with the title shown above their content
+const KINDS: &[(&str, &str)] = &[
+    ("note", "Note"),
+    ("tip", "Tip"),
+    ("important", "Important"),
+    ("warning", "Warning"),
+    ("caution", "Caution"),
+];
+
+/// Delimits the placeholders; private use characters cannot clash with document text
+const MARKER: char = '\u{E002}';
+const CLOSE: &str = "/";
+
+static OPENING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        "<blockquote[^>]*>\n<p>{MARKER}([a-z]+){MARKER}</p>\n"
+    ))
+    .expect("valid regex")
+});
+static CLOSING: LazyLock<String> =
+    LazyLock::new(|| format!("<p>{MARKER}{CLOSE}{MARKER}</p>\n</blockquote>"));
+
+/// Replaces the alert marker of every matching block quote with placeholders
+pub fn mark<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    let quotes: Vec<_> = root
+        .descendants()
+        .filter(|node| matches!(node.data.borrow().value, NodeValue::BlockQuote))
+        .collect();
+
+    for quote in quotes {
+        let Some(kind) = quote.first_child().and_then(strip_marker) else {
+            continue;
+        };
+        let line = quote.data.borrow().sourcepos.start.line;
+        quote.prepend(placeholder(arena, kind, line));
+        quote.append(placeholder(arena, CLOSE, line));
+    }
+}
+
+/// Turns the placeholders left by [`mark`] into callout elements
+pub fn insert(html: &str) -> String {
+    let html = OPENING.replace_all(html, |captures: &Captures| {
+        let kind = &captures[1];
+        let title = KINDS
+            .iter()
+            .find(|(name, _)| *name == kind)
+            .map_or(kind, |(_, title)| title);
+        format!(
+            "<div class=\"callout callout-{}\">\n<p class=\"callout-title\">{}</p>\n",
+            kind, title
+        )
+    });
+    html.replace(CLOSING.as_str(), "</div>")
+}
+
+/// Removes an alert marker from the start of the paragraph, returning the alert kind
+fn strip_marker<'a>(paragraph: &'a AstNode<'a>) -> Option<&'static str> {
+    if !matches!(paragraph.data.borrow().value, NodeValue::Paragraph) {
+        return None;
+    }
+
+    // comrak may split the marker into several text nodes at the brackets
+    let mut marker = String::new();
+    let mut marker_nodes = Vec::new();
+    for node in paragraph.children() {
+        match &node.data.borrow().value {
+            NodeValue::Text(text) => marker.push_str(text),
+            NodeValue::SoftBreak | NodeValue::LineBreak => {
+                marker_nodes.push(node);
+                break;
+            }
+            _ => return None,
+        }
+        marker_nodes.push(node);
+    }
+
+    let name = marker
+        .trim()
+        .strip_prefix("[!")?
+        .strip_suffix(']')?
+        .to_ascii_lowercase();
+    let (kind, _) = KINDS.iter().find(|(kind, _)| *kind == name)?;
+
+    for node in marker_nodes {
+        node.detach();
+    }
+    if paragraph.first_child().is_none() {
+        paragraph.detach();
+    }
+    Some(kind)
+}
+
+/// Creates a paragraph holding only a placeholder
+fn placeholder<'a>(arena: &'a Arena<AstNode<'a>>, name: &str, line: usize) -> &'a AstNode<'a> {
+    let new_node = |value| {
+        let start = LineColumn { line, column: 1 };
+        &*arena.alloc(Node::new(RefCell::new(Ast::new(value, start))))
+    };
+    let paragraph = new_node(NodeValue::Paragraph);
+    paragraph.append(new_node(NodeValue::Text(format!("{MARKER}{name}{MARKER}"))));
+    paragraph
+}
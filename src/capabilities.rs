@@ -0,0 +1,91 @@
+//! Summary of the supported option values and defaults, served at `/capabilities`.
+//!
+//! It is a lighter alternative to `/openapi.json` for clients that only need the choices,
+//! e.g. to fill dropdowns. Every value is taken from the constants request validation uses.
+
+use crate::{
+    backend, logo, themes, COLUMNS_RANGE, DEFAULT_BLEED, DEFAULT_COLUMN_GAP, DEFAULT_DPI,
+    DEFAULT_EXTENSIONS, DEFAULT_FILENAME, DEFAULT_FONT_SIZE, DEFAULT_IMAGE_DPI,
+    DEFAULT_IMAGE_QUALITY, DEFAULT_INPUT_FORMAT, DEFAULT_MARGIN_BOTTOM, DEFAULT_MARGIN_SIDE,
+    DEFAULT_MARGIN_TOP, DEFAULT_ORIENTATION, DEFAULT_PAGE_NUMBER_STYLE, DEFAULT_PAGE_SIZE,
+    DEFAULT_PREVIEW_WIDTH, DEFAULT_TIMESTAMP_FORMAT, DEFAULT_WATERMARK_OPACITY,
+    DEFAULT_WATERMARK_ROTATION, DEFAULT_ZOOM, DIRECTIONS, DPI_RANGE, EXTENSIONS, FLAVORS,
+    GFM_EXTENSIONS, IMAGE_DPI_RANGE, IMAGE_QUALITY_RANGE, INPUT_FORMATS, ORIENTATIONS,
+    PAGE_NUMBER_STYLES, PAGE_SIZES, PREVIEW_WIDTH_RANGE, RENDER_DELAY_RANGE, ZOOM_RANGE,
+};
+use serde_json::{json, Value};
+use std::ops::RangeInclusive;
+use std::sync::LazyLock;
+
+static CAPABILITIES: LazyLock<Value> = LazyLock::new(capabilities);
+
+/// Returns the supported values, along with the backend the server converts with
+pub fn describe(backend: &str) -> Value {
+    let mut capabilities = CAPABILITIES.clone();
+    capabilities["backend"] = json!(backend);
+    capabilities
+}
+
+fn capabilities() -> Value {
+    let page_sizes: Vec<Value> = PAGE_SIZES
+        .iter()
+        .map(|(name, width, height)| json!({ "name": name, "width": width, "height": height }))
+        .collect();
+    let themes: Vec<&str> = themes::THEMES.iter().map(|theme| theme.name).collect();
+
+    json!({
+        "page_sizes": page_sizes,
+        "orientations": ORIENTATIONS,
+        "input_formats": INPUT_FORMATS,
+        "flavors": FLAVORS,
+        "extensions": EXTENSIONS,
+        "gfm_extensions": GFM_EXTENSIONS,
+        "themes": themes,
+        "directions": DIRECTIONS,
+        "page_number_styles": PAGE_NUMBER_STYLES,
+        "logo_positions": logo::POSITIONS,
+        "backends": backend::BACKENDS,
+        "ranges": {
+            "dpi": range(&DPI_RANGE),
+            "zoom": range(&ZOOM_RANGE),
+            "image_quality": range(&IMAGE_QUALITY_RANGE),
+            "image_dpi": range(&IMAGE_DPI_RANGE),
+            "columns": range(&COLUMNS_RANGE),
+            "render_delay_ms": range(&RENDER_DELAY_RANGE),
+            "preview_width": range(&PREVIEW_WIDTH_RANGE),
+        },
+        "defaults": {
+            "page_size": DEFAULT_PAGE_SIZE,
+            "orientation": DEFAULT_ORIENTATION,
+            "input_format": DEFAULT_INPUT_FORMAT,
+            "extensions": DEFAULT_EXTENSIONS,
+            "theme": themes::DEFAULT_THEME,
+            "font_size": DEFAULT_FONT_SIZE,
+            "margins": {
+                "top": DEFAULT_MARGIN_TOP,
+                "bottom": DEFAULT_MARGIN_BOTTOM,
+                "left": DEFAULT_MARGIN_SIDE,
+                "right": DEFAULT_MARGIN_SIDE,
+            },
+            "dpi": DEFAULT_DPI,
+            "zoom": DEFAULT_ZOOM,
+            "image_quality": DEFAULT_IMAGE_QUALITY,
+            "image_dpi": DEFAULT_IMAGE_DPI,
+            "columns": COLUMNS_RANGE.start(),
+            "column_gap": DEFAULT_COLUMN_GAP,
+            "filename": DEFAULT_FILENAME,
+            "page_number_style": DEFAULT_PAGE_NUMBER_STYLE,
+            "timestamp_format": DEFAULT_TIMESTAMP_FORMAT,
+            "bleed": DEFAULT_BLEED,
+            "watermark_opacity": DEFAULT_WATERMARK_OPACITY,
+            "watermark_rotation": DEFAULT_WATERMARK_ROTATION,
+            "logo_position": logo::DEFAULT_POSITION,
+            "logo_height": logo::DEFAULT_HEIGHT,
+            "preview_width": DEFAULT_PREVIEW_WIDTH,
+        },
+    })
+}
+
+fn range<T: serde::Serialize>(range: &RangeInclusive<T>) -> Value {
+    json!({ "min": range.start(), "max": range.end() })
+}
@@ -0,0 +1,132 @@
+use crate::backend::PdfBackend;
+use crate::MarkdownRequest;
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Markdown to PDF converter, run as an HTTP service or as a one-off command
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (the default when no subcommand is given)
+    Serve,
+    /// Convert a markdown file to PDF without starting the server
+    Convert(ConvertArgs),
+    /// Convert a built-in sample document and check the result, without starting the server
+    Selftest,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// Markdown file to convert
+    pub input: PathBuf,
+    /// Where to write the PDF; defaults to the input path with a .pdf extension
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Page size, e.g. A4 or Letter
+    #[arg(long)]
+    pub page_size: Option<String>,
+    /// Page orientation, Portrait or Landscape
+    #[arg(long)]
+    pub orientation: Option<String>,
+    /// Document theme, e.g. light or dark
+    #[arg(long)]
+    pub theme: Option<String>,
+    /// Insert a table of contents
+    #[arg(long)]
+    pub toc: bool,
+}
+
+/// Document converted by `selftest`, touching the main parts of the template
+const SAMPLE_DOCUMENT: &str = r#"# Self-test
+
+This document checks that **markdown** is rendered to *PDF*.
+
+## Table
+
+| Name | Value |
+| ---- | ----- |
+| one  | 1     |
+
+## Code
+
+```rust
+fn main() {
+    println!("Hello, world!");
+}
+```
+
+- First item
+- Second item
+"#;
+
+/// Converts the input file with the same pipeline the server uses and writes the PDF
+pub async fn convert(args: ConvertArgs, backend: &dyn PdfBackend) -> anyhow::Result<PathBuf> {
+    backend.check().map_err(|reason| {
+        anyhow::anyhow!("{} backend is unavailable: {}", backend.name(), reason)
+    })?;
+
+    let markdown = tokio::fs::read_to_string(&args.input)
+        .await
+        .with_context(|| format!("Failed to read {}", args.input.display()))?;
+    let request = MarkdownRequest {
+        markdown: Some(markdown),
+        page_size: args.page_size,
+        orientation: args.orientation,
+        theme: args.theme,
+        toc: args.toc,
+        ..Default::default()
+    };
+    request.validate().map_err(anyhow::Error::msg)?;
+
+    let pdf_bytes = crate::render_pdf(&request, backend, None).await?;
+
+    let output = args
+        .output
+        .unwrap_or_else(|| args.input.with_extension("pdf"));
+    tokio::fs::write(&output, pdf_bytes)
+        .await
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    Ok(output)
+}
+
+/// Converts [`SAMPLE_DOCUMENT`] and checks that the result is a PDF with at least one page,
+/// returning a summary of the run
+pub async fn selftest(backend: &dyn PdfBackend) -> anyhow::Result<String> {
+    backend.check().map_err(|reason| {
+        anyhow::anyhow!("{} backend is unavailable: {}", backend.name(), reason)
+    })?;
+    let version = backend.version();
+
+    let request = MarkdownRequest {
+        markdown: Some(SAMPLE_DOCUMENT.to_string()),
+        extensions: Some(vec!["table".to_string()]),
+        ..Default::default()
+    };
+    let pdf_bytes = crate::render_pdf(&request, backend, None).await?;
+    if pdf_bytes.is_empty() {
+        anyhow::bail!("{} backend produced an empty file", backend.name());
+    }
+    if !pdf_bytes.starts_with(b"%PDF") {
+        anyhow::bail!("{} backend output is not a PDF", backend.name());
+    }
+    let pages =
+        crate::pdf::page_count(&pdf_bytes, None).context("Generated PDF could not be read")?;
+    if pages == 0 {
+        anyhow::bail!("Generated PDF has no pages");
+    }
+
+    Ok(format!(
+        "Self-test passed: {} backend{}, {} page(s), {} bytes",
+        backend.name(),
+        version.map(|v| format!(" ({})", v)).unwrap_or_default(),
+        pages,
+        pdf_bytes.len()
+    ))
+}
@@ -0,0 +1,199 @@
+//! Coalesces identical conversions that run at the same time.
+//!
+//! The first request for a key renders the PDF. Requests with the same key that arrive while
+//! it waits for a conversion slot or runs wait for its result, including its warnings and
+//! errors, instead of taking a slot and starting a renderer of their own. This keeps a burst
+//! of identical requests, such as a cache stampede, to a single wkhtmltopdf process whether
+//! or not the PDF cache is enabled. When the first request is cancelled before it finishes,
+//! the waiting requests render the PDF themselves.
+
+use crate::backend;
+use crate::cache::CacheKey;
+use prometheus::{IntCounter, Registry};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+/// Returned to every request that shared a failed conversion, wrapping its error
+#[derive(Debug, Clone)]
+pub struct SharedFailure(pub Arc<anyhow::Error>);
+
+impl std::fmt::Display for SharedFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for SharedFailure {}
+
+/// Result of a finished conversion and the warnings its renderer reported
+type Outcome = (Result<Vec<u8>, SharedFailure>, Vec<String>);
+
+/// Result of each running conversion, `None` until it is done
+type Running = HashMap<CacheKey, watch::Receiver<Option<Arc<Outcome>>>>;
+
+/// Conversions currently running, by the key of their output
+pub struct InFlight {
+    running: Mutex<Running>,
+    coalesced: IntCounter,
+}
+
+/// Unregisters the conversion of the first request when it finishes or is cancelled
+struct Registration<'a> {
+    in_flight: &'a InFlight,
+    key: CacheKey,
+    finished: bool,
+}
+
+impl Registration<'_> {
+    fn finish(mut self) {
+        self.finished = true;
+        self.in_flight.running().remove(&self.key);
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        // Waiting requests see the sender of a cancelled conversion close
+        if !self.finished {
+            self.in_flight.running().remove(&self.key);
+        }
+    }
+}
+
+impl InFlight {
+    pub fn new() -> prometheus::Result<Self> {
+        Ok(Self {
+            running: Mutex::new(HashMap::new()),
+            coalesced: IntCounter::new(
+                "pdf_conversions_coalesced_total",
+                "Total number of conversions that waited for an identical running conversion",
+            )?,
+        })
+    }
+
+    /// Exposes the coalesced conversion counter through the given registry
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.coalesced.clone()))
+    }
+
+    /// Runs `render`, or waits for the running conversion with the same key instead
+    pub async fn run(
+        &self,
+        key: CacheKey,
+        render: impl Future<Output = anyhow::Result<Vec<u8>>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let sender = {
+            let mut running = self.running();
+            match running.get(&key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    running.insert(key, receiver);
+                    Ok(sender)
+                }
+            }
+        };
+
+        let sender = match sender {
+            Ok(sender) => sender,
+            Err(mut receiver) => {
+                self.coalesced.inc();
+                debug!("Waiting for an identical conversion that is already running");
+                match receiver.wait_for(Option::is_some).await {
+                    Ok(outcome) => {
+                        let (result, warnings) = outcome.as_deref().expect("waited for Some");
+                        backend::report_warnings(warnings.clone());
+                        return result.clone().map_err(anyhow::Error::new);
+                    }
+                    Err(_) => {
+                        debug!("The identical conversion was cancelled; converting instead");
+                        return render.await;
+                    }
+                }
+            }
+        };
+
+        let registration = Registration {
+            in_flight: self,
+            key,
+            finished: false,
+        };
+        let (result, warnings) = backend::collect_warnings(render).await;
+        backend::report_warnings(warnings.clone());
+        registration.finish();
+        let waiting = sender.receiver_count();
+        if waiting == 0 {
+            return result;
+        }
+
+        info!(waiting, "Shared a conversion with identical requests");
+        let result = result.map_err(|e| SharedFailure(Arc::new(e)));
+        let _ = sender.send(Some(Arc::new((result.clone(), warnings))));
+        result.map_err(anyhow::Error::new)
+    }
+
+    fn running(&self) -> std::sync::MutexGuard<'_, Running> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn identical_conversions_share_one_rendering() {
+        let in_flight = InFlight::new().unwrap();
+        let renders = AtomicUsize::new(0);
+        let render = |delay| {
+            let renders = &renders;
+            async move {
+                renders.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+                Ok(b"%PDF-1.4".to_vec())
+            }
+        };
+
+        let (first, second) = tokio::join!(
+            in_flight.run([1; 32], render(Duration::from_millis(50))),
+            in_flight.run([1; 32], render(Duration::ZERO)),
+        );
+
+        assert_eq!(first.unwrap(), b"%PDF-1.4");
+        assert_eq!(second.unwrap(), b"%PDF-1.4");
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+        assert_eq!(in_flight.coalesced.get(), 1);
+        assert!(in_flight.running().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failures_are_shared_and_different_keys_render_separately() {
+        let in_flight = InFlight::new().unwrap();
+        let failing = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Err(anyhow::anyhow!("wkhtmltopdf failed"))
+        };
+
+        let (first, second, other) = tokio::join!(
+            in_flight.run([1; 32], failing),
+            in_flight.run([1; 32], async { Ok(Vec::new()) }),
+            in_flight.run([2; 32], async { Ok(b"other".to_vec()) }),
+        );
+
+        assert!(first
+            .unwrap_err()
+            .to_string()
+            .contains("wkhtmltopdf failed"));
+        let shared = second.unwrap_err();
+        assert!(
+            shared.downcast_ref::<SharedFailure>().is_some(),
+            "{shared:#}"
+        );
+        assert_eq!(other.unwrap(), b"other");
+    }
+}
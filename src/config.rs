@@ -0,0 +1,227 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_PDF_BACKEND: &str = "wkhtmltopdf";
+const DEFAULT_WKHTMLTOPDF_PATH: &str = "wkhtmltopdf";
+const DEFAULT_HOST: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RETRIES: u32 = 1;
+const DEFAULT_QUEUE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_MARKDOWN_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 30;
+const DEFAULT_MAX_BATCH_DOCUMENTS: usize = 50;
+const DEFAULT_JOB_TTL_SECS: u64 = 3600;
+
+/// Service configuration read from environment variables at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address the HTTP server binds to
+    pub host: String,
+    /// Port the HTTP server listens on
+    pub port: u16,
+    /// wkhtmltopdf binary, as a path or a command looked up on `PATH`
+    pub wkhtmltopdf_path: PathBuf,
+    /// Maximum time a single wkhtmltopdf run may take before it is killed
+    pub conversion_timeout: Duration,
+    /// How often a wkhtmltopdf run that crashed without a complete PDF is retried
+    pub wkhtmltopdf_retries: u32,
+    /// Maximum number of conversions that may run at the same time
+    pub max_concurrent_conversions: usize,
+    /// Maximum time a request waits for a free conversion slot
+    pub queue_timeout: Duration,
+    /// Maximum size of a JSON request body
+    pub max_markdown_bytes: usize,
+    /// API key required on conversion routes; authentication is disabled when unset
+    pub api_key: Option<String>,
+    /// Name of the PDF rendering backend
+    pub pdf_backend: String,
+    /// Maximum time to wait for in-flight conversions after a shutdown signal
+    pub shutdown_grace_period: Duration,
+    /// Number of rendered PDFs kept in the in-memory cache; zero disables caching
+    pub pdf_cache_size: usize,
+    /// Hosts `source_url` may point at, with `*` allowing any; `source_url` is disabled when unset
+    pub source_url_allowed_hosts: Option<Vec<String>>,
+    /// Hosts `upload_url` may point at, with `*` allowing any; `upload_url` is disabled when unset
+    pub upload_url_allowed_hosts: Option<Vec<String>>,
+    /// Directory for the files passed to and written by wkhtmltopdf
+    pub temp_dir: PathBuf,
+    /// Conversion requests each client IP may make per minute; zero disables rate limiting
+    pub rate_limit_per_minute: u32,
+    /// Maximum number of documents in a single batch request
+    pub max_batch_documents: usize,
+    /// Origins allowed to call the API from a browser; any origin is allowed when unset
+    pub allowed_origins: Option<Vec<String>>,
+    /// How long the result of a background conversion is kept after it finishes
+    pub job_ttl: Duration,
+    /// Largest number of pages a generated PDF may have; unlimited when unset
+    pub max_pages: Option<usize>,
+    /// Keep the files passed to and written by wkhtmltopdf instead of deleting them
+    pub debug_keep_temp: bool,
+    /// Page template read from `HTML_TEMPLATE_PATH`; the built-in template is used when unset
+    pub html_template: Option<String>,
+    /// Stylesheet applied to every document, on top of the theme and below `custom_css`
+    pub default_css_path: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let config = Self {
+            host: env_or("HOST", DEFAULT_HOST.to_string())?,
+            port: env_or("PORT", DEFAULT_PORT)?,
+            wkhtmltopdf_path: std::env::var_os("WKHTMLTOPDF_PATH")
+                .filter(|path| !path.is_empty())
+                .map_or_else(|| PathBuf::from(DEFAULT_WKHTMLTOPDF_PATH), PathBuf::from),
+            conversion_timeout: Duration::from_secs(env_or(
+                "WKHTMLTOPDF_TIMEOUT_SECS",
+                DEFAULT_TIMEOUT_SECS,
+            )?),
+            wkhtmltopdf_retries: env_or("WKHTMLTOPDF_RETRIES", DEFAULT_RETRIES)?,
+            max_concurrent_conversions: env_or(
+                "MAX_CONCURRENT_CONVERSIONS",
+                std::thread::available_parallelism().map_or(4, usize::from),
+            )?,
+            queue_timeout: Duration::from_secs(env_or(
+                "CONVERSION_QUEUE_TIMEOUT_SECS",
+                DEFAULT_QUEUE_TIMEOUT_SECS,
+            )?),
+            max_markdown_bytes: env_or("MAX_MARKDOWN_BYTES", DEFAULT_MAX_MARKDOWN_BYTES)?,
+            api_key: std::env::var("API_KEY").ok().filter(|key| !key.is_empty()),
+            pdf_backend: env_or("PDF_BACKEND", DEFAULT_PDF_BACKEND.to_string())?,
+            shutdown_grace_period: Duration::from_secs(env_or(
+                "SHUTDOWN_GRACE_PERIOD_SECS",
+                DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS,
+            )?),
+            pdf_cache_size: env_or("PDF_CACHE_SIZE", 0)?,
+            source_url_allowed_hosts: std::env::var("SOURCE_URL_ALLOWED_HOSTS")
+                .ok()
+                .map(|hosts| parse_hosts(&hosts)),
+            upload_url_allowed_hosts: std::env::var("UPLOAD_URL_ALLOWED_HOSTS")
+                .ok()
+                .map(|hosts| parse_hosts(&hosts)),
+            temp_dir: std::env::var_os("TEMP_DIR")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir),
+            rate_limit_per_minute: env_or("RATE_LIMIT_PER_MINUTE", 0)?,
+            max_batch_documents: env_or("MAX_BATCH_DOCUMENTS", DEFAULT_MAX_BATCH_DOCUMENTS)?,
+            allowed_origins: std::env::var("ALLOWED_ORIGINS")
+                .ok()
+                .filter(|origins| !origins.trim().is_empty())
+                .map(|origins| parse_origins(&origins))
+                .transpose()?,
+            job_ttl: Duration::from_secs(env_or("JOB_TTL_SECS", DEFAULT_JOB_TTL_SECS)?),
+            max_pages: Some(env_or("MAX_PAGES", 0)?).filter(|max_pages| *max_pages > 0),
+            debug_keep_temp: env_or("DEBUG_KEEP_TEMP", false)?,
+            html_template: std::env::var_os("HTML_TEMPLATE_PATH")
+                .filter(|path| !path.is_empty())
+                .map(|path| crate::template::load(Path::new(&path)))
+                .transpose()?,
+            default_css_path: std::env::var_os("DEFAULT_CSS_PATH")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        };
+
+        if config.max_concurrent_conversions == 0 {
+            anyhow::bail!("MAX_CONCURRENT_CONVERSIONS must be at least 1");
+        }
+        // A wkhtmltopdf that cannot run is a deployment mistake, whether it was configured or
+        // looked up on the PATH
+        if config.pdf_backend == "wkhtmltopdf" && !is_executable(&config.wkhtmltopdf_path) {
+            if config.wkhtmltopdf_path == Path::new(DEFAULT_WKHTMLTOPDF_PATH) {
+                anyhow::bail!(
+                    "wkhtmltopdf was not found on the PATH. Install it, set WKHTMLTOPDF_PATH \
+                     to its location, or select PDF_BACKEND=builtin"
+                );
+            }
+            anyhow::bail!(
+                "WKHTMLTOPDF_PATH '{}' does not exist or is not an executable file",
+                config.wkhtmltopdf_path.display()
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+/// Splits a comma-separated list of hosts, ignoring blank entries
+fn parse_hosts(hosts: &str) -> Vec<String> {
+    hosts
+        .split(',')
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Creates the temporary directory if needed and checks that files can be written to it,
+/// so a bad `TEMP_DIR` fails at startup rather than in the middle of a conversion
+pub fn prepare_temp_dir(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| anyhow::anyhow!("Cannot create TEMP_DIR '{}': {}", dir.display(), e))?;
+    let probe = dir.join(format!(".write-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&probe, b"")
+        .map_err(|e| anyhow::anyhow!("TEMP_DIR '{}' is not writable: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Checks that a path, or a bare command name looked up on `PATH`, is an executable file
+fn is_executable(path: &Path) -> bool {
+    let is_executable_file = |path: &Path| {
+        std::fs::metadata(path).is_ok_and(|metadata| {
+            #[cfg(unix)]
+            let executable = {
+                use std::os::unix::fs::PermissionsExt;
+                metadata.permissions().mode() & 0o111 != 0
+            };
+            #[cfg(not(unix))]
+            let executable = true;
+            metadata.is_file() && executable
+        })
+    };
+
+    if path.parent() != Some(Path::new("")) {
+        return is_executable_file(path);
+    }
+    std::env::var_os("PATH").is_some_and(|dirs| {
+        std::env::split_paths(&dirs).any(|dir| is_executable_file(&dir.join(path)))
+    })
+}
+
+/// Parses a comma-separated list of origins such as `https://app.example.com`
+fn parse_origins(origins: &str) -> anyhow::Result<Vec<String>> {
+    origins
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/'))
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            // Browsers send the origin as scheme, host and optional port, without a path
+            let uri: actix_web::http::Uri = origin
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid ALLOWED_ORIGINS entry '{}': {}", origin, e))?;
+            if uri.scheme().is_none() || uri.host().is_none() || uri.path() != "/" {
+                anyhow::bail!(
+                    "Invalid ALLOWED_ORIGINS entry '{}': expected an origin such as https://app.example.com",
+                    origin
+                );
+            }
+            Ok(origin.to_string())
+        })
+        .collect()
+}
+
+/// Parses an environment variable, falling back to the default when it is unset
+fn env_or<T>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", name, value, e)),
+        Err(_) => Ok(default),
+    }
+}
@@ -0,0 +1,106 @@
+//! Delivers PDFs to a pre-signed `upload_url` instead of returning them in the response.
+//!
+//! The PDF is sent with a single `PUT`, which is how S3, Google Cloud Storage and Azure Blob
+//! Storage accept uploads to signed URLs, so the server needs no storage SDK or credentials.
+//! Only hosts listed in `UPLOAD_URL_ALLOWED_HOSTS` may be used, and redirects are not
+//! followed. Signed URLs carry credentials in their query string, so error messages and the
+//! reported location leave it out.
+
+use crate::source;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest part of the storage service's error response quoted back to the client
+const MAX_ERROR_BODY_CHARS: usize = 300;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("the HTTP client configuration is valid")
+});
+
+/// Returned when an `upload_url` is not permitted by the server configuration
+#[derive(Debug)]
+pub struct UploadNotAllowed(pub String);
+
+impl std::fmt::Display for UploadNotAllowed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UploadNotAllowed {}
+
+/// Returned when the storage service did not accept the PDF
+#[derive(Debug)]
+pub struct UploadFailed(anyhow::Error);
+
+impl std::fmt::Display for UploadFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to upload the PDF to upload_url: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for UploadFailed {}
+
+/// Checks that `url` may be uploaded to, before any work is spent on the conversion.
+///
+/// `allowed_hosts` lists the hosts that may be used, with `*` allowing any host; `None`
+/// means `upload_url` is disabled.
+pub fn check(url: &str, allowed_hosts: Option<&[String]>) -> Result<(), UploadNotAllowed> {
+    let allowed_hosts = allowed_hosts.ok_or_else(|| {
+        UploadNotAllowed(
+            "upload_url is disabled on this server; set UPLOAD_URL_ALLOWED_HOSTS to enable it"
+                .to_string(),
+        )
+    })?;
+
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| UploadNotAllowed(format!("Invalid upload_url: {}", e)))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(UploadNotAllowed(
+            "Invalid upload_url. Only http and https URLs are supported".to_string(),
+        ));
+    }
+    let host = parsed.host_str().unwrap_or_default();
+    if !source::is_allowed_host(allowed_hosts, host) {
+        return Err(UploadNotAllowed(format!(
+            "upload_url host '{}' is not in UPLOAD_URL_ALLOWED_HOSTS",
+            host
+        )));
+    }
+    Ok(())
+}
+
+/// Uploads the PDF to a URL accepted by [`check`] and returns where it was stored: the
+/// URL without its query string and fragment
+pub async fn put(url: &str, pdf_bytes: Vec<u8>) -> anyhow::Result<String> {
+    let mut location = reqwest::Url::parse(url).map_err(|e| UploadFailed(anyhow::anyhow!(e)))?;
+    let response = CLIENT
+        .put(location.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/pdf")
+        .body(pdf_bytes)
+        .send()
+        .await
+        // The error's own message would repeat the signed URL
+        .map_err(|e| UploadFailed(anyhow::anyhow!(e.without_url())))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        let body: String = body.trim().chars().take(MAX_ERROR_BODY_CHARS).collect();
+        return Err(UploadFailed(if body.is_empty() {
+            anyhow::anyhow!("the storage service answered {}", status)
+        } else {
+            anyhow::anyhow!("the storage service answered {}: {}", status, body)
+        })
+        .into());
+    }
+
+    location.set_query(None);
+    location.set_fragment(None);
+    Ok(location.to_string())
+}
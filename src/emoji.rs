@@ -0,0 +1,425 @@
+//! Emoji shortcodes such as `:rocket:`, expanded when `render_emoji` is set.
+//!
+//! Shortcodes are looked up in a built-in table of the common GitHub names. Only text nodes
+//! are searched, so shortcodes in code spans and blocks stay as written, and names missing
+//! from the table are left untouched.
+
+use comrak::nodes::{AstNode, NodeValue};
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+static SHORTCODE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r":([a-z0-9_+-]+):").expect("valid regex"));
+
+/// Shortcode names and their emoji, sorted by name for binary search
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "\u{1F44D}"),
+    ("-1", "\u{1F44E}"),
+    ("100", "\u{1F4AF}"),
+    ("1st_place_medal", "\u{1F947}"),
+    ("airplane", "\u{2708}\u{FE0F}"),
+    ("alarm_clock", "\u{23F0}"),
+    ("alembic", "\u{2697}\u{FE0F}"),
+    ("alien", "\u{1F47D}"),
+    ("anchor", "\u{2693}"),
+    ("angry", "\u{1F620}"),
+    ("apple", "\u{1F34E}"),
+    ("arrow_down", "\u{2B07}\u{FE0F}"),
+    ("arrow_left", "\u{2B05}\u{FE0F}"),
+    ("arrow_right", "\u{27A1}\u{FE0F}"),
+    ("arrow_up", "\u{2B06}\u{FE0F}"),
+    ("arrows_counterclockwise", "\u{1F504}"),
+    ("art", "\u{1F3A8}"),
+    ("astonished", "\u{1F632}"),
+    ("avocado", "\u{1F951}"),
+    ("baby", "\u{1F476}"),
+    ("balloon", "\u{1F388}"),
+    ("ballot_box_with_check", "\u{2611}\u{FE0F}"),
+    ("banana", "\u{1F34C}"),
+    ("bangbang", "\u{203C}\u{FE0F}"),
+    ("bar_chart", "\u{1F4CA}"),
+    ("basketball", "\u{1F3C0}"),
+    ("battery", "\u{1F50B}"),
+    ("bear", "\u{1F43B}"),
+    ("bee", "\u{1F41D}"),
+    ("beer", "\u{1F37A}"),
+    ("beers", "\u{1F37B}"),
+    ("bell", "\u{1F514}"),
+    ("bike", "\u{1F6B2}"),
+    ("bird", "\u{1F426}"),
+    ("birthday", "\u{1F382}"),
+    ("black_circle", "\u{26AB}"),
+    ("black_heart", "\u{1F5A4}"),
+    ("blue_heart", "\u{1F499}"),
+    ("blush", "\u{1F60A}"),
+    ("bomb", "\u{1F4A3}"),
+    ("book", "\u{1F4D6}"),
+    ("bookmark", "\u{1F516}"),
+    ("bookmark_tabs", "\u{1F4D1}"),
+    ("books", "\u{1F4DA}"),
+    ("boom", "\u{1F4A5}"),
+    ("brain", "\u{1F9E0}"),
+    ("bread", "\u{1F35E}"),
+    ("briefcase", "\u{1F4BC}"),
+    ("broken_heart", "\u{1F494}"),
+    ("bug", "\u{1F41B}"),
+    ("bulb", "\u{1F4A1}"),
+    ("bus", "\u{1F68C}"),
+    ("bust_in_silhouette", "\u{1F464}"),
+    ("busts_in_silhouette", "\u{1F465}"),
+    ("butterfly", "\u{1F98B}"),
+    ("cactus", "\u{1F335}"),
+    ("cake", "\u{1F370}"),
+    ("calendar", "\u{1F4C6}"),
+    ("camera", "\u{1F4F7}"),
+    ("camping", "\u{1F3D5}\u{FE0F}"),
+    ("car", "\u{1F697}"),
+    ("card_index", "\u{1F4C7}"),
+    ("carrot", "\u{1F955}"),
+    ("cat", "\u{1F431}"),
+    ("champagne", "\u{1F37E}"),
+    ("chart_with_downwards_trend", "\u{1F4C9}"),
+    ("chart_with_upwards_trend", "\u{1F4C8}"),
+    ("checkered_flag", "\u{1F3C1}"),
+    ("cheese", "\u{1F9C0}"),
+    ("cherries", "\u{1F352}"),
+    ("cherry_blossom", "\u{1F338}"),
+    ("chicken", "\u{1F414}"),
+    ("christmas_tree", "\u{1F384}"),
+    ("clap", "\u{1F44F}"),
+    ("clipboard", "\u{1F4CB}"),
+    ("cloud", "\u{2601}\u{FE0F}"),
+    ("coffee", "\u{2615}"),
+    ("cold_sweat", "\u{1F630}"),
+    ("collision", "\u{1F4A5}"),
+    ("computer", "\u{1F4BB}"),
+    ("confetti_ball", "\u{1F38A}"),
+    ("confused", "\u{1F615}"),
+    ("construction", "\u{1F6A7}"),
+    ("cookie", "\u{1F36A}"),
+    ("cool", "\u{1F192}"),
+    ("copyright", "\u{A9}\u{FE0F}"),
+    ("corn", "\u{1F33D}"),
+    ("cow", "\u{1F42E}"),
+    ("crab", "\u{1F980}"),
+    ("credit_card", "\u{1F4B3}"),
+    ("crescent_moon", "\u{1F319}"),
+    ("crocodile", "\u{1F40A}"),
+    ("crossed_fingers", "\u{1F91E}"),
+    ("crown", "\u{1F451}"),
+    ("cry", "\u{1F622}"),
+    ("dart", "\u{1F3AF}"),
+    ("dash", "\u{1F4A8}"),
+    ("date", "\u{1F4C5}"),
+    ("deciduous_tree", "\u{1F333}"),
+    ("dizzy", "\u{1F4AB}"),
+    ("dna", "\u{1F9EC}"),
+    ("dog", "\u{1F436}"),
+    ("dollar", "\u{1F4B5}"),
+    ("dolphin", "\u{1F42C}"),
+    ("doughnut", "\u{1F369}"),
+    ("dragon", "\u{1F409}"),
+    ("droplet", "\u{1F4A7}"),
+    ("earth_africa", "\u{1F30D}"),
+    ("earth_americas", "\u{1F30E}"),
+    ("earth_asia", "\u{1F30F}"),
+    ("eight", "8\u{FE0F}\u{20E3}"),
+    ("electric_plug", "\u{1F50C}"),
+    ("email", "\u{2709}\u{FE0F}"),
+    ("envelope", "\u{2709}\u{FE0F}"),
+    ("euro", "\u{1F4B6}"),
+    ("evergreen_tree", "\u{1F332}"),
+    ("exclamation", "\u{2757}"),
+    ("exploding_head", "\u{1F92F}"),
+    ("expressionless", "\u{1F611}"),
+    ("eyeglasses", "\u{1F453}"),
+    ("eyes", "\u{1F440}"),
+    ("factory", "\u{1F3ED}"),
+    ("fallen_leaf", "\u{1F342}"),
+    ("family", "\u{1F46A}"),
+    ("fearful", "\u{1F628}"),
+    ("file_folder", "\u{1F4C1}"),
+    ("fire", "\u{1F525}"),
+    ("fish", "\u{1F41F}"),
+    ("fist", "\u{270A}"),
+    ("five", "5\u{FE0F}\u{20E3}"),
+    ("flashlight", "\u{1F526}"),
+    ("flushed", "\u{1F633}"),
+    ("football", "\u{1F3C8}"),
+    ("four", "4\u{FE0F}\u{20E3}"),
+    ("four_leaf_clover", "\u{1F340}"),
+    ("fox_face", "\u{1F98A}"),
+    ("free", "\u{1F193}"),
+    ("fries", "\u{1F35F}"),
+    ("frog", "\u{1F438}"),
+    ("frowning_face", "\u{2639}"),
+    ("game_die", "\u{1F3B2}"),
+    ("gear", "\u{2699}\u{FE0F}"),
+    ("gem", "\u{1F48E}"),
+    ("ghost", "\u{1F47B}"),
+    ("gift", "\u{1F381}"),
+    ("globe_with_meridians", "\u{1F310}"),
+    ("grapes", "\u{1F347}"),
+    ("green_apple", "\u{1F34F}"),
+    ("green_circle", "\u{1F7E2}"),
+    ("green_heart", "\u{1F49A}"),
+    ("grey_exclamation", "\u{2755}"),
+    ("grey_question", "\u{2754}"),
+    ("grin", "\u{1F601}"),
+    ("grinning", "\u{1F600}"),
+    ("guitar", "\u{1F3B8}"),
+    ("hamburger", "\u{1F354}"),
+    ("hammer", "\u{1F528}"),
+    ("hammer_and_wrench", "\u{1F6E0}\u{FE0F}"),
+    ("handshake", "\u{1F91D}"),
+    ("hash", "#\u{FE0F}\u{20E3}"),
+    ("headphones", "\u{1F3A7}"),
+    ("heart", "\u{2764}\u{FE0F}"),
+    ("heart_eyes", "\u{1F60D}"),
+    ("heavy_check_mark", "\u{2714}\u{FE0F}"),
+    ("heavy_division_sign", "\u{2797}"),
+    ("heavy_exclamation_mark", "\u{2757}"),
+    ("heavy_minus_sign", "\u{2796}"),
+    ("heavy_multiplication_x", "\u{2716}\u{FE0F}"),
+    ("heavy_plus_sign", "\u{2795}"),
+    ("hole", "\u{1F573}\u{FE0F}"),
+    ("honeybee", "\u{1F41D}"),
+    ("hospital", "\u{1F3E5}"),
+    ("hourglass", "\u{231B}\u{FE0F}"),
+    ("hourglass_flowing_sand", "\u{23F3}"),
+    ("house", "\u{1F3E0}"),
+    ("ice_cream", "\u{1F368}"),
+    ("inbox_tray", "\u{1F4E5}"),
+    ("incoming_envelope", "\u{1F4E8}"),
+    ("information_source", "\u{2139}\u{FE0F}"),
+    ("innocent", "\u{1F607}"),
+    ("interrobang", "\u{2049}\u{FE0F}"),
+    ("iphone", "\u{1F4F1}"),
+    ("jack_o_lantern", "\u{1F383}"),
+    ("jigsaw", "\u{1F9E9}"),
+    ("joy", "\u{1F602}"),
+    ("key", "\u{1F511}"),
+    ("keyboard", "\u{2328}\u{FE0F}"),
+    ("keycap_ten", "\u{1F51F}"),
+    ("kissing_heart", "\u{1F618}"),
+    ("koala", "\u{1F428}"),
+    ("label", "\u{1F3F7}\u{FE0F}"),
+    ("large_blue_circle", "\u{1F535}"),
+    ("laughing", "\u{1F606}"),
+    ("lemon", "\u{1F34B}"),
+    ("link", "\u{1F517}"),
+    ("lion", "\u{1F981}"),
+    ("lock", "\u{1F512}"),
+    ("loudspeaker", "\u{1F4E2}"),
+    ("mag", "\u{1F50D}"),
+    ("mag_right", "\u{1F50E}"),
+    ("mailbox", "\u{1F4EB}"),
+    ("man", "\u{1F468}"),
+    ("mask", "\u{1F637}"),
+    ("medal_sports", "\u{1F3C5}"),
+    ("mega", "\u{1F4E3}"),
+    ("memo", "\u{1F4DD}"),
+    ("microphone", "\u{1F3A4}"),
+    ("microscope", "\u{1F52C}"),
+    ("moneybag", "\u{1F4B0}"),
+    ("monkey_face", "\u{1F435}"),
+    ("mortar_board", "\u{1F393}"),
+    ("mountain", "\u{26F0}\u{FE0F}"),
+    ("mouse", "\u{1F42D}"),
+    ("movie_camera", "\u{1F3A5}"),
+    ("muscle", "\u{1F4AA}"),
+    ("musical_note", "\u{1F3B5}"),
+    ("necktie", "\u{1F454}"),
+    ("nerd_face", "\u{1F913}"),
+    ("neutral_face", "\u{1F610}"),
+    ("new", "\u{1F195}"),
+    ("nine", "9\u{FE0F}\u{20E3}"),
+    ("no_bell", "\u{1F515}"),
+    ("no_entry", "\u{26D4}"),
+    ("no_entry_sign", "\u{1F6AB}"),
+    ("notebook", "\u{1F4D3}"),
+    ("notes", "\u{1F3B6}"),
+    ("nut_and_bolt", "\u{1F529}"),
+    ("ocean", "\u{1F30A}"),
+    ("octopus", "\u{1F419}"),
+    ("office", "\u{1F3E2}"),
+    ("ok", "\u{1F197}"),
+    ("ok_hand", "\u{1F44C}"),
+    ("one", "1\u{FE0F}\u{20E3}"),
+    ("open_book", "\u{1F4D6}"),
+    ("open_file_folder", "\u{1F4C2}"),
+    ("orange_heart", "\u{1F9E1}"),
+    ("outbox_tray", "\u{1F4E4}"),
+    ("owl", "\u{1F989}"),
+    ("package", "\u{1F4E6}"),
+    ("page_facing_up", "\u{1F4C4}"),
+    ("page_with_curl", "\u{1F4C3}"),
+    ("panda_face", "\u{1F43C}"),
+    ("paperclip", "\u{1F4CE}"),
+    ("partying_face", "\u{1F973}"),
+    ("peach", "\u{1F351}"),
+    ("pencil", "\u{1F4DD}"),
+    ("pencil2", "\u{270F}\u{FE0F}"),
+    ("penguin", "\u{1F427}"),
+    ("pensive", "\u{1F614}"),
+    ("phone", "\u{260E}\u{FE0F}"),
+    ("pig", "\u{1F437}"),
+    ("pill", "\u{1F48A}"),
+    ("pizza", "\u{1F355}"),
+    ("point_down", "\u{1F447}"),
+    ("point_left", "\u{1F448}"),
+    ("point_right", "\u{1F449}"),
+    ("point_up", "\u{261D}"),
+    ("poop", "\u{1F4A9}"),
+    ("popcorn", "\u{1F37F}"),
+    ("pray", "\u{1F64F}"),
+    ("punch", "\u{1F44A}"),
+    ("purple_heart", "\u{1F49C}"),
+    ("pushpin", "\u{1F4CC}"),
+    ("question", "\u{2753}"),
+    ("rabbit", "\u{1F430}"),
+    ("rage", "\u{1F621}"),
+    ("rainbow", "\u{1F308}"),
+    ("raised_hand", "\u{270B}"),
+    ("raised_hands", "\u{1F64C}"),
+    ("recycle", "\u{267B}\u{FE0F}"),
+    ("red_car", "\u{1F697}"),
+    ("red_circle", "\u{1F534}"),
+    ("registered", "\u{AE}\u{FE0F}"),
+    ("relieved", "\u{1F60C}"),
+    ("repeat", "\u{1F500}"),
+    ("robot", "\u{1F916}"),
+    ("rocket", "\u{1F680}"),
+    ("rofl", "\u{1F923}"),
+    ("roll_eyes", "\u{1F644}"),
+    ("rose", "\u{1F339}"),
+    ("rotating_light", "\u{1F6A8}"),
+    ("round_pushpin", "\u{1F4CD}"),
+    ("santa", "\u{1F385}"),
+    ("satellite", "\u{1F4E1}"),
+    ("school", "\u{1F3EB}"),
+    ("school_satchel", "\u{1F392}"),
+    ("scissors", "\u{2702}\u{FE0F}"),
+    ("scream", "\u{1F631}"),
+    ("see_no_evil", "\u{1F648}"),
+    ("seedling", "\u{1F331}"),
+    ("seven", "7\u{FE0F}\u{20E3}"),
+    ("shield", "\u{1F6E1}\u{FE0F}"),
+    ("ship", "\u{1F6A2}"),
+    ("six", "6\u{FE0F}\u{20E3}"),
+    ("skull", "\u{1F480}"),
+    ("sleeping", "\u{1F634}"),
+    ("sleepy", "\u{1F62A}"),
+    ("slightly_frowning_face", "\u{1F641}"),
+    ("slightly_smiling_face", "\u{1F642}"),
+    ("smile", "\u{1F604}"),
+    ("smiley", "\u{1F603}"),
+    ("smirk", "\u{1F60F}"),
+    ("snail", "\u{1F40C}"),
+    ("snake", "\u{1F40D}"),
+    ("snowflake", "\u{2744}\u{FE0F}"),
+    ("sob", "\u{1F62D}"),
+    ("soccer", "\u{26BD}"),
+    ("soon", "\u{1F51C}"),
+    ("sos", "\u{1F198}"),
+    ("sparkles", "\u{2728}"),
+    ("sparkling_heart", "\u{1F496}"),
+    ("speech_balloon", "\u{1F4AC}"),
+    ("speech_left", "\u{1F5E8}\u{FE0F}"),
+    ("spider", "\u{1F577}"),
+    ("star", "\u{2B50}"),
+    ("star2", "\u{1F31F}"),
+    ("stop_sign", "\u{1F6D1}"),
+    ("stopwatch", "\u{23F1}\u{FE0F}"),
+    ("straight_ruler", "\u{1F4CF}"),
+    ("strawberry", "\u{1F353}"),
+    ("stuck_out_tongue", "\u{1F61B}"),
+    ("sunflower", "\u{1F33B}"),
+    ("sunglasses", "\u{1F60E}"),
+    ("sunny", "\u{2600}\u{FE0F}"),
+    ("sweat_drops", "\u{1F4A6}"),
+    ("sweat_smile", "\u{1F605}"),
+    ("syringe", "\u{1F489}"),
+    ("taco", "\u{1F32E}"),
+    ("tada", "\u{1F389}"),
+    ("taxi", "\u{1F695}"),
+    ("tea", "\u{1F375}"),
+    ("telephone_receiver", "\u{1F4DE}"),
+    ("telescope", "\u{1F52D}"),
+    ("tennis", "\u{1F3BE}"),
+    ("test_tube", "\u{1F9EA}"),
+    ("thinking", "\u{1F914}"),
+    ("thought_balloon", "\u{1F4AD}"),
+    ("three", "3\u{FE0F}\u{20E3}"),
+    ("thumbsdown", "\u{1F44E}"),
+    ("thumbsup", "\u{1F44D}"),
+    ("ticket", "\u{1F3AB}"),
+    ("tiger", "\u{1F42F}"),
+    ("timer_clock", "\u{23F2}\u{FE0F}"),
+    ("tm", "\u{2122}\u{FE0F}"),
+    ("tomato", "\u{1F345}"),
+    ("top", "\u{1F51D}"),
+    ("train", "\u{1F68B}"),
+    ("triangular_flag_on_post", "\u{1F6A9}"),
+    ("triangular_ruler", "\u{1F4D0}"),
+    ("triumph", "\u{1F624}"),
+    ("trophy", "\u{1F3C6}"),
+    ("truck", "\u{1F69A}"),
+    ("tulip", "\u{1F337}"),
+    ("turtle", "\u{1F422}"),
+    ("tv", "\u{1F4FA}"),
+    ("two", "2\u{FE0F}\u{20E3}"),
+    ("umbrella", "\u{2614}"),
+    ("unamused", "\u{1F612}"),
+    ("unicorn", "\u{1F984}"),
+    ("unlock", "\u{1F513}"),
+    ("up", "\u{1F199}"),
+    ("upside_down_face", "\u{1F643}"),
+    ("v", "\u{270C}"),
+    ("vertical_traffic_light", "\u{1F6A6}"),
+    ("video_game", "\u{1F3AE}"),
+    ("warning", "\u{26A0}\u{FE0F}"),
+    ("watch", "\u{231A}\u{FE0F}"),
+    ("watermelon", "\u{1F349}"),
+    ("wave", "\u{1F44B}"),
+    ("whale", "\u{1F433}"),
+    ("white_check_mark", "\u{2705}"),
+    ("white_circle", "\u{26AA}"),
+    ("white_flag", "\u{1F3F3}\u{FE0F}"),
+    ("wine_glass", "\u{1F377}"),
+    ("wink", "\u{1F609}"),
+    ("woman", "\u{1F469}"),
+    ("world_map", "\u{1F5FA}\u{FE0F}"),
+    ("worried", "\u{1F61F}"),
+    ("wrench", "\u{1F527}"),
+    ("x", "\u{274C}"),
+    ("yellow_circle", "\u{1F7E1}"),
+    ("yellow_heart", "\u{1F49B}"),
+    ("yum", "\u{1F60B}"),
+    ("zap", "\u{26A1}"),
+    ("zero", "0\u{FE0F}\u{20E3}"),
+    ("zzz", "\u{1F4A4}"),
+];
+
+/// Returns the emoji for a shortcode name, without the surrounding colons
+fn lookup(name: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by_key(&name, |(code, _)| code)
+        .ok()
+        .map(|index| SHORTCODES[index].1)
+}
+
+/// Replaces every known shortcode in the document's text with its emoji
+pub fn expand<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        if let NodeValue::Text(text) = &mut node.data.borrow_mut().value {
+            if SHORTCODE_PATTERN.is_match(text) {
+                *text = SHORTCODE_PATTERN
+                    .replace_all(text, |captures: &Captures| {
+                        lookup(&captures[1]).unwrap_or(&captures[0]).to_string()
+                    })
+                    .into_owned();
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Predicts the size of a conversion from the parsed markdown, without rendering it.
+//!
+//! Page counts come from a simple line model: paragraph text wraps at the average number of
+//! characters that fit on a line, code blocks and table rows take a line each, and headings
+//! and images a fixed number of lines. Real output depends on fonts, CSS and image sizes, so
+//! the prediction is returned as a range.
+
+use crate::pagebreak;
+use comrak::nodes::{AstNode, NodeCode, NodeValue};
+use comrak::{parse_document, Arena, ComrakOptions};
+use serde::Serialize;
+
+/// Average character width as a fraction of the font size, for proportional fonts
+const CHARACTER_WIDTH: f32 = 0.5;
+/// Lines taken by a heading, including the space around it
+const HEADING_LINES: f32 = 2.5;
+/// Lines taken by an image, which is usually far wider than it is tall
+const IMAGE_LINES: f32 = 12.0;
+/// Lines of blank space after paragraphs, lists, tables and code blocks
+const BLOCK_SPACING_LINES: f32 = 1.0;
+/// Factors applied to the predicted page count for the lower and upper end of the range
+const PAGE_RANGE: (f32, f32) = (0.75, 1.5);
+/// Predicted pages above which a conversion counts as `medium` and `high`
+const COMPLEXITY_PAGES: (usize, usize) = (10, 100);
+/// Images above which a conversion counts as `medium` and `high`
+const COMPLEXITY_IMAGES: (usize, usize) = (10, 50);
+
+/// The area text is laid out in
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// Width available to text, in points
+    pub width: f32,
+    /// Height available to text, in points
+    pub height: f32,
+    pub font_size: f32,
+    /// Line height as a multiple of the font size
+    pub line_height: f32,
+}
+
+impl Layout {
+    fn characters_per_line(&self) -> f32 {
+        (self.width / (self.font_size * CHARACTER_WIDTH)).max(1.0)
+    }
+
+    fn lines_per_page(&self) -> f32 {
+        (self.height / (self.font_size * self.line_height)).max(1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Complexity {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageRange {
+    pub min: usize,
+    pub max: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Estimate {
+    /// Characters of text, including code
+    pub characters: usize,
+    pub words: usize,
+    pub headings: usize,
+    pub images: usize,
+    pub tables: usize,
+    pub code_blocks: usize,
+    /// Forced page breaks, including those between sections
+    pub page_breaks: usize,
+    pub pages: PageRange,
+    pub complexity: Complexity,
+}
+
+/// Running totals while walking the documents
+#[derive(Default)]
+struct Counts {
+    characters: usize,
+    words: usize,
+    headings: usize,
+    images: usize,
+    tables: usize,
+    code_blocks: usize,
+    page_breaks: usize,
+}
+
+/// Parses each source with the request's options and predicts the size of the PDF.
+/// Every source starts on a new page, as sections do.
+pub fn estimate(sources: &[&str], options: &ComrakOptions, layout: Layout) -> Estimate {
+    let mut counts = Counts::default();
+    let mut pages = 0.0;
+    // Each run of content between page breaks takes at least one page of its own
+    let mut runs = 0;
+
+    for (index, source) in sources.iter().enumerate() {
+        if index > 0 {
+            counts.page_breaks += 1;
+        }
+        let arena = Arena::new();
+        let root = parse_document(&arena, source, options);
+        for lines in count(root, &mut counts, layout) {
+            pages += lines / layout.lines_per_page();
+            runs += 1;
+        }
+    }
+
+    let min = ((pages * PAGE_RANGE.0).floor() as usize).max(runs);
+    let max = ((pages * PAGE_RANGE.1).ceil() as usize).max(min);
+    let complexity = if max > COMPLEXITY_PAGES.1 || counts.images > COMPLEXITY_IMAGES.1 {
+        Complexity::High
+    } else if max > COMPLEXITY_PAGES.0 || counts.images > COMPLEXITY_IMAGES.0 {
+        Complexity::Medium
+    } else {
+        Complexity::Low
+    };
+
+    Estimate {
+        characters: counts.characters,
+        words: counts.words,
+        headings: counts.headings,
+        images: counts.images,
+        tables: counts.tables,
+        code_blocks: counts.code_blocks,
+        page_breaks: counts.page_breaks,
+        pages: PageRange { min, max },
+        complexity,
+    }
+}
+
+/// Adds the document's elements to `counts` and returns the lines of each run of content
+/// between forced page breaks
+fn count<'a>(root: &'a AstNode<'a>, counts: &mut Counts, layout: Layout) -> Vec<f32> {
+    let mut runs = vec![0.0];
+    for block in root.children() {
+        if pagebreak::is_directive(block) {
+            counts.page_breaks += 1;
+            runs.push(0.0);
+            continue;
+        }
+        let lines = runs.last_mut().expect("runs is never empty");
+        for node in block.descendants() {
+            *lines += node_lines(node, counts, layout);
+        }
+    }
+    runs
+}
+
+/// Counts a single node and returns the lines it adds on its own, not counting its children
+fn node_lines<'a>(node: &'a AstNode<'a>, counts: &mut Counts, layout: Layout) -> f32 {
+    let mut add_text = |text: &str| {
+        counts.characters += text.chars().count();
+        counts.words += text.split_whitespace().count();
+    };
+    match &node.data.borrow().value {
+        NodeValue::Text(text) | NodeValue::Code(NodeCode { literal: text, .. }) => {
+            add_text(text);
+            0.0
+        }
+        NodeValue::Heading(_) => {
+            counts.headings += 1;
+            HEADING_LINES
+        }
+        NodeValue::Paragraph => {
+            let mut characters = 0;
+            for child in node.descendants() {
+                match &child.data.borrow().value {
+                    NodeValue::Text(text) => characters += text.chars().count(),
+                    NodeValue::Code(code) => characters += code.literal.chars().count(),
+                    _ => {}
+                }
+            }
+            (characters as f32 / layout.characters_per_line()).ceil() + BLOCK_SPACING_LINES
+        }
+        NodeValue::Image(_) => {
+            counts.images += 1;
+            IMAGE_LINES
+        }
+        NodeValue::Table(_) => {
+            counts.tables += 1;
+            BLOCK_SPACING_LINES
+        }
+        // Cells rarely wrap, so a row is counted as one line
+        NodeValue::TableRow(_) => 1.0,
+        NodeValue::CodeBlock(block) => {
+            counts.code_blocks += 1;
+            add_text(&block.literal);
+            block.literal.lines().count() as f32 + BLOCK_SPACING_LINES
+        }
+        _ => 0.0,
+    }
+}
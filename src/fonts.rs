@@ -0,0 +1,145 @@
+//! Builds `@font-face` rules for custom fonts supplied with a request.
+//!
+//! Remote fonts are downloaded by the service and embedded like base64 fonts, so the
+//! renderer never fetches them and all font data counts towards one size budget.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use tracing::warn;
+
+/// Maximum combined size of all fonts embedded into one document
+pub const MAX_FONT_BYTES: usize = 5 * 1024 * 1024;
+/// Maximum number of fonts per request
+const MAX_FONTS: usize = 10;
+
+/// Restricts family names to characters that are safe inside a quoted CSS string
+pub static FAMILY_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9 _-]{1,64}$").unwrap());
+
+/// A font made available to the document under the given family name
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Font {
+    pub family: String,
+    /// http(s) URL the font is downloaded from
+    pub url: Option<String>,
+    /// Base64-encoded font file
+    pub data: Option<String>,
+}
+
+/// Validates the font list, returning a client-facing message on failure
+pub fn validate(fonts: &[Font]) -> Result<(), String> {
+    if fonts.len() > MAX_FONTS {
+        return Err(format!("fonts may contain at most {} entries", MAX_FONTS));
+    }
+
+    let mut embedded_bytes = 0;
+    for font in fonts {
+        if !FAMILY_PATTERN.is_match(&font.family) {
+            return Err(format!(
+                "Invalid font family '{}'. Use up to 64 letters, digits, spaces, '-' or '_'",
+                font.family
+            ));
+        }
+
+        match (&font.url, &font.data) {
+            (Some(url), None) => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(format!(
+                        "Invalid url for font '{}'. Only http and https URLs are supported",
+                        font.family
+                    ));
+                }
+            }
+            (None, Some(data)) => {
+                let bytes = STANDARD
+                    .decode(data)
+                    .map_err(|_| format!("data for font '{}' is not valid base64", font.family))?;
+                if font_format(&bytes).is_none() {
+                    return Err(format!(
+                        "data for font '{}' is not a TTF, OTF, WOFF or WOFF2 font",
+                        font.family
+                    ));
+                }
+                embedded_bytes += bytes.len();
+            }
+            _ => {
+                return Err(format!(
+                    "Font '{}' must have exactly one of url or data",
+                    font.family
+                ))
+            }
+        }
+    }
+
+    if embedded_bytes > MAX_FONT_BYTES {
+        return Err(format!(
+            "fonts total {} bytes, which exceeds the {} byte limit",
+            embedded_bytes, MAX_FONT_BYTES
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the `@font-face` rules for validated fonts, downloading remote ones.
+///
+/// Remote fonts that fail to download or do not fit the remaining budget are skipped and
+/// logged, so the document falls back to the next font in its stack.
+pub async fn font_face_css(fonts: &[Font]) -> String {
+    let mut budget = MAX_FONT_BYTES;
+    let mut embedded = Vec::with_capacity(fonts.len());
+
+    // Embedded fonts were size checked during validation, so they claim the budget first
+    for font in fonts {
+        if let Some(data) = &font.data {
+            let bytes = STANDARD.decode(data).unwrap_or_default();
+            budget = budget.saturating_sub(bytes.len());
+            embedded.push((font, bytes));
+        }
+    }
+
+    for font in fonts {
+        let Some(url) = &font.url else {
+            continue;
+        };
+        match crate::images::fetch_remote(url, budget).await {
+            Ok((_, bytes)) if font_format(&bytes).is_some() => {
+                budget -= bytes.len();
+                embedded.push((font, bytes));
+            }
+            Ok(_) => warn!(url = %url, "Downloaded font is not a recognised font format"),
+            Err(e) => warn!(url = %url, error = format!("{:#}", e), "Failed to download font"),
+        }
+    }
+
+    embedded
+        .into_iter()
+        .filter_map(|(font, bytes)| {
+            let (mime_type, format) = font_format(&bytes)?;
+            Some(format!(
+                r#"@font-face {{ font-family: "{}"; src: url(data:{};base64,{}) format("{}"); }}"#,
+                font.family,
+                mime_type,
+                STANDARD.encode(&bytes),
+                format
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n        ")
+}
+
+/// Identifies a font file from its leading bytes, returning its MIME type and CSS format
+fn font_format(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    if bytes.starts_with(b"wOF2") {
+        Some(("font/woff2", "woff2"))
+    } else if bytes.starts_with(b"wOFF") {
+        Some(("font/woff", "woff"))
+    } else if bytes.starts_with(b"OTTO") {
+        Some(("font/otf", "opentype"))
+    } else if bytes.starts_with(b"\x00\x01\x00\x00") || bytes.starts_with(b"true") {
+        Some(("font/ttf", "truetype"))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,69 @@
+//! Keeps footnote links of separate sections apart.
+//!
+//! comrak numbers footnotes from 1 in every document it renders and links references and
+//! definitions through the ids `fnref-N` and `fn-N`, which wkhtmltopdf turns into named
+//! destinations. Sections are rendered one by one, so without renaming a reference in the
+//! second section would jump to the first section's footnote of the same number.
+
+use regex::bytes::Regex;
+use std::sync::LazyLock;
+
+static FOOTNOTE_ID_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r##"(id="|href="#)(fn|fnref)-(\d+)""##).expect("valid regex"));
+
+/// Renames the footnote ids and links of a rendered section to `fn-S-N` and `fnref-S-N`,
+/// where S is the section's position starting at 1
+pub fn scope(html: &[u8], section: usize) -> Vec<u8> {
+    FOOTNOTE_ID_PATTERN
+        .replace_all(html, format!("${{1}}${{2}}-{section}-${{3}}\"").as_bytes())
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MarkdownRequest;
+
+    #[test]
+    fn ids_and_links_get_the_section_number() {
+        let html = br##"<sup class="footnote-ref"><a href="#fn-1" id="fnref-1">1</a></sup>
+<li id="fn-1"><p>Note <a href="#fnref-1" class="footnote-backref">&#8617;</a></p></li>
+<a href="#fn-10">not a footnote link: id="fn-x"</a>"##;
+
+        let scoped = String::from_utf8(scope(html, 2)).unwrap();
+
+        assert!(
+            scoped.contains(r##"<a href="#fn-2-1" id="fnref-2-1">1</a>"##),
+            "{scoped}"
+        );
+        assert!(
+            scoped.contains(r##"<li id="fn-2-1"><p>Note <a href="#fnref-2-1""##),
+            "{scoped}"
+        );
+        assert!(scoped.contains(r##"href="#fn-2-10""##), "{scoped}");
+        assert!(scoped.contains(r#"id="fn-x""#), "{scoped}");
+    }
+
+    #[test]
+    fn sections_with_the_same_label_link_to_their_own_footnote() {
+        let section = "Claim[^source].\n\n[^source]: Where it came from.\n";
+        let request = MarkdownRequest {
+            sections: Some(vec![section.to_string(), section.to_string()]),
+            extensions: Some(vec!["footnotes".to_string()]),
+            ..Default::default()
+        };
+        let html = crate::render_markdown(&request, request.theme());
+
+        let ids: Vec<&str> = html
+            .match_indices(r#"id=""#)
+            .map(|(start, _)| {
+                let id = &html[start + 4..];
+                &id[..id.find('"').unwrap()]
+            })
+            .filter(|id| id.starts_with("fn"))
+            .collect();
+        assert_eq!(ids, ["fnref-1", "fn-1", "fnref-2-1", "fn-2-1"], "{html}");
+        assert!(html.contains(r##"href="#fn-1""##), "{html}");
+        assert!(html.contains(r##"href="#fn-2-1""##), "{html}");
+    }
+}
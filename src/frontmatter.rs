@@ -0,0 +1,125 @@
+//! Reads Jekyll-style YAML front matter at the top of a markdown document.
+//!
+//! Only the keys that map to PDF metadata are understood: `title`, `author`, `date` and
+//! `keywords`, with plain or quoted scalars and, for `author` and `keywords`, lists. Other
+//! keys and YAML constructs are ignored. comrak strips the block from the rendered body.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+/// Line that opens and closes the front matter block
+pub const DELIMITER: &str = "---";
+
+/// The metadata found in a document's front matter
+#[derive(Debug, Default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Date in PDF format, e.g. `D:20240115093000Z`
+    pub date: Option<String>,
+    pub keywords: Option<String>,
+}
+
+/// Parses the front matter at the start of `markdown`, if there is any
+pub fn parse(markdown: &str) -> FrontMatter {
+    let mut front_matter = FrontMatter::default();
+    let mut lines = markdown.lines();
+    if lines.next().map(str::trim_end) != Some(DELIMITER) {
+        return front_matter;
+    }
+    let mut block = Vec::new();
+    loop {
+        match lines.next() {
+            Some(line) if line.trim_end() == DELIMITER => break,
+            Some(line) => block.push(line),
+            // An unclosed block is rendered as markdown, so it is not front matter either
+            None => return front_matter,
+        }
+    }
+
+    let mut index = 0;
+    while index < block.len() {
+        let line = block[index];
+        index += 1;
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        let value = value.trim();
+        let values = if value.is_empty() {
+            // A block list on the following, indented lines
+            let items: Vec<&str> = block[index..]
+                .iter()
+                .take_while(|line| line.starts_with(char::is_whitespace))
+                .map_while(|line| line.trim_start().strip_prefix("- "))
+                .collect();
+            index += items.len();
+            items.into_iter().map(unquote).collect()
+        } else if let Some(list) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            list.split(',')
+                .map(unquote)
+                .filter(|item| !item.is_empty())
+                .collect()
+        } else {
+            vec![unquote(value)]
+        };
+        if values.is_empty() {
+            continue;
+        }
+
+        match key.trim() {
+            "title" => front_matter.title = Some(values.join(" ")),
+            "author" | "authors" => front_matter.author = Some(values.join(", ")),
+            "keywords" => front_matter.keywords = Some(values.join(", ")),
+            "date" => front_matter.date = pdf_date(&values[0]),
+            _ => {}
+        }
+    }
+    front_matter
+}
+
+/// Strips surrounding quotes and whitespace from a YAML scalar
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// Converts the date formats Jekyll accepts to a PDF date, ignoring anything else
+fn pdf_date(value: &str) -> Option<String> {
+    let date: DateTime<FixedOffset> = DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z"))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+                .map(|date| Utc.from_utc_datetime(&date).fixed_offset())
+        })?;
+
+    let offset = date.offset().local_minus_utc();
+    let zone = match offset {
+        0 => "Z".to_string(),
+        _ => format!(
+            "{}{:02}'{:02}'",
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 3600,
+            offset.abs() % 3600 / 60
+        ),
+    };
+    Some(format!("D:{}{}", date.format("%Y%m%d%H%M%S"), zone))
+}
@@ -0,0 +1,79 @@
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::html;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{append_highlighted_html_for_styled_line, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME_SET: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Highlights fenced code blocks with inline styles so wkhtmltopdf needs no extra stylesheet.
+///
+/// Blocks without a language, or with one syntect does not recognise, are rendered exactly
+/// as comrak would render them without a highlighter.
+pub struct SyntaxHighlighter {
+    theme: &'static Theme,
+}
+
+impl SyntaxHighlighter {
+    /// Creates a highlighter using one of syntect's built-in themes
+    pub fn new(theme_name: &str) -> Self {
+        Self {
+            theme: THEME_SET
+                .themes
+                .get(theme_name)
+                .expect("built-in syntect theme is missing"),
+        }
+    }
+
+    fn find_syntax(lang: Option<&str>) -> Option<&'static SyntaxReference> {
+        lang.filter(|lang| !lang.is_empty())
+            .and_then(|lang| SYNTAX_SET.find_syntax_by_token(lang))
+    }
+
+    fn highlight(&self, code: &str, syntax: &SyntaxReference) -> Result<String, syntect::Error> {
+        let mut highlighter = HighlightLines::new(syntax, self.theme);
+        let mut output = String::new();
+
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter.highlight_line(line, &SYNTAX_SET)?;
+            append_highlighted_html_for_styled_line(&regions, IncludeBackground::No, &mut output)?;
+        }
+        Ok(output)
+    }
+}
+
+impl SyntaxHighlighterAdapter for SyntaxHighlighter {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()> {
+        match Self::find_syntax(lang).map(|syntax| self.highlight(code, syntax)) {
+            Some(Ok(highlighted)) => output.write_all(highlighted.as_bytes()),
+            _ => html::escape(output, code.as_bytes()),
+        }
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        html::write_opening_tag(output, "pre", attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        html::write_opening_tag(output, "code", attributes)
+    }
+}
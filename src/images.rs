@@ -0,0 +1,445 @@
+//! Inlines `<img>` sources as `data:` URIs so the renderer never has to fetch them.
+//!
+//! The bytes end up in the HTML that `/convert/html` returns, so sources are confined: local
+//! files are only read from inside the request's `base_dir`, the directory of an uploaded
+//! archive, and only when their content is an image. Remote images, like fonts and logos
+//! fetched with [`fetch_remote`], are only downloaded from public addresses, so clients cannot
+//! reach loopback, private or link-local services such as cloud metadata endpoints.
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::{Captures, Regex};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tracing::warn;
+
+/// Maximum size of a single inlined image
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+/// Maximum combined size of all images inlined into one document
+const MAX_TOTAL_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Redirects followed for a remote image, each checked like the original URL
+const MAX_REDIRECTS: usize = 5;
+
+/// Matches the `src` attribute of an `<img>` tag as emitted by comrak
+static IMG_SRC_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(<img\b[^>]*?\bsrc=")([^"]*)(")"#).unwrap());
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        // Host names are resolved to public addresses only, so checking them at connection
+        // time also covers names that resolve differently on a second lookup
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() > MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if let Err(e) = check_remote_url(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .expect("the HTTP client configuration is valid")
+});
+
+/// Resolves host names like the system resolver, but only to public addresses
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Whether an address is reachable on the public internet, as opposed to loopback, private,
+/// link-local, shared or reserved ranges
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (18..20).contains(&b))
+                // Reserved, 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    // Unique local, fc00::/7
+                    || (first & 0xfe00) == 0xfc00
+                    // Link-local, fe80::/10
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Checks the parts of a remote URL the resolver does not see: the scheme, and hosts given
+/// as IP addresses
+fn check_remote_url(url: &reqwest::Url) -> anyhow::Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("only http and https URLs are fetched");
+    }
+    let host = url.host_str().context("the URL has no host")?;
+    // IPv6 hosts keep their brackets in URLs
+    let Ok(address) = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    else {
+        return Ok(());
+    };
+    if !is_public(address) {
+        anyhow::bail!("{} is not a public address", address);
+    }
+    Ok(())
+}
+
+/// Rewrites every `<img src>` in the HTML to a `data:` URI.
+///
+/// Local paths must be relative and are read from `base_dir`; without one, local images are
+/// not read. Images that cannot be fetched or that exceed the size limits are left untouched.
+pub async fn inline_images(html: &str, base_dir: Option<&Path>) -> String {
+    let sources: Vec<String> = IMG_SRC_PATTERN
+        .captures_iter(html)
+        .map(|captures| captures[2].to_string())
+        .collect();
+
+    let mut budget = MAX_TOTAL_IMAGE_BYTES;
+    let mut data_uris = Vec::with_capacity(sources.len());
+    for source in &sources {
+        let data_uri = match fetch_image(&source.replace("&amp;", "&"), base_dir, budget).await {
+            Ok(Some((mime_type, bytes))) => {
+                budget -= bytes.len();
+                Some(format!(
+                    "data:{};base64,{}",
+                    mime_type,
+                    STANDARD.encode(&bytes)
+                ))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!(src = %source, error = format!("{:#}", e), "Failed to inline image");
+                None
+            }
+        };
+        data_uris.push(data_uri);
+    }
+
+    let mut data_uris = data_uris.into_iter();
+    IMG_SRC_PATTERN
+        .replace_all(html, |captures: &Captures| {
+            match data_uris.next().flatten() {
+                Some(data_uri) => format!("{}{}{}", &captures[1], data_uri, &captures[3]),
+                None => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Loads an image from an http(s) URL or a local path, returning its MIME type and bytes.
+///
+/// Returns `None` for sources that are already inline.
+async fn fetch_image(
+    source: &str,
+    base_dir: Option<&Path>,
+    budget: usize,
+) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+    let limit = MAX_IMAGE_BYTES.min(budget);
+
+    if source.starts_with("data:") {
+        return Ok(None);
+    }
+    let (mime_type, bytes) = if source.starts_with("http://") || source.starts_with("https://") {
+        let (declared_type, bytes) = fetch_remote(source, limit).await?;
+        let mime_type = sniff_image_type(&bytes)
+            .map(str::to_string)
+            .or(declared_type)
+            .context("unrecognised image type")?;
+        (mime_type, bytes)
+    } else {
+        let path = local_path(source, base_dir).await?;
+        let size = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", source))?
+            .len();
+        if size > limit as u64 {
+            anyhow::bail!(
+                "image is {} bytes, which exceeds the {} byte limit",
+                size,
+                limit
+            );
+        }
+        let bytes = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", source))?;
+        // The content decides, so files that are not images are never inlined whatever
+        // their name
+        let mime_type = sniff_image_type(&bytes).context("the file is not an image")?;
+        (mime_type.to_string(), bytes)
+    };
+    Ok(Some((mime_type, bytes)))
+}
+
+/// Resolves a local image source inside `base_dir`, rejecting absolute paths and paths that
+/// lead out of it, including through symbolic links
+async fn local_path(source: &str, base_dir: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let base_dir =
+        base_dir.context("local images are only read from the files of an uploaded archive")?;
+    let path = Path::new(source.strip_prefix("file://").unwrap_or(source));
+    if !path.is_relative() {
+        anyhow::bail!("absolute image paths are not read");
+    }
+    let base_dir = tokio::fs::canonicalize(base_dir)
+        .await
+        .context("Failed to resolve the archive directory")?;
+    let path = tokio::fs::canonicalize(base_dir.join(path))
+        .await
+        .with_context(|| format!("Failed to read {}", source))?;
+    if !path.starts_with(&base_dir) {
+        anyhow::bail!("the path leads out of the archive directory");
+    }
+    Ok(path)
+}
+
+/// Downloads a remote file without reading more than `limit` bytes, returning any image
+/// content type the server declared
+pub async fn fetch_remote(url: &str, limit: usize) -> anyhow::Result<(Option<String>, Vec<u8>)> {
+    let url = reqwest::Url::parse(url)?;
+    check_remote_url(&url)?;
+    let mut response = CLIENT.get(url).send().await?.error_for_status()?;
+
+    let declared_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        })
+        .filter(|value| value.starts_with("image/"));
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > limit {
+            anyhow::bail!("response exceeds the {} byte limit", limit);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok((declared_type, bytes))
+}
+
+/// Identifies common image formats from their leading bytes
+pub fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.starts_with(b"BM") {
+        Some("image/bmp")
+    } else {
+        None
+    }
+}
+
+/// Identifies images from their content, including SVG documents, which have no signature
+fn sniff_image_type(bytes: &[u8]) -> Option<&'static str> {
+    sniff_mime_type(bytes).or_else(|| is_svg(bytes).then_some("image/svg+xml"))
+}
+
+/// Whether the bytes are an SVG document: markup whose first element is `<svg>`, possibly
+/// after an XML declaration, comments or a doctype
+fn is_svg(bytes: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return false;
+    };
+    let mut rest = text.trim_start_matches('\u{feff}').trim_start();
+    loop {
+        if rest.starts_with("<svg") {
+            return true;
+        }
+        let closing = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<!") {
+            ">"
+        } else {
+            return false;
+        };
+        match rest.find(closing) {
+            Some(end) => rest = rest[end + closing.len()..].trim_start(),
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    /// An archive directory with an image and a text file, next to a file outside it
+    struct Archive {
+        root: PathBuf,
+    }
+
+    impl Archive {
+        fn new() -> Self {
+            let root =
+                std::env::temp_dir().join(format!("md-to-pdf-images-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(root.join("archive/images")).unwrap();
+            std::fs::write(root.join("archive/images/chart.png"), PNG).unwrap();
+            std::fs::write(root.join("archive/notes.png"), "not an image").unwrap();
+            std::fs::write(root.join("secret.png"), PNG).unwrap();
+            Self { root }
+        }
+
+        fn dir(&self) -> PathBuf {
+            self.root.join("archive")
+        }
+    }
+
+    impl Drop for Archive {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn img(src: &str) -> String {
+        format!(r#"<p><img src="{}" alt="x" /></p>"#, src)
+    }
+
+    #[tokio::test]
+    async fn inlines_relative_images_from_the_archive() {
+        let archive = Archive::new();
+        let html = inline_images(&img("images/chart.png"), Some(&archive.dir())).await;
+        let expected = format!("data:image/png;base64,{}", STANDARD.encode(PNG));
+        assert_eq!(html, img(&expected));
+    }
+
+    #[tokio::test]
+    async fn leaves_paths_outside_the_archive_alone() {
+        let archive = Archive::new();
+        let outside = archive.root.join("secret.png");
+        std::os::unix::fs::symlink(&outside, archive.dir().join("link.png")).unwrap();
+
+        for src in [
+            "../secret.png".to_string(),
+            "images/../../secret.png".to_string(),
+            outside.display().to_string(),
+            format!("file://{}", outside.display()),
+            "link.png".to_string(),
+        ] {
+            let html = inline_images(&img(&src), Some(&archive.dir())).await;
+            assert_eq!(html, img(&src), "{src} was inlined");
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_no_local_files_without_an_archive() {
+        let archive = Archive::new();
+        let src = archive.root.join("secret.png").display().to_string();
+        assert_eq!(inline_images(&img(&src), None).await, img(&src));
+        assert_eq!(
+            inline_images(&img("secret.png"), None).await,
+            img("secret.png")
+        );
+    }
+
+    #[tokio::test]
+    async fn only_inlines_files_whose_content_is_an_image() {
+        let archive = Archive::new();
+        let html = inline_images(&img("notes.png"), Some(&archive.dir())).await;
+        assert_eq!(html, img("notes.png"));
+
+        std::fs::write(
+            archive.dir().join("logo.svg"),
+            "<?xml version=\"1.0\"?>\n<!-- logo -->\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+        )
+        .unwrap();
+        let html = inline_images(&img("logo.svg"), Some(&archive.dir())).await;
+        assert!(html.contains("data:image/svg+xml;base64,"), "{html}");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_fetch_internal_addresses() {
+        for url in [
+            "http://127.0.0.1:9/image.png",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.1.2.3/image.png",
+            "http://[::1]:9/image.png",
+            "http://[::ffff:192.168.0.1]/image.png",
+            "http://localhost:9/image.png",
+            "file:///etc/passwd",
+        ] {
+            let error = fetch_remote(url, MAX_IMAGE_BYTES).await.expect_err(url);
+            let message = format!("{error:#}");
+            assert!(
+                message.contains("public address") || message.contains("only http"),
+                "{url}: {message}"
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_addresses() {
+        for public in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(public.parse().unwrap()), "{public}");
+        }
+        for internal in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(internal.parse().unwrap()), "{internal}");
+        }
+    }
+}
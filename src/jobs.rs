@@ -0,0 +1,120 @@
+//! In-memory store for conversions that run in the background.
+//!
+//! `POST /convert/async` registers a job and answers immediately; the conversion then runs
+//! in a spawned task and records its outcome here, where `GET /jobs/{id}` picks it up.
+//! Finished jobs are dropped once they are older than `JOB_TTL_SECS`, whether or not their
+//! result was fetched. Jobs do not survive a restart.
+
+use actix_web::web::Bytes;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often expired jobs are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Progress of a job, as reported to clients
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// What a job has produced so far
+#[derive(Clone)]
+pub enum JobOutcome {
+    Pending,
+    Done {
+        pdf_bytes: Bytes,
+        filename: String,
+    },
+    /// The JSON error body `/convert` would have answered with
+    Failed(serde_json::Map<String, serde_json::Value>),
+}
+
+impl JobOutcome {
+    pub fn status(&self) -> JobStatus {
+        match self {
+            JobOutcome::Pending => JobStatus::Pending,
+            JobOutcome::Done { .. } => JobStatus::Done,
+            JobOutcome::Failed(_) => JobStatus::Failed,
+        }
+    }
+}
+
+struct Job {
+    outcome: JobOutcome,
+    /// When the job finished; pending jobs never expire
+    finished: Option<Instant>,
+}
+
+struct Jobs {
+    entries: HashMap<Uuid, Job>,
+    last_pruned: Instant,
+}
+
+pub struct JobStore {
+    ttl: Duration,
+    jobs: Mutex<Jobs>,
+}
+
+impl JobStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            jobs: Mutex::new(Jobs {
+                entries: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Registers a new pending job and returns its ID
+    pub fn create(&self) -> Uuid {
+        let id = Uuid::new_v4();
+        self.lock().entries.insert(
+            id,
+            Job {
+                outcome: JobOutcome::Pending,
+                finished: None,
+            },
+        );
+        id
+    }
+
+    /// Records the result of a job, starting its time to live
+    pub fn finish(&self, id: Uuid, outcome: JobOutcome) {
+        if let Some(job) = self.lock().entries.get_mut(&id) {
+            job.outcome = outcome;
+            job.finished = Some(Instant::now());
+        }
+    }
+
+    /// Returns the job's outcome, or `None` for unknown and expired jobs
+    pub fn get(&self, id: Uuid) -> Option<JobOutcome> {
+        self.lock()
+            .entries
+            .get(&id)
+            .filter(|job| !self.is_expired(job, Instant::now()))
+            .map(|job| job.outcome.clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Jobs> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if now.duration_since(jobs.last_pruned) >= PRUNE_INTERVAL {
+            jobs.entries.retain(|_, job| !self.is_expired(job, now));
+            jobs.last_pruned = now;
+        }
+        jobs
+    }
+
+    fn is_expired(&self, job: &Job, now: Instant) -> bool {
+        job.finished
+            .is_some_and(|finished| now.duration_since(finished) >= self.ttl)
+    }
+}
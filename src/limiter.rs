@@ -0,0 +1,66 @@
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::warn;
+
+/// Returned when no conversion slot frees up within the queue timeout
+#[derive(Debug)]
+pub struct QueueTimeout {
+    /// How long the request waited for a slot
+    pub waited: Duration,
+    /// Suggested delay before retrying, sent as the `Retry-After` header
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for QueueTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Server is at conversion capacity; no slot became available within {} seconds",
+            self.waited.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for QueueTimeout {}
+
+/// Caps the number of wkhtmltopdf processes running at the same time
+pub struct ConversionLimiter {
+    semaphore: Semaphore,
+    queue_timeout: Duration,
+    conversion_timeout: Duration,
+}
+
+impl ConversionLimiter {
+    /// `conversion_timeout` bounds how long a running conversion can hold its slot, so it is
+    /// used as the retry estimate when the queue is full
+    pub fn new(
+        max_concurrent: usize,
+        queue_timeout: Duration,
+        conversion_timeout: Duration,
+    ) -> Self {
+        Self {
+            semaphore: Semaphore::new(max_concurrent),
+            queue_timeout,
+            conversion_timeout,
+        }
+    }
+
+    /// Waits for a free conversion slot, giving up after the queue timeout
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, QueueTimeout> {
+        match tokio::time::timeout(self.queue_timeout, self.semaphore.acquire()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed, so only the timeout can fail
+            _ => {
+                warn!(
+                    queue_timeout_secs = self.queue_timeout.as_secs(),
+                    "Timed out waiting for a conversion slot"
+                );
+                Err(QueueTimeout {
+                    waited: self.queue_timeout,
+                    // Retry-After is in whole seconds, and zero would invite an immediate retry
+                    retry_after: self.conversion_timeout.max(Duration::from_secs(1)),
+                })
+            }
+        }
+    }
+}
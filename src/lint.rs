@@ -0,0 +1,101 @@
+//! Finds likely mistakes in markdown without rendering it, for editors giving live feedback.
+//!
+//! Problems that comrak silently renders as text are reported: code fences that are never
+//! closed, which swallow the rest of the document, and references to link definitions that
+//! do not exist.
+
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{parse_document_with_broken_link_callback, Arena, ComrakOptions};
+use serde::Serialize;
+
+/// A problem found in the markdown
+#[derive(Debug, Serialize)]
+pub struct Warning {
+    /// 1-based line of the problem, when it can be located
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// 1-based index into `sections`, for requests that use them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<usize>,
+    pub message: String,
+}
+
+/// Parses the markdown with the request's options and returns the problems found in it
+pub fn check(markdown: &str, options: &ComrakOptions) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let mut broken_references = Vec::new();
+    let mut record_reference = |label: &str| {
+        // Collapsed references such as `[Foo][]` are also reported with an empty label
+        if !label.is_empty() && !broken_references.contains(&label.to_string()) {
+            broken_references.push(label.to_string());
+        }
+        None
+    };
+
+    let arena = Arena::new();
+    let root = parse_document_with_broken_link_callback(
+        &arena,
+        markdown,
+        options,
+        Some(&mut record_reference),
+    );
+    let lines: Vec<&str> = markdown.lines().collect();
+    // comrak numbers lines from after the front matter
+    let offset = root
+        .children()
+        .find_map(|node| match &node.data.borrow().value {
+            NodeValue::FrontMatter(front_matter) => Some(front_matter.matches('\n').count()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    for node in root.descendants() {
+        if let Some(line) = unclosed_fence(node, &lines[offset..]) {
+            warnings.push(Warning {
+                line: Some(line + offset),
+                section: None,
+                message: "Code fence is never closed, so the rest of the document is shown as code"
+                    .to_string(),
+            });
+        }
+    }
+
+    for label in broken_references {
+        warnings.push(Warning {
+            line: reference_line(&lines, &label),
+            section: None,
+            message: format!("No link definition found for [{}]", label),
+        });
+    }
+    warnings.sort_by_key(|warning| warning.line);
+    warnings
+}
+
+/// Returns the opening line of a fenced code block that runs to the end of its container
+fn unclosed_fence<'a>(node: &'a AstNode<'a>, lines: &[&str]) -> Option<usize> {
+    let data = node.data.borrow();
+    let NodeValue::CodeBlock(block) = &data.value else {
+        return None;
+    };
+    if !block.fenced {
+        return None;
+    }
+
+    // A closed block ends on its closing fence; strip quote markers and indentation first
+    let last = lines.get(data.sourcepos.end.line.checked_sub(1)?)?;
+    let last = last
+        .trim_start_matches(|c: char| c == '>' || c.is_whitespace())
+        .trim_end();
+    let fence = char::from(block.fence_char);
+    let closed = last.len() >= block.fence_length && last.chars().all(|c| c == fence);
+    let is_opening_line = data.sourcepos.end.line == data.sourcepos.start.line;
+    (!closed || is_opening_line).then_some(data.sourcepos.start.line)
+}
+
+/// Finds the first line that mentions the label in brackets, ignoring case like comrak does
+fn reference_line(lines: &[&str], label: &str) -> Option<usize> {
+    let needle = format!("[{}]", label.to_lowercase());
+    lines
+        .iter()
+        .position(|line| line.to_lowercase().contains(&needle))
+        .map(|index| index + 1)
+}
@@ -0,0 +1,135 @@
+//! Builds the header page that shows a logo on every page of the PDF.
+//!
+//! wkhtmltopdf renders the page given with `--header-html` into the top margin of each page.
+//! Remote logos are downloaded by the service and embedded like base64 ones, so the header
+//! page never fetches anything itself.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Maximum size of the logo image
+const MAX_LOGO_BYTES: usize = 1024 * 1024;
+/// Horizontal positions of the logo in the header
+pub const POSITIONS: &[&str] = &["left", "center", "right"];
+pub const DEFAULT_POSITION: &str = "right";
+pub const DEFAULT_HEIGHT: &str = "10mm";
+
+/// An image shown in the header of every page
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HeaderLogo {
+    /// http(s) URL the image is downloaded from
+    pub url: Option<String>,
+    /// Base64-encoded image
+    pub data: Option<String>,
+    pub position: Option<String>,
+    /// Height of the image; the width follows from its aspect ratio
+    pub height: Option<String>,
+}
+
+impl HeaderLogo {
+    pub fn position(&self) -> &str {
+        self.position.as_deref().unwrap_or(DEFAULT_POSITION)
+    }
+
+    pub fn height(&self) -> &str {
+        self.height.as_deref().unwrap_or(DEFAULT_HEIGHT)
+    }
+}
+
+/// Validates the logo, returning a client-facing message on failure
+pub fn validate(logo: &HeaderLogo) -> Result<(), String> {
+    match (&logo.url, &logo.data) {
+        (Some(url), None) => {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(
+                    "Invalid header_logo.url. Only http and https URLs are supported".to_string(),
+                );
+            }
+        }
+        (None, Some(data)) => {
+            let bytes = STANDARD
+                .decode(data)
+                .map_err(|_| "header_logo.data is not valid base64".to_string())?;
+            if bytes.len() > MAX_LOGO_BYTES {
+                return Err(format!(
+                    "header_logo.data is {} bytes, which exceeds the {} byte limit",
+                    bytes.len(),
+                    MAX_LOGO_BYTES
+                ));
+            }
+            if crate::images::sniff_mime_type(&bytes).is_none() {
+                return Err(
+                    "header_logo.data is not a PNG, JPEG, GIF, WebP or BMP image".to_string(),
+                );
+            }
+        }
+        _ => return Err("header_logo must have exactly one of url or data".to_string()),
+    }
+
+    if !POSITIONS.contains(&logo.position()) {
+        return Err(format!(
+            "Unsupported header_logo.position '{}'. Supported values: {}",
+            logo.position(),
+            POSITIONS.join(", ")
+        ));
+    }
+    if !crate::LENGTH_PATTERN.is_match(logo.height()) {
+        return Err(format!(
+            "Invalid header_logo.height '{}'. Expected a number followed by mm, cm, in or px",
+            logo.height()
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the header page for a validated logo, downloading a remote image.
+///
+/// A remote logo that fails to download or is not an image is logged and `None` is
+/// returned, so the PDF is rendered without a header.
+pub async fn header_html(logo: &HeaderLogo) -> Option<String> {
+    let (mime_type, bytes) = match (&logo.url, &logo.data) {
+        (_, Some(data)) => {
+            let bytes = STANDARD.decode(data).unwrap_or_default();
+            (crate::images::sniff_mime_type(&bytes)?.to_string(), bytes)
+        }
+        (Some(url), None) => match crate::images::fetch_remote(url, MAX_LOGO_BYTES).await {
+            Ok((declared_type, bytes)) => {
+                let Some(mime_type) = crate::images::sniff_mime_type(&bytes)
+                    .map(str::to_string)
+                    .or(declared_type)
+                else {
+                    warn!(url = %url, "Downloaded header logo is not a recognised image type");
+                    return None;
+                };
+                (mime_type, bytes)
+            }
+            Err(e) => {
+                warn!(url = %url, error = format!("{:#}", e), "Failed to download header logo");
+                return None;
+            }
+        },
+        (None, None) => return None,
+    };
+
+    Some(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <style>
+        html, body {{ margin: 0; padding: 0; }}
+        .header-logo {{ text-align: {position}; line-height: 0; }}
+        .header-logo img {{ height: {height}; }}
+    </style>
+</head>
+<body>
+    <div class="header-logo"><img src="data:{mime_type};base64,{data}" alt=""></div>
+</body>
+</html>"#,
+        position = logo.position(),
+        height = logo.height(),
+        mime_type = mime_type,
+        data = STANDARD.encode(&bytes),
+    ))
+}
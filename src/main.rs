@@ -1,199 +1,3203 @@
 use actix_cors::Cors;
-use actix_web::{http, web, App, HttpResponse, HttpServer, Result};
-use anyhow::Context;
-use comrak::{markdown_to_html, ComrakOptions};
+use actix_multipart::Multipart;
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ETag, EntityTag,
+    ExtendedValue, HeaderName, HeaderValue, IfNoneMatch, Quality,
+};
+use actix_web::middleware::{from_fn, Compress};
+use actix_web::{
+    web, App, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer,
+    ResponseError, Result,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::format::{Item, StrftimeItems};
+use chrono::Utc;
+use comrak::{format_html_with_plugins, parse_document, Arena, ComrakOptions, ComrakPlugins};
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use lopdf::Permissions;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize)]
+mod archive;
+mod auth;
+mod backend;
+mod batch;
+mod cache;
+mod callouts;
+mod capabilities;
+mod cli;
+mod coalesce;
+mod config;
+mod destination;
+mod emoji;
+mod estimate;
+mod fonts;
+mod footnotes;
+mod frontmatter;
+mod highlight;
+mod images;
+mod jobs;
+mod limiter;
+mod lint;
+mod logo;
+mod math;
+mod mermaid;
+mod metrics;
+mod openapi;
+mod pagebreak;
+mod pagenumbers;
+mod pdf;
+mod pdfa;
+mod ratelimit;
+mod request_id;
+mod sanitize;
+mod shutdown;
+mod source;
+mod subscript;
+mod template;
+mod themes;
+mod toc;
+mod upload;
+
+use archive::InvalidArchive;
+use backend::{ConversionTimeout, PdfBackend, RenderFailed, Unsupported};
+use batch::BatchRequest;
+use cache::PdfCache;
+use clap::Parser;
+use cli::{Cli, Command};
+use coalesce::SharedFailure;
+use config::Config;
+use destination::{UploadFailed, UploadNotAllowed};
+use fonts::Font;
+use highlight::SyntaxHighlighter;
+use jobs::{JobOutcome, JobStatus, JobStore};
+use limiter::{ConversionLimiter, QueueTimeout};
+use logo::HeaderLogo;
+use metrics::Metrics;
+use pdf::PageLimitExceeded;
+use pdfa::GhostscriptMissing;
+use ratelimit::RateLimiter;
+use source::{SourceFetchFailed, SourceNotAllowed};
+use themes::Theme;
+use upload::UploadError;
+
+/// Supported page sizes with their portrait width and height
+const PAGE_SIZES: &[(&str, &str, &str)] = &[
+    ("A4", "210mm", "297mm"),
+    ("A3", "297mm", "420mm"),
+    ("Letter", "8.5in", "11in"),
+    ("Legal", "8.5in", "14in"),
+];
+
+/// Accepted values for `input_format`
+const INPUT_FORMATS: &[&str] = &["markdown", "html"];
+const DEFAULT_INPUT_FORMAT: &str = "markdown";
+
+/// Supported text directions
+const DIRECTIONS: &[&str] = &["ltr", "rtl"];
+
+/// Supported page orientations
+const ORIENTATIONS: &[&str] = &["Portrait", "Landscape"];
+
+/// Ways of writing the numbers substituted for `[page]` and `[topage]`
+const PAGE_NUMBER_STYLES: &[&str] = &["decimal", "roman"];
+const DEFAULT_PAGE_NUMBER_STYLE: &str = "decimal";
+
+/// Markdown dialects a request can be parsed as
+const FLAVORS: &[&str] = &["commonmark", "gfm"];
+/// Extensions GitHub enables for markdown files
+const GFM_EXTENSIONS: &[&str] = &[
+    "table",
+    "strikethrough",
+    "tasklist",
+    "autolink",
+    "footnotes",
+    "alerts",
+];
+
+/// Extensions on when the request sets no flavor, so pipe tables work out of the box
+const DEFAULT_EXTENSIONS: &[&str] = &["table"];
+
+/// Markdown extensions that can be enabled per request; all but `alerts` and `subscript`
+/// are comrak's own
+const EXTENSIONS: &[&str] = &[
+    "table",
+    "strikethrough",
+    "tasklist",
+    "autolink",
+    "footnotes",
+    "alerts",
+    "description_lists",
+    "superscript",
+    "subscript",
+];
+
+const DEFAULT_PAGE_SIZE: &str = "A4";
+const DEFAULT_ORIENTATION: &str = "Portrait";
+/// Maximum size of the per-request custom stylesheet
+const MAX_CUSTOM_CSS_BYTES: usize = 100 * 1024;
+
+const DEFAULT_DPI: u32 = 96;
+const DPI_RANGE: std::ops::RangeInclusive<u32> = 72..=600;
+const DEFAULT_ZOOM: f32 = 1.0;
+const ZOOM_RANGE: std::ops::RangeInclusive<f32> = 0.5..=3.0;
+/// wkhtmltopdf's own defaults, which keep images close to their original quality
+const DEFAULT_IMAGE_QUALITY: u8 = 94;
+const IMAGE_QUALITY_RANGE: std::ops::RangeInclusive<u8> = 0..=100;
+const DEFAULT_IMAGE_DPI: u32 = 600;
+const IMAGE_DPI_RANGE: std::ops::RangeInclusive<u32> = 36..=1200;
+const COLUMNS_RANGE: std::ops::RangeInclusive<u8> = 1..=3;
+const DEFAULT_COLUMN_GAP: &str = "8mm";
+/// Milliseconds scripts may run before the page is rendered; well inside the renderer timeout
+const RENDER_DELAY_RANGE: std::ops::RangeInclusive<u32> = 0..=10_000;
+/// Format of the footer timestamp; "UTC" is appended to formats that do not print it
+const DEFAULT_TIMESTAMP_FORMAT: &str = "Generated %Y-%m-%d %H:%M UTC";
+/// Base font size of the document; headings and body text are sized relative to it
+const DEFAULT_FONT_SIZE: &str = "16pt";
+/// Bleed added around each page of print-ready output
+const DEFAULT_BLEED: &str = "3mm";
+
+/// Width of `/convert/png` previews in pixels
+const DEFAULT_PREVIEW_WIDTH: u32 = 800;
+const PREVIEW_WIDTH_RANGE: std::ops::RangeInclusive<u32> = 100..=4000;
+
+const DEFAULT_WATERMARK_OPACITY: f64 = 0.15;
+const DEFAULT_WATERMARK_ROTATION: f64 = -45.0;
+/// Maximum length of the watermark text in characters
+const MAX_WATERMARK_CHARS: usize = 100;
+
+const DEFAULT_FILENAME: &str = "document.pdf";
+/// Routes that can upload their PDF to `upload_url` instead of answering with it
+const UPLOAD_URL_ROUTES: &str = "/convert, /convert/upload, /convert/text and /convert/archive";
+/// Response types `/convert` can produce, in the order they are listed to clients
+const CONVERT_RESPONSE_TYPES: &[&str] = &["application/pdf", "application/json"];
+/// Content types `/convert/text` accepts as raw markdown
+const TEXT_CONTENT_TYPES: &[&str] = &["text/markdown", "text/plain"];
+/// Headers `response_headers` may set besides `X-` headers; anything describing the body
+/// itself, such as its type or length, stays under the service's control
+const RESPONSE_HEADERS: &[&str] = &["cache-control", "expires", "content-language"];
+/// `X-` headers the service sets itself
+const RESERVED_RESPONSE_HEADERS: &[&str] = &["x-request-id"];
+const DEFAULT_MARGIN_TOP: &str = "20mm";
+const DEFAULT_MARGIN_BOTTOM: &str = "20mm";
+/// wkhtmltopdf's own left and right margin, used when the request sets none
+const DEFAULT_MARGIN_SIDE: &str = "10mm";
+/// Line height assumed for estimates when the request sets none, as in the light theme
+const DEFAULT_LINE_HEIGHT: f32 = 1.6;
+/// Time allowed for writing out responses once conversions have drained at shutdown
+const RESPONSE_FLUSH_TIMEOUT_SECS: u64 = 5;
+
+/// Matches a BCP 47 language tag such as "en" or "ar-EG"
+static LANG_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z]{2,8}(-[A-Za-z0-9]{1,8})*$").unwrap());
+
+/// Matches a length such as "15mm" or "0.5in"
+static LENGTH_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d+(\.\d+)?(mm|cm|in|px)$").unwrap());
+
+/// Matches a font size such as "11pt" or "14px"
+static FONT_SIZE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d+(\.\d+)?(pt|px|mm|cm|in)$").unwrap());
+
+/// Page margins, each expressed as a length with a unit
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Margins {
+    top: Option<String>,
+    bottom: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+}
+
+/// Text for the left, center and right slots of a page header or footer.
+///
+/// Values may contain wkhtmltopdf placeholders such as `[page]`, `[topage]` and `[title]`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct HeaderFooter {
+    left: Option<String>,
+    center: Option<String>,
+    right: Option<String>,
+}
+
+impl HeaderFooter {
+    /// Returns the text of each slot by slot name
+    fn slots(&self) -> [(&'static str, Option<&str>); 3] {
+        [
+            ("left", self.left.as_deref()),
+            ("center", self.center.as_deref()),
+            ("right", self.right.as_deref()),
+        ]
+    }
+
+    /// Appends `--<prefix>-left` style arguments for each slot that is set, except for slots
+    /// with page numbers when those are drawn by [`pagenumbers::stamp`]
+    fn apply(&self, command: &mut tokio::process::Command, prefix: &str, skip_page_numbers: bool) {
+        for (slot, text) in self.slots() {
+            let Some(text) = text else {
+                continue;
+            };
+            if skip_page_numbers && pagenumbers::has_page_number(text) {
+                continue;
+            }
+            command.arg(format!("--{}-{}", prefix, slot)).arg(text);
+        }
+    }
+}
+
+/// Document metadata written to the PDF Info dictionary
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Metadata {
+    title: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    keywords: Option<String>,
+    /// Taken from the front matter `date`; not a request option
+    #[serde(skip)]
+    creation_date: Option<String>,
+}
+
+impl Metadata {
+    /// Returns the Info dictionary entries for the fields that are set
+    fn info_entries(&self) -> Vec<(&str, &str)> {
+        [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+            ("CreationDate", &self.creation_date),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .collect()
+    }
+}
+
+/// Text stamped diagonally across every page
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Watermark {
+    text: String,
+    /// Opacity between 0 (invisible) and 1 (solid)
+    opacity: Option<f64>,
+    /// Clockwise rotation in degrees
+    rotation: Option<f64>,
+}
+
+impl Watermark {
+    /// Returns the stylesheet for the watermark layer.
+    ///
+    /// wkhtmltopdf repeats fixed-position elements on every printed page.
+    fn css(&self) -> String {
+        let rotation = self.rotation.unwrap_or(DEFAULT_WATERMARK_ROTATION);
+        format!(
+            r#".watermark {{
+            position: fixed;
+            top: 45%;
+            left: 0;
+            width: 100%;
+            text-align: center;
+            font-size: 6rem !important;
+            font-weight: bold;
+            color: #000;
+            opacity: {opacity};
+            -webkit-transform: rotate({rotation}deg);
+            transform: rotate({rotation}deg);
+            z-index: 1000;
+            pointer-events: none;
+        }}"#,
+            opacity = self.opacity.unwrap_or(DEFAULT_WATERMARK_OPACITY),
+            rotation = rotation
+        )
+    }
+
+    fn element(&self) -> String {
+        format!(
+            r#"<div class="watermark">{}</div>"#,
+            escape_html(&self.text)
+        )
+    }
+}
+
+/// Password protection applied to the generated PDF
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Encryption {
+    user_password: String,
+    owner_password: Option<String>,
+    allow_print: Option<bool>,
+    allow_copy: Option<bool>,
+}
+
+impl Encryption {
+    /// Encrypts the PDF with the configured passwords and permissions
+    fn apply(&self, pdf: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut permissions = Permissions::empty();
+        if self.allow_print.unwrap_or(true) {
+            permissions |= Permissions::PRINTABLE | Permissions::PRINTABLE_IN_HIGH_QUALITY;
+        }
+        if self.allow_copy.unwrap_or(true) {
+            permissions |= Permissions::COPYABLE | Permissions::COPYABLE_FOR_ACCESSIBILITY;
+        }
+
+        // Without an owner password nobody may lift the restrictions, so use a random one
+        let owner_password = self
+            .owner_password
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        pdf::encrypt(pdf, &self.user_password, &owner_password, permissions)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 struct MarkdownRequest {
-    markdown: String,
+    /// Document source, interpreted according to `input_format`. The aliases spare clients
+    /// whose payloads name it differently a translation step
+    #[serde(alias = "html", alias = "content", alias = "md")]
+    markdown: Option<String>,
+    /// http(s) URL the markdown is downloaded from instead of being sent in `markdown`
+    source_url: Option<String>,
+    /// Markdown documents rendered separately and joined with page breaks into one PDF
+    sections: Option<Vec<String>>,
+    input_format: Option<String>,
+    /// Treat HTML input as a complete document instead of wrapping it in the page template
+    #[serde(default)]
+    full_document: bool,
+    page_size: Option<String>,
+    orientation: Option<String>,
+    margins: Option<Margins>,
+    extensions: Option<Vec<String>>,
+    /// Markdown dialect; unset keeps CommonMark with front matter and the requested extensions
+    flavor: Option<String>,
+    metadata: Option<Metadata>,
+    filename: Option<String>,
+    /// Pre-signed URL the PDF is uploaded to with `PUT` instead of being returned. Where the
+    /// PDF goes does not change it, so it is left out of the cache key
+    #[serde(skip_serializing)]
+    upload_url: Option<String>,
+    /// Extra headers for the PDF response. They do not change the PDF, so they are left out
+    /// of the cache key and ETag
+    #[serde(skip_serializing)]
+    response_headers: Option<BTreeMap<String, String>>,
+    custom_css: Option<String>,
+    header: Option<HeaderFooter>,
+    footer: Option<HeaderFooter>,
+    header_logo: Option<HeaderLogo>,
+    #[serde(default)]
+    toc: bool,
+    #[serde(default)]
+    inline_images: bool,
+    encryption: Option<Encryption>,
+    watermark: Option<Watermark>,
+    theme: Option<String>,
+    dpi: Option<u32>,
+    zoom: Option<f32>,
+    image_quality: Option<u8>,
+    image_dpi: Option<u32>,
+    sanitize: Option<bool>,
+    lang: Option<String>,
+    dir: Option<String>,
+    fonts: Option<Vec<Font>>,
+    #[serde(default)]
+    render_mermaid: bool,
+    #[serde(default)]
+    render_math: bool,
+    #[serde(default)]
+    render_emoji: bool,
+    /// Pass raw HTML in the markdown through to the page instead of omitting it. Only for
+    /// trusted input: without `sanitize` the HTML can run scripts and load local files in
+    /// the renderer, and even sanitized it can change the whole layout
+    #[serde(default)]
+    allow_raw_html: bool,
+    #[serde(default)]
+    pdf_a: bool,
+    /// Produce the PDF in shades of gray, for cheaper printing
+    #[serde(default)]
+    grayscale: bool,
+    #[serde(default)]
+    print_ready: bool,
+    bleed: Option<String>,
+    #[serde(default)]
+    smart_shrinking: bool,
+    /// Run scripts in the page before rendering it; off for safety and speed
+    #[serde(default)]
+    enable_javascript: bool,
+    /// How long scripts may run before the page is rendered, with `enable_javascript`
+    render_delay_ms: Option<u32>,
+    /// Keep every newline inside a paragraph as a line break; defaults to on for `gfm`
+    hard_breaks: Option<bool>,
+    /// Print CSS background colours and images; defaults to what the theme needs
+    print_background: Option<bool>,
+    font_size: Option<String>,
+    line_height: Option<f32>,
+    /// Width the body is narrowed to and centred at, instead of the default side padding
+    content_max_width: Option<String>,
+    /// Number of columns the body text flows through
+    columns: Option<u8>,
+    column_gap: Option<String>,
+    /// Base64-encoded PDF whose pages are inserted before the generated content
+    prepend_pdf_base64: Option<String>,
+    /// Base64-encoded PDF whose pages are inserted after the generated content
+    append_pdf_base64: Option<String>,
+    #[serde(default)]
+    allow_empty: bool,
+    /// Print the UTC generation time in the right footer slot
+    #[serde(default)]
+    footer_timestamp: bool,
+    /// strftime-style format of the footer timestamp
+    timestamp_format: Option<String>,
+    /// Added to the page numbers substituted for `[page]` and `[topage]`
+    page_offset: Option<i32>,
+    page_number_style: Option<String>,
+    /// Directory relative links resolve against, set for extracted `/convert/archive` uploads
+    #[serde(skip)]
+    base_dir: Option<PathBuf>,
+}
+
+impl MarkdownRequest {
+    /// Validates the rendering options, returning a client-facing message on failure
+    fn validate(&self) -> Result<(), String> {
+        let sources = [
+            self.markdown.is_some(),
+            self.source_url.is_some(),
+            self.sections.is_some(),
+        ];
+        match sources.iter().filter(|given| **given).count() {
+            0 => return Err("One of markdown, source_url or sections is required".to_string()),
+            1 => {}
+            _ => return Err("Provide only one of markdown, source_url or sections".to_string()),
+        }
+
+        if let Some(sections) = &self.sections {
+            if sections.is_empty() {
+                return Err("sections must contain at least one section".to_string());
+            }
+            if self.input_format() == "html" {
+                return Err("sections are only supported for markdown input".to_string());
+            }
+        }
+
+        // A blank document is almost always a client bug, so it is only rendered on request
+        let has_content = self.markdown.is_some() || self.sections.is_some();
+        if !self.allow_empty
+            && has_content
+            && self
+                .markdown_sources()
+                .iter()
+                .all(|source| source.trim().is_empty())
+        {
+            return Err(
+                "markdown is empty. Set allow_empty to true to render a blank document".to_string(),
+            );
+        }
+
+        if self.footer_timestamp
+            && self
+                .footer
+                .as_ref()
+                .is_some_and(|footer| footer.right.is_some())
+        {
+            return Err(
+                "footer_timestamp uses the right footer slot, so footer.right must not be set"
+                    .to_string(),
+            );
+        }
+
+        if let Some(format) = &self.timestamp_format {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("Invalid timestamp_format '{}'", format));
+            }
+        }
+
+        if !PAGE_NUMBER_STYLES.contains(&self.page_number_style()) {
+            return Err(format!(
+                "Unsupported page_number_style '{}'. Supported values: {}",
+                self.page_number_style(),
+                PAGE_NUMBER_STYLES.join(", ")
+            ));
+        }
+        if pagenumbers::is_drawn(self) && self.page_offset() < 0 {
+            return Err("page_offset must not be negative with roman page numbers".to_string());
+        }
+
+        if let Some(input_format) = &self.input_format {
+            if !INPUT_FORMATS.contains(&input_format.as_str()) {
+                return Err(format!(
+                    "Unsupported input_format '{}'. Supported values: {}",
+                    input_format,
+                    INPUT_FORMATS.join(", ")
+                ));
+            }
+        }
+
+        if let Some(flavor) = &self.flavor {
+            if !FLAVORS.contains(&flavor.as_str()) {
+                return Err(format!(
+                    "Unsupported flavor '{}'. Supported values: {}",
+                    flavor,
+                    FLAVORS.join(", ")
+                ));
+            }
+            if self.input_format() == "html" {
+                return Err("flavor is only supported for markdown input".to_string());
+            }
+            if flavor == "commonmark" && self.extensions.iter().flatten().next().is_some() {
+                return Err(
+                    "flavor 'commonmark' is strict CommonMark and cannot be combined with extensions"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.input_format() == "html" && self.toc {
+            return Err("toc is only supported for markdown input".to_string());
+        }
+
+        if self.input_format() == "html" && self.render_mermaid {
+            return Err("render_mermaid is only supported for markdown input".to_string());
+        }
+
+        if self.input_format() == "html" && self.hard_breaks.is_some() {
+            return Err("hard_breaks is only supported for markdown input".to_string());
+        }
+
+        if self.input_format() == "html" && self.render_math {
+            return Err("render_math is only supported for markdown input".to_string());
+        }
+
+        if self.input_format() == "html" && self.render_emoji {
+            return Err("render_emoji is only supported for markdown input".to_string());
+        }
+
+        if self.input_format() == "html" && self.allow_raw_html {
+            return Err("allow_raw_html is only supported for markdown input".to_string());
+        }
+
+        if self.full_document {
+            if self.input_format() != "html" {
+                return Err("full_document requires input_format 'html'".to_string());
+            }
+            if self.sanitize() {
+                return Err(
+                    "full_document requires sanitize to be false, because sanitizing keeps only the document body"
+                        .to_string(),
+                );
+            }
+        }
+
+        if let Some(page_size) = &self.page_size {
+            if !PAGE_SIZES.iter().any(|(name, _, _)| name == page_size) {
+                return Err(format!(
+                    "Unsupported page_size '{}'. Supported values: {}",
+                    page_size,
+                    PAGE_SIZES
+                        .iter()
+                        .map(|(name, _, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+
+        if let Some(orientation) = &self.orientation {
+            if !ORIENTATIONS.contains(&orientation.as_str()) {
+                return Err(format!(
+                    "Unsupported orientation '{}'. Supported values: {}",
+                    orientation,
+                    ORIENTATIONS.join(", ")
+                ));
+            }
+        }
+
+        if let Some(margins) = &self.margins {
+            for (side, value) in [
+                ("top", &margins.top),
+                ("bottom", &margins.bottom),
+                ("left", &margins.left),
+                ("right", &margins.right),
+            ] {
+                if let Some(value) = value {
+                    if !LENGTH_PATTERN.is_match(value) {
+                        return Err(format!(
+                            "Invalid margins.{} '{}'. Expected a number followed by mm, cm, in or px",
+                            side, value
+                        ));
+                    }
+                }
+            }
+        }
+
+        for extension in self.extensions.iter().flatten() {
+            if !EXTENSIONS.contains(&extension.as_str()) {
+                return Err(format!(
+                    "Unsupported extension '{}'. Supported values: {}",
+                    extension,
+                    EXTENSIONS.join(", ")
+                ));
+            }
+        }
+        // comrak reads a single pair of tildes as strikethrough too
+        if self.has_extension("subscript") && self.has_extension("strikethrough") {
+            return Err(
+                "The subscript extension cannot be combined with strikethrough, which is also on for flavor 'gfm'"
+                    .to_string(),
+            );
+        }
+
+        if let Some(css) = &self.custom_css {
+            if css.len() > MAX_CUSTOM_CSS_BYTES {
+                return Err(format!(
+                    "custom_css is {} bytes, which exceeds the {} byte limit",
+                    css.len(),
+                    MAX_CUSTOM_CSS_BYTES
+                ));
+            }
+            if css.to_lowercase().contains("</style") {
+                return Err("custom_css must not contain a closing </style> tag".to_string());
+            }
+        }
+
+        if let Some(lang) = &self.lang {
+            if !LANG_PATTERN.is_match(lang) {
+                return Err(format!(
+                    "Invalid lang '{}'. Expected a language tag such as 'en' or 'ar-EG'",
+                    lang
+                ));
+            }
+        }
+
+        if let Some(dir) = &self.dir {
+            if !DIRECTIONS.contains(&dir.as_str()) {
+                return Err(format!(
+                    "Unsupported dir '{}'. Supported values: {}",
+                    dir,
+                    DIRECTIONS.join(", ")
+                ));
+            }
+        }
+
+        if let Some(fonts) = &self.fonts {
+            fonts::validate(fonts)?;
+        }
+
+        if let Some(header_logo) = &self.header_logo {
+            logo::validate(header_logo)?;
+            if self.header.is_some() {
+                return Err(
+                    "header_logo replaces the text header, so header must not be set".to_string(),
+                );
+            }
+            let margin_top = self
+                .margins
+                .as_ref()
+                .and_then(|m| m.top.as_deref())
+                .unwrap_or(DEFAULT_MARGIN_TOP);
+            if length_to_points(header_logo.height()) > length_to_points(margin_top) {
+                return Err(format!(
+                    "header_logo.height '{}' does not fit in the top margin of {}",
+                    header_logo.height(),
+                    margin_top
+                ));
+            }
+        }
+
+        if let Some(render_delay_ms) = self.render_delay_ms {
+            if !self.enable_javascript {
+                return Err("render_delay_ms requires enable_javascript".to_string());
+            }
+            if !RENDER_DELAY_RANGE.contains(&render_delay_ms) {
+                return Err(format!(
+                    "Invalid render_delay_ms {}. Expected a value between {} and {}",
+                    render_delay_ms,
+                    RENDER_DELAY_RANGE.start(),
+                    RENDER_DELAY_RANGE.end()
+                ));
+            }
+        }
+
+        if let Some(dpi) = self.dpi {
+            if !DPI_RANGE.contains(&dpi) {
+                return Err(format!(
+                    "Invalid dpi {}. Expected a value between {} and {}",
+                    dpi,
+                    DPI_RANGE.start(),
+                    DPI_RANGE.end()
+                ));
+            }
+        }
+
+        if let Some(zoom) = self.zoom {
+            if !ZOOM_RANGE.contains(&zoom) {
+                return Err(format!(
+                    "Invalid zoom {}. Expected a value between {} and {}",
+                    zoom,
+                    ZOOM_RANGE.start(),
+                    ZOOM_RANGE.end()
+                ));
+            }
+        }
+
+        if let Some(image_quality) = self.image_quality {
+            if !IMAGE_QUALITY_RANGE.contains(&image_quality) {
+                return Err(format!(
+                    "Invalid image_quality {}. Expected a value between {} and {}",
+                    image_quality,
+                    IMAGE_QUALITY_RANGE.start(),
+                    IMAGE_QUALITY_RANGE.end()
+                ));
+            }
+        }
+
+        if let Some(image_dpi) = self.image_dpi {
+            if !IMAGE_DPI_RANGE.contains(&image_dpi) {
+                return Err(format!(
+                    "Invalid image_dpi {}. Expected a value between {} and {}",
+                    image_dpi,
+                    IMAGE_DPI_RANGE.start(),
+                    IMAGE_DPI_RANGE.end()
+                ));
+            }
+        }
+
+        if let Some(font_size) = &self.font_size {
+            if !FONT_SIZE_PATTERN.is_match(font_size) {
+                return Err(format!(
+                    "Invalid font_size '{}'. Expected a number followed by pt, px, mm, cm or in",
+                    font_size
+                ));
+            }
+        }
+
+        if let Some(line_height) = self.line_height {
+            if !line_height.is_finite() || line_height <= 0.0 {
+                return Err(format!(
+                    "Invalid line_height {}. Expected a positive number",
+                    line_height
+                ));
+            }
+        }
+
+        for (name, attachment) in [
+            ("prepend_pdf_base64", &self.prepend_pdf_base64),
+            ("append_pdf_base64", &self.append_pdf_base64),
+        ] {
+            if let Some(attachment) = attachment {
+                let bytes = STANDARD
+                    .decode(attachment)
+                    .map_err(|_| format!("{} is not valid base64", name))?;
+                pdf::validate_attachment(&bytes).map_err(|e| format!("{} {}", name, e))?;
+            }
+        }
+
+        if let Some(theme) = &self.theme {
+            if themes::find(theme).is_none() {
+                return Err(format!(
+                    "Unsupported theme '{}'. Supported values: {}",
+                    theme,
+                    themes::THEMES
+                        .iter()
+                        .map(|theme| theme.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+
+        if let Some(watermark) = &self.watermark {
+            if watermark.text.trim().is_empty() {
+                return Err("watermark.text must not be empty".to_string());
+            }
+            if watermark.text.chars().count() > MAX_WATERMARK_CHARS {
+                return Err(format!(
+                    "watermark.text exceeds the {} character limit",
+                    MAX_WATERMARK_CHARS
+                ));
+            }
+            if let Some(opacity) = watermark.opacity {
+                if !(0.0..=1.0).contains(&opacity) {
+                    return Err(format!(
+                        "Invalid watermark.opacity {}. Expected a number between 0 and 1",
+                        opacity
+                    ));
+                }
+            }
+            if let Some(rotation) = watermark.rotation {
+                if !(-360.0..=360.0).contains(&rotation) {
+                    return Err(format!(
+                        "Invalid watermark.rotation {}. Expected degrees between -360 and 360",
+                        rotation
+                    ));
+                }
+            }
+        }
+
+        if let Some(content_max_width) = &self.content_max_width {
+            if !LENGTH_PATTERN.is_match(content_max_width) {
+                return Err(format!(
+                    "Invalid content_max_width '{}'. Expected a number followed by mm, cm, in or px",
+                    content_max_width
+                ));
+            }
+        }
+
+        if let Some(columns) = self.columns {
+            if !COLUMNS_RANGE.contains(&columns) {
+                return Err(format!(
+                    "Invalid columns {}. Expected a value between {} and {}",
+                    columns,
+                    COLUMNS_RANGE.start(),
+                    COLUMNS_RANGE.end()
+                ));
+            }
+        }
+
+        if let Some(column_gap) = &self.column_gap {
+            if self.columns.is_none() {
+                return Err("column_gap requires columns".to_string());
+            }
+            if !LENGTH_PATTERN.is_match(column_gap) {
+                return Err(format!(
+                    "Invalid column_gap '{}'. Expected a number followed by mm, cm, in or px",
+                    column_gap
+                ));
+            }
+        }
+
+        if let Some(bleed) = &self.bleed {
+            if !self.print_ready {
+                return Err("bleed requires print_ready".to_string());
+            }
+            if !LENGTH_PATTERN.is_match(bleed) {
+                return Err(format!(
+                    "Invalid bleed '{}'. Expected a number followed by mm, cm, in or px",
+                    bleed
+                ));
+            }
+        }
+
+        if let Some(response_headers) = &self.response_headers {
+            for (name, value) in response_headers {
+                validate_response_header(name, value)?;
+            }
+        }
+
+        if self.pdf_a && self.encryption.is_some() {
+            return Err(
+                "pdf_a cannot be combined with encryption; PDF/A forbids encryption".to_string(),
+            );
+        }
+
+        if let Some(encryption) = &self.encryption {
+            if encryption.user_password.is_empty() {
+                return Err("encryption.user_password must not be empty".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the comrak options, enabling the requested extensions
+    fn comrak_options(&self) -> ComrakOptions {
+        let mut options = ComrakOptions::default();
+        for extension in self.enabled_extensions() {
+            match extension {
+                "table" => options.extension.table = true,
+                "strikethrough" => options.extension.strikethrough = true,
+                "tasklist" => options.extension.tasklist = true,
+                "autolink" => options.extension.autolink = true,
+                "footnotes" => options.extension.footnotes = true,
+                "description_lists" => options.extension.description_lists = true,
+                "superscript" => options.extension.superscript = true,
+                _ => {}
+            }
+        }
+
+        // Heading ids become named destinations in the PDF, so `#heading` links and the
+        // table of contents jump to the right page
+        options.extension.header_ids = Some(String::new());
+        // Front matter becomes PDF metadata instead of showing up in the body
+        if self.reads_front_matter() {
+            options.extension.front_matter_delimiter = Some(frontmatter::DELIMITER.to_string());
+        }
+        if self.flavor.as_deref() == Some("gfm") {
+            // GitHub renders raw HTML with tags such as <script> disabled
+            options.extension.tagfilter = true;
+        }
+        options.render.hardbreaks = self.hard_breaks();
+        // Sanitizing, when requested, still runs on the rendered result
+        options.render.unsafe_ = self.allow_raw_html;
+        options
+    }
+
+    /// Returns the requested extensions plus those the flavor, or its absence, turns on
+    fn enabled_extensions(&self) -> Vec<&str> {
+        let flavor_extensions = match self.flavor.as_deref() {
+            Some("gfm") => GFM_EXTENSIONS,
+            Some(_) => &[],
+            None => DEFAULT_EXTENSIONS,
+        };
+        let mut extensions: Vec<&str> = self
+            .extensions
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        extensions.extend(flavor_extensions);
+        extensions
+    }
+
+    fn has_extension(&self, name: &str) -> bool {
+        self.enabled_extensions().contains(&name)
+    }
+
+    /// Whether a leading `---` block is read as front matter; strict CommonMark renders it
+    fn reads_front_matter(&self) -> bool {
+        self.input_format() != "html" && self.flavor.as_deref() != Some("commonmark")
+    }
+
+    /// Returns the document source, which is empty until a `source_url` has been resolved
+    fn markdown(&self) -> &str {
+        self.markdown.as_deref().unwrap_or_default()
+    }
+
+    /// Returns the sections, or the document source as a single section
+    fn markdown_sources(&self) -> Vec<&str> {
+        match &self.sections {
+            Some(sections) => sections.iter().map(String::as_str).collect(),
+            None => vec![self.markdown()],
+        }
+    }
+
+    /// Returns the request metadata, with fields it leaves unset filled from the front matter
+    fn document_metadata(&self) -> Metadata {
+        let requested = self.metadata.as_ref();
+        let front_matter = if self.reads_front_matter() {
+            frontmatter::parse(self.markdown_sources().first().copied().unwrap_or_default())
+        } else {
+            frontmatter::FrontMatter::default()
+        };
+        let field = |requested: Option<&Option<String>>, fallback: Option<String>| {
+            requested.cloned().flatten().or(fallback)
+        };
+        Metadata {
+            title: field(requested.map(|m| &m.title), front_matter.title),
+            author: field(requested.map(|m| &m.author), front_matter.author),
+            subject: requested.and_then(|m| m.subject.clone()),
+            keywords: field(requested.map(|m| &m.keywords), front_matter.keywords),
+            creation_date: front_matter.date,
+        }
+    }
+
+    /// Returns the document title from the metadata or front matter, if there is one
+    fn title(&self) -> Option<String> {
+        self.document_metadata().title
+    }
+
+    /// Returns a sanitized download filename ending in `.pdf`
+    fn filename(&self) -> String {
+        self.filename
+            .as_deref()
+            .and_then(sanitize_pdf_filename)
+            .unwrap_or_else(|| DEFAULT_FILENAME.to_string())
+    }
+
+    fn input_format(&self) -> &str {
+        self.input_format.as_deref().unwrap_or(DEFAULT_INPUT_FORMAT)
+    }
+
+    fn page_size(&self) -> &str {
+        self.page_size.as_deref().unwrap_or(DEFAULT_PAGE_SIZE)
+    }
+
+    fn orientation(&self) -> &str {
+        self.orientation.as_deref().unwrap_or(DEFAULT_ORIENTATION)
+    }
+
+    /// Whether the rendered HTML is passed through the sanitizer, which is on by default
+    fn sanitize(&self) -> bool {
+        self.sanitize.unwrap_or(true)
+    }
+
+    /// Whether newlines inside a paragraph become line breaks, as GitHub does in issues and
+    /// comments, rather than spaces
+    fn hard_breaks(&self) -> bool {
+        self.hard_breaks
+            .unwrap_or(self.flavor.as_deref() == Some("gfm"))
+    }
+
+    /// Whether backgrounds are printed, which only themes that rely on them do by default
+    fn print_background(&self) -> bool {
+        self.print_background
+            .unwrap_or_else(|| self.theme().print_background)
+    }
+
+    fn dpi(&self) -> u32 {
+        self.dpi.unwrap_or(DEFAULT_DPI)
+    }
+
+    fn zoom(&self) -> f32 {
+        self.zoom.unwrap_or(DEFAULT_ZOOM)
+    }
+
+    fn page_offset(&self) -> i32 {
+        self.page_offset.unwrap_or_default()
+    }
+
+    fn page_number_style(&self) -> &str {
+        self.page_number_style
+            .as_deref()
+            .unwrap_or(DEFAULT_PAGE_NUMBER_STYLE)
+    }
+
+    fn image_quality(&self) -> u8 {
+        self.image_quality.unwrap_or(DEFAULT_IMAGE_QUALITY)
+    }
+
+    fn image_dpi(&self) -> u32 {
+        self.image_dpi.unwrap_or(DEFAULT_IMAGE_DPI)
+    }
+
+    /// Returns the footer timestamp for the current time, if requested, labelled as UTC
+    fn footer_timestamp(&self) -> Option<String> {
+        if !self.footer_timestamp {
+            return None;
+        }
+        let format = self
+            .timestamp_format
+            .as_deref()
+            .unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+        let timestamp = Utc::now().format(format).to_string();
+        if timestamp.contains("UTC") {
+            Some(timestamp)
+        } else {
+            Some(format!("{} UTC", timestamp))
+        }
+    }
+
+    fn font_size(&self) -> &str {
+        self.font_size.as_deref().unwrap_or(DEFAULT_FONT_SIZE)
+    }
+
+    fn column_gap(&self) -> &str {
+        self.column_gap.as_deref().unwrap_or(DEFAULT_COLUMN_GAP)
+    }
+
+    fn bleed(&self) -> &str {
+        self.bleed.as_deref().unwrap_or(DEFAULT_BLEED)
+    }
+
+    /// Returns the `lang` and `dir` attributes for the `<html>` element
+    fn html_attributes(&self) -> String {
+        let mut attributes = String::new();
+        if let Some(lang) = &self.lang {
+            attributes.push_str(&format!(r#" lang="{}""#, lang));
+        }
+        if let Some(dir) = &self.dir {
+            attributes.push_str(&format!(r#" dir="{}""#, dir));
+        }
+        attributes
+    }
+
+    /// Returns the selected theme, falling back to the default for unknown names
+    fn theme(&self) -> &'static Theme {
+        self.theme
+            .as_deref()
+            .and_then(themes::find)
+            .or_else(|| themes::find(themes::DEFAULT_THEME))
+            .expect("the default theme is registered")
+    }
+
+    /// Returns the page width and height, taking orientation into account
+    fn page_dimensions(&self) -> (&'static str, &'static str) {
+        let (_, width, height) = PAGE_SIZES
+            .iter()
+            .find(|(name, _, _)| *name == self.page_size())
+            .unwrap_or(&PAGE_SIZES[0]);
+
+        if self.orientation() == "Landscape" {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Returns the area the body text is laid out in, for estimating page counts
+    fn text_layout(&self) -> estimate::Layout {
+        let (page_width, page_height) = self.page_dimensions();
+        let margins = self.margins.as_ref();
+        let margin =
+            |value: Option<&str>, default: &str| length_to_points(value.unwrap_or(default));
+        let font_size = length_to_points(self.font_size());
+        let page_width = length_to_points(page_width)
+            - margin(margins.and_then(|m| m.left.as_deref()), DEFAULT_MARGIN_SIDE)
+            - margin(
+                margins.and_then(|m| m.right.as_deref()),
+                DEFAULT_MARGIN_SIDE,
+            );
+        let width = match &self.content_max_width {
+            Some(content_max_width) => page_width.min(length_to_points(content_max_width)),
+            // The body is padded by 5em on either side
+            None => page_width - 10.0 * font_size,
+        };
+        let height = length_to_points(page_height)
+            - margin(margins.and_then(|m| m.top.as_deref()), DEFAULT_MARGIN_TOP)
+            - margin(
+                margins.and_then(|m| m.bottom.as_deref()),
+                DEFAULT_MARGIN_BOTTOM,
+            );
+        estimate::Layout {
+            width,
+            height,
+            font_size,
+            line_height: self.line_height.unwrap_or(DEFAULT_LINE_HEIGHT),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+    /// Additional context for the client, such as a summary of the renderer's output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl ErrorResponse {
+    fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            detail: None,
+        }
+    }
+
+    fn with_detail(error: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+/// Error body for documents over the `MAX_PAGES` limit
+#[derive(Debug, Serialize)]
+struct PageLimitResponse {
+    error: String,
+    page_count: usize,
+    max_pages: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct UploadResponse {
+    /// The `upload_url` without its query string, which holds the signature
+    location: String,
+    size_bytes: usize,
+    page_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PdfJsonResponse {
+    pdf_base64: String,
+    size_bytes: usize,
+    page_count: usize,
+    /// Problems the renderer reported without failing, such as images that did not load
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct JobResponse {
+    job_id: String,
+    status: JobStatus,
+    /// The error body of failed jobs, as `/convert` would have returned it
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    error: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationResponse {
+    valid: bool,
+    warnings: Vec<lint::Warning>,
+}
+
+#[derive(Debug, Serialize)]
+struct EstimateResponse {
+    #[serde(flatten)]
+    estimate: estimate::Estimate,
+    /// Whether even the smallest predicted page count is over `MAX_PAGES`, when it is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exceeds_max_pages: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct LivenessResponse {
+    status: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: String,
+    version: String,
+    backend: String,
+    /// Version of the external renderer, such as "wkhtmltopdf 0.12.6 (with patched qt)"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend_version: Option<String>,
+    /// Whether Ghostscript is installed, which `pdf_a` output requires
+    pdf_a_available: bool,
+}
+
+/// Checks that a `response_headers` entry is well-formed and may be set by clients
+fn validate_response_header(name: &str, value: &str) -> Result<(), String> {
+    let header_name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("Invalid response header name '{}'", name))?;
+    let lower = header_name.as_str();
+    let allowed = RESPONSE_HEADERS.contains(&lower)
+        || (lower.starts_with("x-") && !RESERVED_RESPONSE_HEADERS.contains(&lower));
+    if !allowed {
+        return Err(format!(
+            "Response header '{}' cannot be set. Allowed headers: {} and X- headers other than {}",
+            name,
+            RESPONSE_HEADERS.join(", "),
+            RESERVED_RESPONSE_HEADERS.join(", ")
+        ));
+    }
+    // Rejects control characters such as CR and LF, which would split the header
+    HeaderValue::from_str(value)
+        .map_err(|_| format!("Invalid value for response header '{}'", name))?;
+    Ok(())
+}
+
+/// Strips path separators and control characters and ensures a `.pdf` extension.
+///
+/// Returns `None` when nothing usable is left of the name.
+fn sanitize_pdf_filename(name: &str) -> Option<String> {
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control() && *c != '/' && *c != '\\')
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+
+    if cleaned.is_empty() {
+        None
+    } else if cleaned.to_lowercase().ends_with(".pdf") {
+        Some(cleaned.to_string())
+    } else {
+        Some(format!("{}.pdf", cleaned))
+    }
+}
+
+/// Replaces filesystem paths with their file name so messages do not reveal the server layout
+fn redact_paths(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let is_path = word.contains('/') && !word.contains("://");
+            match Path::new(word.trim_end_matches([':', ',', '.'])).file_name() {
+                Some(name) if is_path => {
+                    let suffix = &word[word.trim_end_matches([':', ',', '.']).len()..];
+                    format!("{}{}", name.to_string_lossy(), suffix)
+                }
+                _ => word.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Flows the body through `columns` columns, keeping headings with the text that follows
+/// them and blocks that are hard to read when split in one column. wkhtmltopdf's WebKit only
+/// understands the prefixed properties
+fn columns_css(columns: u8, gap: &str) -> String {
+    format!(
+        r#"body {{ -webkit-column-count: {columns}; column-count: {columns}; -webkit-column-gap: {gap}; column-gap: {gap}; }}
+        h1, h2, h3, h4, h5, h6 {{ -webkit-column-break-after: avoid; break-after: avoid; }}
+        h1, h2, h3, h4, h5, h6, pre, table, blockquote, img, dt, .callout, .math.display {{ -webkit-column-break-inside: avoid; break-inside: avoid; }}"#
+    )
+}
+
+/// Converts a validated length such as "20mm" or "8.5in" to PDF points
+fn length_to_points(length: &str) -> f32 {
+    let split = length
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(length.len());
+    let value: f32 = length[..split].parse().unwrap_or(0.0);
+    match &length[split..] {
+        "mm" => value * 72.0 / 25.4,
+        "cm" => value * 72.0 / 2.54,
+        "in" => value * 72.0,
+        "px" => value * 0.75,
+        _ => value,
+    }
+}
+
+/// Escapes text for safe inclusion in HTML content and attribute values
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Reverses the escaping comrak applies to text and code block content
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Renders the request markdown to an HTML fragment, highlighting fenced code blocks
+fn render_markdown(request: &MarkdownRequest, theme: &Theme) -> String {
+    let options = request.comrak_options();
+    let highlighter = SyntaxHighlighter::new(theme.code_theme);
+    let mut plugins = ComrakPlugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&highlighter);
+
+    // Sections are parsed separately so an unclosed block cannot run into the next one.
+    // Blank sections are skipped so they do not turn into empty pages.
+    let mut sources = request.markdown_sources();
+    if request.sections.is_some() {
+        sources.retain(|source| !source.trim().is_empty());
+    }
+    // Formulas are swapped out before parsing so their TeX is not read as markdown
+    let mut formulas = Vec::new();
+    let sources: Vec<Cow<str>> = sources
+        .into_iter()
+        .map(|source| {
+            if request.render_math {
+                Cow::Owned(math::extract(source, &mut formulas))
+            } else {
+                Cow::Borrowed(source)
+            }
+        })
+        .collect();
+    let arenas: Vec<Arena<_>> = sources.iter().map(|_| Arena::new()).collect();
+    let roots: Vec<_> = sources
+        .iter()
+        .zip(&arenas)
+        .map(|(source, arena)| parse_document(arena, source, &options))
+        .collect();
+
+    let mut html = Vec::new();
+    if request.toc {
+        html.extend_from_slice(toc::table_of_contents(&roots).as_bytes());
+    }
+    for (index, (root, arena)) in roots.into_iter().zip(&arenas).enumerate() {
+        if index > 0 {
+            html.extend_from_slice(pagebreak::PAGE_BREAK_HTML.as_bytes());
+            html.push(b'\n');
+        }
+        pagebreak::mark(root);
+        if request.has_extension("alerts") {
+            callouts::mark(arena, root);
+        }
+        if request.has_extension("subscript") {
+            subscript::mark(root);
+        }
+        if request.render_emoji {
+            emoji::expand(root);
+        }
+        let mut section_html = Vec::new();
+        format_html_with_plugins(root, &options, &mut section_html, &plugins)
+            .expect("writing HTML to a Vec cannot fail");
+        if index > 0 && request.has_extension("footnotes") {
+            section_html = footnotes::scope(&section_html, index + 1);
+        }
+        html.append(&mut section_html);
+    }
+    let html = pagebreak::insert(&String::from_utf8(html).expect("comrak always produces UTF-8"));
+    let html = callouts::insert(&html);
+    let html = subscript::insert(&html);
+    math::insert(&html, &formulas)
+}
+
+/// Wraps the request content in the HTML page template.
+///
+/// Markdown is converted with comrak first; HTML input is used as the page body directly.
+fn markdown_to_html_converter(request: &MarkdownRequest, font_css: &str) -> String {
+    let theme = request.theme();
+    let content = match request.input_format() {
+        "html" => request.markdown().to_string(),
+        _ => render_markdown(request, theme),
+    };
+
+    // Strip anything scriptable before the HTML reaches the renderer's WebKit engine
+    let content = if request.sanitize() {
+        sanitize::sanitize_html(&content)
+    } else {
+        content
+    };
+    let (page_width, _) = request.page_dimensions();
+
+    let css = format!(
+        r#"        @page {{
+            size: {page_size} {orientation};
+            margin: 10mm;
+        }}
+        html {{
+            font-size: {font_size} !important;
+            width: {page_width};  /* page width */
+        }}
+        body {{
+            padding: 0 5em;
+            font-size: 1rem !important;
+            width: 100%;
+            margin: 0;
+            overflow-wrap: break-word;
+            word-wrap: break-word;
+            word-break: break-word;
+        }}
+        /* Force consistent sizes */
+        p, div, span, li, td, dt, dd {{
+            font-size: 1rem !important;
+        }}
+        h1 {{ font-size: 1.4rem !important; }}
+        h2 {{ font-size: 1.2rem !important; }}
+        h3 {{ font-size: 1.1rem !important; }}
+        h4, h5, h6 {{ font-size: 1.1rem !important; }}
+        /* Nested ordered lists number 1., a., i. like an outline */
+        ol {{ list-style-type: decimal; }}
+        ol ol {{ list-style-type: lower-alpha; }}
+        ol ol ol {{ list-style-type: lower-roman; }}
+        dl {{ margin: 1em 0; }}
+        dt {{ font-weight: bold; margin-top: 0.5em; page-break-after: avoid; }}
+        dd {{ margin: 0.25em 0 0.5em 2em; }}
+        dd > p {{ margin: 0.25em 0; }}
+        /* Keep sub- and superscripts from widening the line they are on */
+        sub, sup {{ font-size: 75%; line-height: 0; }}
+        /* Tables split across pages repeat their header row, and rows are not cut in half */
+        table {{ max-width: 100%; }}
+        thead {{ display: table-header-group; }}
+        tfoot {{ display: table-footer-group; }}
+        tr {{ page-break-inside: avoid; break-inside: avoid; }}
+        /* Footnotes are collected in a smaller, ruled-off list at the end of the document */
+        .footnote-ref a, .footnote-backref {{ text-decoration: none; }}
+        section.footnotes {{ border-top: 1px solid #8c959f; margin-top: 2em; }}
+        section.footnotes li, section.footnotes p {{ font-size: 0.85rem !important; }}
+        section.footnotes li {{ page-break-inside: avoid; }}
+        .page-break {{ page-break-after: always; }}
+        .math.display {{ display: block; margin: 1em 0; text-align: center; }}
+        /* GitHub-style alerts, colored like GitHub's */
+        .callout {{ border-left: 0.25em solid #0969da; padding: 0.5em 1em; margin: 1em 0; page-break-inside: avoid; }}
+        .callout > :last-child {{ margin-bottom: 0; }}
+        .callout-title {{ font-weight: bold; margin-top: 0; }}
+        .callout-note {{ border-color: #0969da; }}
+        .callout-note .callout-title {{ color: #0969da; }}
+        .callout-note .callout-title:before {{ content: "\2139\00a0"; }}
+        .callout-tip {{ border-color: #1a7f37; }}
+        .callout-tip .callout-title {{ color: #1a7f37; }}
+        .callout-tip .callout-title:before {{ content: "\2713\00a0"; }}
+        .callout-important {{ border-color: #8250df; }}
+        .callout-important .callout-title {{ color: #8250df; }}
+        .callout-important .callout-title:before {{ content: "\2757\00a0"; }}
+        .callout-warning {{ border-color: #9a6700; }}
+        .callout-warning .callout-title {{ color: #9a6700; }}
+        .callout-warning .callout-title:before {{ content: "\26a0\00a0"; }}
+        .callout-caution {{ border-color: #d1242f; }}
+        .callout-caution .callout-title {{ color: #d1242f; }}
+        .callout-caution .callout-title:before {{ content: "\2298\00a0"; }}
+        /* Handle long URLs */
+        a {{
+            word-wrap: break-word;
+            word-break: break-all;
+            white-space: pre-wrap;
+            overflow-wrap: break-word;
+            max-width: 100%;
+            display: inline-block;
+        }}
+        {font_css}
+        {theme_css}
+        {default_css}
+        {line_height_css}
+        {content_width_css}
+        {columns_css}
+        {direction_css}
+        {watermark_css}
+        {custom_css}"#,
+        page_size = request.page_size(),
+        orientation = request.orientation().to_lowercase(),
+        page_width = page_width,
+        font_css = font_css,
+        theme_css = theme.css,
+        default_css = template::default_css(),
+        font_size = request.font_size(),
+        // Themes set their own line height, which applies unless the request overrides it
+        line_height_css = request
+            .line_height
+            .map(|line_height| format!("body {{ line-height: {}; }}", line_height))
+            .unwrap_or_default(),
+        // Replaces the side padding, which would otherwise narrow the measure further
+        content_width_css = request
+            .content_max_width
+            .as_ref()
+            .map(|width| format!(
+                "body {{ padding: 0; max-width: {}; margin: 0 auto; }}",
+                width
+            ))
+            .unwrap_or_default(),
+        columns_css = request
+            .columns
+            .filter(|columns| *columns > 1)
+            .map(|columns| columns_css(columns, request.column_gap()))
+            .unwrap_or_default(),
+        direction_css = match request.dir.as_deref() {
+            Some("rtl") => "body { direction: rtl; text-align: right; }",
+            _ => "",
+        },
+        watermark_css = request
+            .watermark
+            .as_ref()
+            .map(Watermark::css)
+            .unwrap_or_default(),
+        custom_css = request.custom_css.as_deref().unwrap_or_default(),
+    );
+    let title = escape_html(request.title().as_deref().unwrap_or("Document"));
+    let body = format!(
+        "{}\n    {}",
+        request
+            .watermark
+            .as_ref()
+            .map(Watermark::element)
+            .unwrap_or_default(),
+        content
+    );
+
+    if let Some(custom) = template::custom() {
+        return template::fill(custom, &title, &css, &body);
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html{html_attributes}>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>{title}</title>{base}
+    <style>
+{css}
+    </style>
+</head>
+<body>
+    {body}
+</body>
+</html>"#,
+        html_attributes = request.html_attributes(),
+        title = title,
+        base = request
+            .base_dir
+            .as_deref()
+            .and_then(|dir| reqwest::Url::from_directory_path(dir).ok())
+            .map(|url| format!("\n    <base href=\"{}\">", escape_html(url.as_str())))
+            .unwrap_or_default(),
+        css = css,
+        body = body,
+    )
+}
+
+/// Builds an attachment Content-Disposition header for the given filename.
+///
+/// Non-ASCII names get an ASCII fallback plus an RFC 5987 `filename*` parameter.
+fn content_disposition(filename: &str) -> ContentDisposition {
+    let ascii_filename: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    let mut parameters = vec![DispositionParam::Filename(ascii_filename)];
+
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    }
+}
+
+/// Builds the CORS policy; without configured origins every origin, method and header is allowed
+fn cors(allowed_origins: Option<&[String]>) -> Cors {
+    let Some(origins) = allowed_origins else {
+        return Cors::permissive();
+    };
+    origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allowed_methods(["GET", "POST"])
+        .allowed_headers([
+            header::ACCEPT,
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-api-key"),
+            request_id::REQUEST_ID_HEADER,
+        ])
+        .expose_headers([
+            header::CONTENT_DISPOSITION,
+            header::ETAG,
+            header::RETRY_AFTER,
+            request_id::REQUEST_ID_HEADER,
+        ])
+        .max_age(3600)
+}
+
+/// Builds the JSON extractor config, answering oversized bodies with a structured 413
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |err, req| match err {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                let response = HttpResponse::PayloadTooLarge().json(ErrorResponse::new(format!(
+                    "Request body exceeds the {} byte limit set by MAX_MARKDOWN_BYTES",
+                    limit
+                )));
+                InternalError::from_response(err, response).into()
+            }
+            // Usually raw markdown posted as text/plain, or curl's form encoding default
+            JsonPayloadError::ContentType => {
+                let response = unsupported_media_type(req);
+                InternalError::from_response(err, response).into()
+            }
+            err => {
+                let response = HttpResponse::build(err.status_code()).json(
+                    ErrorResponse::with_detail("Invalid JSON request body", err.to_string()),
+                );
+                InternalError::from_response(err, response).into()
+            }
+        })
+}
+
+/// Explains the expected request format to clients that did not send JSON, usually raw
+/// markdown posted as text/plain or curl's form encoding default
+fn unsupported_media_type(req: &HttpRequest) -> HttpResponse {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("none");
+    let example = if req.path().ends_with("/batch") {
+        r##"{"documents": [{"name": "report", "markdown": "# Title"}]}"##
+    } else {
+        r##"{"markdown": "# Title"}"##
+    };
+    HttpResponse::UnsupportedMediaType().json(ErrorResponse::with_detail(
+        format!(
+            "Unsupported Content-Type '{}'. This endpoint expects 'application/json', e.g. {}",
+            content_type, example
+        ),
+        "To send a markdown file as is, POST it as multipart/form-data to /convert/upload, \
+         or as the body of a text/markdown request to /convert/text",
+    ))
+}
+
+/// Builds the query string extractor config, answering malformed parameters with JSON
+fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let response = HttpResponse::BadRequest().json(ErrorResponse::with_detail(
+            "Invalid query string",
+            err.to_string(),
+        ));
+        InternalError::from_response(err, response).into()
+    })
+}
+
+/// Runs `convert` once a conversion slot is free, unless the PDF is cached or an identical
+/// request is converting already. Then the cached PDF or that request's outcome is returned,
+/// without taking a slot or rendering the HTML again.
+async fn convert_shared(
+    request: &MarkdownRequest,
+    cache: &PdfCache,
+    limiter: &ConversionLimiter,
+    convert: impl Future<Output = anyhow::Result<Vec<u8>>>,
+) -> anyhow::Result<Vec<u8>> {
+    let key = PdfCache::key(request);
+    // A cached PDF would show the time of the first conversion in its footer
+    let cacheable = cache.is_enabled() && !request.footer_timestamp;
+    if cacheable {
+        if let Some(pdf_bytes) = cache.get(&key) {
+            debug!("Serving PDF from cache");
+            return Ok(pdf_bytes);
+        }
+    }
+
+    cache
+        .coalesce(key, async {
+            let _permit = limiter.acquire().await?;
+            let pdf_bytes = convert.await?;
+            if cacheable {
+                cache.insert(key, &pdf_bytes);
+            }
+            Ok(pdf_bytes)
+        })
+        .await
+}
+
+/// Runs the full markdown to PDF pipeline for a validated request, logging the outcome
+async fn render_pdf(
+    request: &MarkdownRequest,
+    backend: &dyn PdfBackend,
+    max_pages: Option<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    let started = Instant::now();
+    let result = convert_document(request, backend, max_pages).await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    match &result {
+        Ok(pdf_bytes) => info!(
+            markdown_bytes = request
+                .markdown_sources()
+                .iter()
+                .map(|m| m.len())
+                .sum::<usize>(),
+            pdf_bytes = pdf_bytes.len(),
+            duration_ms,
+            "Converted markdown to PDF"
+        ),
+        Err(e) => error!(
+            markdown_bytes = request
+                .markdown_sources()
+                .iter()
+                .map(|m| m.len())
+                .sum::<usize>(),
+            duration_ms,
+            error = format!("{:#}", e),
+            "Failed to convert markdown to PDF"
+        ),
+    }
+
+    result
+}
+
+/// Builds the complete HTML document that is handed to the PDF backend
+async fn render_html(request: &MarkdownRequest) -> String {
+    let html = if request.full_document {
+        request.markdown().to_string()
+    } else {
+        let font_css = match &request.fonts {
+            Some(fonts) => fonts::font_face_css(fonts).await,
+            None => String::new(),
+        };
+        markdown_to_html_converter(request, &font_css)
+    };
+
+    // Embed images so the renderer does not need network or file access to load them
+    let html = if request.inline_images {
+        images::inline_images(&html, request.base_dir.as_deref()).await
+    } else {
+        html
+    };
+
+    let html = if request.render_mermaid {
+        mermaid::render_diagrams(&html).await
+    } else {
+        html
+    };
+
+    if request.render_math {
+        math::render_formulas(&html).await
+    } else {
+        html
+    }
+}
+
+/// Converts the request markdown to HTML, renders it and applies PDF post-processing
+async fn convert_document(
+    request: &MarkdownRequest,
+    backend: &dyn PdfBackend,
+    max_pages: Option<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    // Convert markdown to HTML
+    let html = render_html(request).await;
+    render_document(&html, request, backend, max_pages).await
+}
+
+/// Renders the HTML with the backend and applies PDF post-processing
+async fn render_document(
+    html: &str,
+    request: &MarkdownRequest,
+    backend: &dyn PdfBackend,
+    max_pages: Option<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    // Convert HTML to PDF
+    let pdf_bytes = backend.render(html, request).await?;
+
+    // Add cover pages and attachments before the metadata and encryption cover all pages
+    let pdf_bytes = match (&request.prepend_pdf_base64, &request.append_pdf_base64) {
+        (None, None) => pdf_bytes,
+        (prepend, append) => {
+            let decode = |pdf: &Option<String>| pdf.as_deref().map(|pdf| STANDARD.decode(pdf));
+            let prepend = decode(prepend).transpose()?;
+            let append = decode(append).transpose()?;
+            pdf::merge(&pdf_bytes, prepend.as_deref(), append.as_deref())?
+        }
+    };
+
+    // Grow the pages after merging so attached pages get crop marks as well
+    let pdf_bytes = if request.print_ready {
+        pdf::add_print_marks(&pdf_bytes, length_to_points(request.bleed()))?
+    } else {
+        pdf_bytes
+    };
+
+    // wkhtmltopdf draws its own pages in gray, but merged PDFs keep their colours, so those
+    // and backends without grayscale support go through Ghostscript
+    let attaches_pdfs = request.prepend_pdf_base64.is_some() || request.append_pdf_base64.is_some();
+    let pdf_bytes = if request.grayscale && (attaches_pdfs || !backend.renders_grayscale()) {
+        pdfa::grayscale(&pdf_bytes).await?
+    } else {
+        pdf_bytes
+    };
+
+    // Counted once all pages are in place, but before encryption makes the PDF harder to read
+    if let Some(max_pages) = max_pages {
+        pdf::check_page_limit(&pdf_bytes, max_pages)?;
+    }
+
+    // Write any requested metadata into the PDF Info dictionary
+    let metadata = request.document_metadata();
+    let pdf_bytes = match metadata.info_entries() {
+        entries if entries.is_empty() => pdf_bytes,
+        entries => pdf::set_info(&pdf_bytes, &entries)?,
+    };
+
+    // Convert after the metadata is written, since Ghostscript carries it into the XMP packet
+    let pdf_bytes = if request.pdf_a {
+        pdfa::convert(&pdf_bytes).await?
+    } else {
+        pdf_bytes
+    };
+
+    // Encrypt last so the metadata is protected as well
+    match &request.encryption {
+        Some(encryption) => encryption.apply(&pdf_bytes),
+        None => Ok(pdf_bytes),
+    }
+}
+
+/// Maps a conversion failure to an HTTP response
+fn conversion_error_response(e: anyhow::Error) -> HttpResponse {
+    match e.downcast_ref::<SharedFailure>() {
+        // Requests that shared a conversion answer as the one that ran it
+        Some(SharedFailure(shared)) => failure_response(shared),
+        None => failure_response(&e),
+    }
+}
+
+fn failure_response(e: &anyhow::Error) -> HttpResponse {
+    if e.downcast_ref::<ConversionTimeout>().is_some() {
+        HttpResponse::GatewayTimeout().json(ErrorResponse::new(e.to_string()))
+    } else if let Some(timeout) = e.downcast_ref::<QueueTimeout>() {
+        HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, timeout.retry_after.as_secs()))
+            .json(ErrorResponse::new(e.to_string()))
+    } else if e.downcast_ref::<SourceNotAllowed>().is_some()
+        || e.downcast_ref::<UploadNotAllowed>().is_some()
+        || e.downcast_ref::<InvalidArchive>().is_some()
+    {
+        HttpResponse::BadRequest().json(ErrorResponse::new(e.to_string()))
+    } else if e.downcast_ref::<SourceFetchFailed>().is_some()
+        || e.downcast_ref::<UploadFailed>().is_some()
+    {
+        HttpResponse::BadGateway().json(ErrorResponse::new(e.to_string()))
+    } else if e.downcast_ref::<Unsupported>().is_some()
+        || e.downcast_ref::<GhostscriptMissing>().is_some()
+    {
+        HttpResponse::NotImplemented().json(ErrorResponse::new(e.to_string()))
+    } else if let Some(limit) = e.downcast_ref::<PageLimitExceeded>() {
+        HttpResponse::UnprocessableEntity().json(PageLimitResponse {
+            error: e.to_string(),
+            page_count: limit.page_count,
+            max_pages: limit.max_pages,
+        })
+    } else if let Some(failure) = e.downcast_ref::<RenderFailed>() {
+        HttpResponse::InternalServerError().json(ErrorResponse::with_detail(
+            "PDF rendering failed",
+            failure.detail.clone(),
+        ))
+    } else {
+        HttpResponse::InternalServerError().json(ErrorResponse::with_detail(
+            "PDF conversion failed",
+            redact_paths(&format!("{:#}", e)),
+        ))
+    }
+}
+
+/// Downloads the markdown for requests that name a `source_url` instead of sending content
+async fn resolve_source(request: &mut MarkdownRequest, config: &Config) -> anyhow::Result<()> {
+    if let Some(url) = &request.source_url {
+        let markdown = source::fetch_markdown(
+            url,
+            config.source_url_allowed_hosts.as_deref(),
+            config.max_markdown_bytes,
+        )
+        .await?;
+        request.markdown = Some(markdown);
+    }
+    Ok(())
+}
+
+/// Checks the request's `upload_url` against `UPLOAD_URL_ALLOWED_HOSTS`
+fn check_upload_url(request: &MarkdownRequest, config: &Config) -> anyhow::Result<()> {
+    if let Some(url) = &request.upload_url {
+        destination::check(url, config.upload_url_allowed_hosts.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Rejects `upload_url` on routes that answer with something other than the PDF itself
+fn forbid_upload_url(request: &MarkdownRequest) -> Result<(), String> {
+    match request.upload_url {
+        Some(_) => Err(format!(
+            "upload_url is only supported by {}",
+            UPLOAD_URL_ROUTES
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns the ETag for the PDF a request produces, unless it would differ between runs
+fn document_etag(request: &MarkdownRequest, backend: &dyn PdfBackend) -> Option<EntityTag> {
+    // The footer shows the time of conversion, so no two conversions are equivalent, and
+    // an upload has to happen even when the client has seen the PDF before
+    (!request.footer_timestamp && request.upload_url.is_none())
+        .then(|| PdfCache::entity_tag(request, backend))
+}
+
+/// Answers with `304 Not Modified` when the client already has the PDF the request produces
+fn not_modified(
+    req: &HttpRequest,
+    request: &MarkdownRequest,
+    etag: Option<&EntityTag>,
+) -> Option<HttpResponse> {
+    let etag = etag?;
+    let matches = match req.get_header::<IfNoneMatch>()? {
+        IfNoneMatch::Any => true,
+        IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+    };
+    matches.then(|| {
+        // The response must repeat caching headers the full response would have carried
+        let mut response = HttpResponse::NotModified();
+        response.insert_header(ETag(etag.clone()));
+        insert_response_headers(&mut response, request);
+        response.finish()
+    })
+}
+
+/// Handles the POST request to convert markdown to PDF
+async fn convert_markdown_to_pdf(
+    req: HttpRequest,
+    mut payload: web::Json<MarkdownRequest>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+) -> Result<HttpResponse> {
+    let Some(response_type) = negotiate_response_type(&req) else {
+        return Ok(not_acceptable(&req));
+    };
+
+    // Reject unsupported rendering options
+    if let Err(message) = payload.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+    if let Err(e) = check_upload_url(&payload, &config) {
+        return Ok(conversion_error_response(e));
+    }
+
+    // The base64 wrapper is not cached by clients, so only the PDF gets an ETag
+    let etag = match response_type {
+        ResponseType::Pdf => document_etag(&payload, backend.get_ref()),
+        ResponseType::Json => None,
+    };
+    if let Some(response) = not_modified(&req, &payload, etag.as_ref()) {
+        return Ok(response);
+    }
+
+    let render = convert_shared(
+        &payload,
+        &cache,
+        &limiter,
+        metrics.track(render_pdf(&payload, backend.get_ref(), config.max_pages)),
+    );
+    let mut response = match response_type {
+        ResponseType::Json if payload.upload_url.is_none() => {
+            match backend::collect_warnings(render).await {
+                (Ok(pdf_bytes), warnings) => pdf_json_response(&payload, pdf_bytes, warnings),
+                (Err(e), _) => conversion_error_response(e),
+            }
+        }
+        // Uploads are answered with their location whichever type was negotiated
+        _ => match render.await {
+            Ok(pdf_bytes) => deliver_pdf(&payload, etag, pdf_bytes).await,
+            Err(e) => conversion_error_response(e),
+        },
+    };
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("Accept"));
+    Ok(response)
 }
 
-#[derive(Debug, Serialize)]
-struct HealthResponse {
-    status: String,
-    version: String,
+/// Representations `/convert` can answer with
+#[derive(Debug, PartialEq)]
+enum ResponseType {
+    Pdf,
+    Json,
 }
 
-/// Converts markdown text to HTML using comrak
-fn markdown_to_html_converter(markdown: &str) -> String {
-    let options = ComrakOptions::default();
-    let content = markdown_to_html(markdown, &options);
+/// Picks the `/convert` response type from the Accept header.
+///
+/// The PDF is returned when the header is missing, cannot be parsed or accepts anything;
+/// `None` means none of the accepted types can be produced.
+fn negotiate_response_type(req: &HttpRequest) -> Option<ResponseType> {
+    let Some(header::Accept(mut accepted)) = req.get_header::<header::Accept>() else {
+        return Some(ResponseType::Pdf);
+    };
+    if accepted.is_empty() {
+        return Some(ResponseType::Pdf);
+    }
+    accepted.retain(|item| item.quality > Quality::ZERO);
+    // Stable, so types with the same quality keep the client's order
+    accepted.sort_by_key(|item| std::cmp::Reverse(item.quality));
+    accepted
+        .iter()
+        .find_map(|item| match item.item.essence_str() {
+            "application/pdf" | "application/*" | "*/*" => Some(ResponseType::Pdf),
+            "application/json" => Some(ResponseType::Json),
+            _ => None,
+        })
+}
 
-    format!(
-        r#"<!DOCTYPE html>
-<html>
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Document</title>
-    <style>
-        @page {{
-            size: A4;
-            margin: 10mm;
-        }}
-        html {{
-            font-size: 16pt !important;
-            width: 210mm;  /* A4 width */
-        }}
-        body {{
-            font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, "Helvetica Neue", Arial, sans-serif;
-            line-height: 1.6;
-            padding: 0 5em;
-            font-size: 1rem !important;
-            width: 100%;
-            margin: 0;
-            overflow-wrap: break-word;
-            word-wrap: break-word;
-            word-break: break-word;
-        }}
-        /* Force consistent sizes */
-        p, div, span, li, td {{
-            font-size: 1rem !important;
-        }}
-        h1 {{ font-size: 1.4rem !important; }}
-        h2 {{ font-size: 1.2rem !important; }}
-        h3 {{ font-size: 1.1rem !important; }}
-        h4, h5, h6 {{ font-size: 1.1rem !important; }}
-        /* Handle long URLs */
-        a {{
-            word-wrap: break-word;
-            word-break: break-all;
-            white-space: pre-wrap;
-            overflow-wrap: break-word;
-            max-width: 100%;
-            display: inline-block;
-        }}
-    </style>
-</head>
-<body>
-    {}
-</body>
-</html>"#,
-        content
-    )
+/// Tells clients which response types `/convert` can produce
+fn not_acceptable(req: &HttpRequest) -> HttpResponse {
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("none");
+    HttpResponse::NotAcceptable().json(ErrorResponse::new(format!(
+        "Unsupported Accept header '{}'. This endpoint produces one of: {}",
+        accept,
+        CONVERT_RESPONSE_TYPES.join(", ")
+    )))
 }
 
-/// Creates a temporary file with the given content and returns its path
-fn create_temp_file(content: &str, extension: &str) -> anyhow::Result<PathBuf> {
-    let temp_dir = std::env::temp_dir();
-    let file_name = format!("{}.{}", Uuid::new_v4(), extension);
-    let file_path = temp_dir.join(file_name);
-
-    fs::write(&file_path, content)?;
-    Ok(file_path)
-}
-
-/// Converts HTML to PDF using wkhtmltopdf command line tool
-async fn html_to_pdf(html: &str) -> anyhow::Result<Vec<u8>> {
-    // Create temporary HTML file
-    let html_path =
-        create_temp_file(html, "html").context("Failed to create temporary HTML file")?;
-
-    // Create temporary PDF file path
-    let pdf_path = html_path.with_extension("pdf");
-
-    // Run wkhtmltopdf with margin settings
-    let output = Command::new("wkhtmltopdf")
-        .arg("--page-size")
-        .arg("A4")
-        .arg("--dpi")
-        .arg("96")
-        .arg("--margin-top")
-        .arg("20mm")
-        .arg("--margin-bottom")
-        .arg("20mm")
-        .arg("--disable-smart-shrinking")
-        .arg("--enable-local-file-access")
-        .arg("--zoom")
-        .arg("1.0")
-        .arg("--print-media-type")
-        .arg("--no-background")
-        .arg(&html_path)
-        .arg(&pdf_path)
-        .output()
-        .context("Failed to execute wkhtmltopdf")?;
-
-    if !output.status.success() {
-        return Err(anyhow::anyhow!(
-            "wkhtmltopdf failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+/// Builds the `200 OK` response carrying a rendered PDF
+fn pdf_response(
+    request: &MarkdownRequest,
+    etag: Option<EntityTag>,
+    pdf_bytes: Vec<u8>,
+) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("application/pdf")
+        .insert_header(content_disposition(&request.filename()));
+    if let Some(etag) = etag {
+        response.insert_header(ETag(etag));
+    }
+    insert_response_headers(&mut response, request);
+    response.body(pdf_bytes)
+}
+
+/// Adds the request's validated `response_headers`
+fn insert_response_headers(response: &mut HttpResponseBuilder, request: &MarkdownRequest) {
+    for (name, value) in request.response_headers.iter().flatten() {
+        response.insert_header((name.as_str(), value.as_str()));
+    }
+}
+
+/// Handles the POST request to convert an uploaded markdown file to PDF
+async fn convert_markdown_upload(
+    req: HttpRequest,
+    multipart: Multipart,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+) -> Result<HttpResponse> {
+    let mut request = match upload::read_request(multipart, config.max_markdown_bytes).await {
+        Ok(request) => request,
+        Err(UploadError::Invalid(message)) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)))
+        }
+        Err(UploadError::TooLarge) => {
+            return Ok(
+                HttpResponse::PayloadTooLarge().json(ErrorResponse::new(format!(
+                    "Request body exceeds the {} byte limit set by MAX_MARKDOWN_BYTES",
+                    config.max_markdown_bytes
+                ))),
+            )
+        }
+    };
+
+    // Reject unsupported rendering options
+    if let Err(message) = request.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
     }
 
-    // Read the generated PDF
-    let pdf_content = fs::read(&pdf_path).context("Failed to read generated PDF")?;
+    if let Err(e) = resolve_source(&mut request, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+    if let Err(e) = check_upload_url(&request, &config) {
+        return Ok(conversion_error_response(e));
+    }
 
-    // Clean up temporary files
-    let _ = fs::remove_file(html_path);
-    let _ = fs::remove_file(pdf_path);
+    let etag = document_etag(&request, backend.get_ref());
+    if let Some(response) = not_modified(&req, &request, etag.as_ref()) {
+        return Ok(response);
+    }
 
-    Ok(pdf_content)
+    let render = convert_shared(
+        &request,
+        &cache,
+        &limiter,
+        metrics.track(render_pdf(&request, backend.get_ref(), config.max_pages)),
+    );
+    match render.await {
+        Ok(pdf_bytes) => Ok(deliver_pdf(&request, etag, pdf_bytes).await),
+        Err(e) => Ok(conversion_error_response(e)),
+    }
 }
 
-/// Handles the POST request to convert markdown to PDF
-async fn convert_markdown_to_pdf(payload: web::Json<MarkdownRequest>) -> Result<HttpResponse> {
-    // Convert markdown to HTML
-    let html = markdown_to_html_converter(&payload.markdown);
+/// Handles the POST request to convert a zip of an `index.md` and its assets to PDF
+async fn convert_markdown_archive(
+    multipart: Multipart,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+) -> Result<HttpResponse> {
+    let (archive, options) =
+        match upload::read_archive_form(multipart, config.max_markdown_bytes).await {
+            Ok(form) => form,
+            Err(UploadError::Invalid(message)) => {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)))
+            }
+            Err(UploadError::TooLarge) => {
+                return Ok(
+                    HttpResponse::PayloadTooLarge().json(ErrorResponse::new(format!(
+                        "Request body exceeds the {} byte limit set by MAX_MARKDOWN_BYTES",
+                        config.max_markdown_bytes
+                    ))),
+                )
+            }
+        };
 
-    // Convert HTML to PDF
-    match html_to_pdf(&html).await {
-        Ok(pdf_bytes) => Ok(HttpResponse::Ok()
+    // Removed again when this handler returns, however the conversion ends
+    let mut extracted = match archive::extract(&archive, &config.temp_dir) {
+        Ok(extracted) => extracted,
+        Err(e) => return Ok(conversion_error_response(e)),
+    };
+    let markdown = std::mem::take(&mut extracted.markdown);
+    let mut request = match upload::form_request(options, markdown) {
+        Ok(request) => request,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+    request.base_dir = Some(extracted.dir().to_path_buf());
+
+    // Reject unsupported rendering options
+    if let Err(message) = request.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+    if let Err(e) = check_upload_url(&request, &config) {
+        return Ok(conversion_error_response(e));
+    }
+
+    let render = convert_shared(
+        &request,
+        &cache,
+        &limiter,
+        metrics.track(render_pdf(&request, backend.get_ref(), config.max_pages)),
+    );
+    match render.await {
+        Ok(pdf_bytes) => Ok(deliver_pdf(&request, None, pdf_bytes).await),
+        Err(e) => Ok(conversion_error_response(e)),
+    }
+}
+
+/// Handles the POST request to convert a raw markdown body to PDF, with options in the query
+async fn convert_markdown_text(
+    req: HttpRequest,
+    payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+) -> Result<HttpResponse> {
+    let content_type = req.get_header::<header::ContentType>();
+    let is_text = content_type.as_ref().is_some_and(|content_type| {
+        TEXT_CONTENT_TYPES.contains(&content_type.essence_str().to_ascii_lowercase().as_str())
+    });
+    if !is_text {
+        return Ok(
+            HttpResponse::UnsupportedMediaType().json(ErrorResponse::with_detail(
+                format!(
+                    "Unsupported Content-Type '{}'. This endpoint expects one of: {}",
+                    content_type.map_or("none".to_string(), |c| c.to_string()),
+                    TEXT_CONTENT_TYPES.join(", ")
+                ),
+                "To send markdown inside a JSON body, POST it to /convert",
+            )),
+        );
+    }
+
+    let query = match web::Query::<Vec<(String, String)>>::from_query(req.query_string()) {
+        Ok(query) => query.into_inner(),
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::with_detail(
+                "Invalid query string",
+                e.to_string(),
+            )))
+        }
+    };
+    let mut request =
+        match upload::read_text_request(payload, query, config.max_markdown_bytes).await {
+            Ok(request) => request,
+            Err(UploadError::Invalid(message)) => {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)))
+            }
+            Err(UploadError::TooLarge) => {
+                return Ok(
+                    HttpResponse::PayloadTooLarge().json(ErrorResponse::new(format!(
+                        "Request body exceeds the {} byte limit set by MAX_MARKDOWN_BYTES",
+                        config.max_markdown_bytes
+                    ))),
+                )
+            }
+        };
+
+    // Reject unsupported rendering options
+    if let Err(message) = request.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    if let Err(e) = resolve_source(&mut request, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+    if let Err(e) = check_upload_url(&request, &config) {
+        return Ok(conversion_error_response(e));
+    }
+
+    let etag = document_etag(&request, backend.get_ref());
+    if let Some(response) = not_modified(&req, &request, etag.as_ref()) {
+        return Ok(response);
+    }
+
+    let render = convert_shared(
+        &request,
+        &cache,
+        &limiter,
+        metrics.track(render_pdf(&request, backend.get_ref(), config.max_pages)),
+    );
+    match render.await {
+        Ok(pdf_bytes) => Ok(deliver_pdf(&request, etag, pdf_bytes).await),
+        Err(e) => Ok(conversion_error_response(e)),
+    }
+}
+
+/// Handles the POST request to convert markdown to a base64-encoded PDF wrapped in JSON
+async fn convert_markdown_to_json(
+    mut payload: web::Json<MarkdownRequest>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+) -> Result<HttpResponse> {
+    // Reject unsupported rendering options
+    if let Err(message) = payload
+        .validate()
+        .and_then(|()| forbid_upload_url(&payload))
+    {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+
+    let render = convert_shared(
+        &payload,
+        &cache,
+        &limiter,
+        metrics.track(render_pdf(&payload, backend.get_ref(), config.max_pages)),
+    );
+    match backend::collect_warnings(render).await {
+        (Ok(pdf_bytes), warnings) => Ok(pdf_json_response(&payload, pdf_bytes, warnings)),
+        (Err(e), _) => Ok(conversion_error_response(e)),
+    }
+}
+
+/// Counts the pages of a rendered PDF, which is encrypted when the request asked for it
+fn pdf_page_count(request: &MarkdownRequest, pdf_bytes: &[u8]) -> anyhow::Result<usize> {
+    let password = request
+        .encryption
+        .as_ref()
+        .map(|e| e.user_password.as_str());
+    pdf::page_count(pdf_bytes, password)
+}
+
+/// Answers with the PDF, or uploads it to the request's `upload_url` and answers with where
+/// it was stored
+async fn deliver_pdf(
+    request: &MarkdownRequest,
+    etag: Option<EntityTag>,
+    pdf_bytes: Vec<u8>,
+) -> HttpResponse {
+    let Some(upload_url) = &request.upload_url else {
+        return pdf_response(request, etag, pdf_bytes);
+    };
+    let page_count = match pdf_page_count(request, &pdf_bytes) {
+        Ok(page_count) => page_count,
+        Err(e) => return conversion_error_response(e),
+    };
+    let size_bytes = pdf_bytes.len();
+    match destination::put(upload_url, pdf_bytes).await {
+        Ok(location) => HttpResponse::Ok().json(UploadResponse {
+            location,
+            size_bytes,
+            page_count,
+        }),
+        Err(e) => conversion_error_response(e),
+    }
+}
+
+/// Builds the `200 OK` response carrying a rendered PDF as base64 with its page count
+fn pdf_json_response(
+    request: &MarkdownRequest,
+    pdf_bytes: Vec<u8>,
+    warnings: Vec<String>,
+) -> HttpResponse {
+    let page_count = match pdf_page_count(request, &pdf_bytes) {
+        Ok(page_count) => page_count,
+        Err(e) => return conversion_error_response(e),
+    };
+    HttpResponse::Ok().json(PdfJsonResponse {
+        pdf_base64: STANDARD.encode(&pdf_bytes),
+        size_bytes: pdf_bytes.len(),
+        page_count,
+        warnings,
+    })
+}
+
+/// Handles the POST request to convert markdown to PDF in a background job.
+///
+/// The request is validated and its source fetched before answering `202 Accepted`, so
+/// invalid requests still fail immediately.
+async fn convert_markdown_async(
+    mut payload: web::Json<MarkdownRequest>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+    jobs: web::Data<JobStore>,
+) -> Result<HttpResponse> {
+    // Reject unsupported rendering options
+    if let Err(message) = payload
+        .validate()
+        .and_then(|()| forbid_upload_url(&payload))
+    {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+
+    let job_id = jobs.create();
+    let request = payload.into_inner();
+    let background_jobs = jobs.clone();
+    actix_web::rt::spawn(async move {
+        let result = convert_shared(
+            &request,
+            &cache,
+            &limiter,
+            metrics.track(render_pdf(&request, backend.get_ref(), config.max_pages)),
+        )
+        .await;
+
+        let outcome = match result {
+            Ok(pdf_bytes) => JobOutcome::Done {
+                pdf_bytes: pdf_bytes.into(),
+                filename: request.filename(),
+            },
+            Err(e) => {
+                let body =
+                    actix_web::body::to_bytes(conversion_error_response(e).into_body()).await;
+                JobOutcome::Failed(
+                    body.ok()
+                        .and_then(|body| serde_json::from_slice(&body).ok())
+                        .unwrap_or_default(),
+                )
+            }
+        };
+        background_jobs.finish(job_id, outcome);
+    });
+
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/jobs/{}", job_id)))
+        .json(JobResponse {
+            job_id: job_id.to_string(),
+            status: JobStatus::Pending,
+            error: None,
+        }))
+}
+
+/// Handles the GET request for the status of a background job, returning the PDF once done
+async fn job_status(path: web::Path<String>, jobs: web::Data<JobStore>) -> Result<HttpResponse> {
+    let id = path.into_inner();
+    let Some(outcome) = Uuid::parse_str(&id)
+        .ok()
+        .and_then(|job_id| jobs.get(job_id))
+    else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new(format!(
+            "Job '{}' not found. Finished jobs are kept for a limited time",
+            id
+        ))));
+    };
+
+    let status = outcome.status();
+    match outcome {
+        JobOutcome::Done {
+            pdf_bytes,
+            filename,
+        } => Ok(HttpResponse::Ok()
             .content_type("application/pdf")
-            .append_header((
-                "Content-Disposition",
-                "attachment; filename=\"document.pdf\"",
-            ))
+            .insert_header(content_disposition(&filename))
             .body(pdf_bytes)),
+        JobOutcome::Pending => Ok(HttpResponse::Ok().json(JobResponse {
+            job_id: id,
+            status,
+            error: None,
+        })),
+        JobOutcome::Failed(error) => Ok(HttpResponse::Ok().json(JobResponse {
+            job_id: id,
+            status,
+            error: Some(error),
+        })),
+    }
+}
+
+/// Query parameters of `/convert/png`
+#[derive(Debug, Deserialize)]
+struct PreviewQuery {
+    /// Width of the image in pixels
+    width: Option<u32>,
+}
+
+/// Handles the POST request to render the first page of the document as a PNG preview
+async fn convert_markdown_to_png(
+    mut payload: web::Json<MarkdownRequest>,
+    query: web::Query<PreviewQuery>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse> {
+    // Reject unsupported rendering options
+    if let Err(message) = payload
+        .validate()
+        .and_then(|()| forbid_upload_url(&payload))
+    {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    let width = query.width.unwrap_or(DEFAULT_PREVIEW_WIDTH);
+    if !PREVIEW_WIDTH_RANGE.contains(&width) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "Invalid width {}. Must be between {} and {}",
+            width,
+            PREVIEW_WIDTH_RANGE.start(),
+            PREVIEW_WIDTH_RANGE.end()
+        ))));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+
+    // Wait for a free conversion slot
+    let _permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(e) => return Ok(conversion_error_response(e.into())),
+    };
+
+    let render = async {
+        let html = render_html(&payload).await;
+        backend.render_png(&html, &payload, width).await
+    };
+    match metrics.track(render).await {
+        Ok(png_bytes) => Ok(HttpResponse::Ok().content_type("image/png").body(png_bytes)),
+        Err(e) => Ok(conversion_error_response(e)),
+    }
+}
+
+/// Handles the POST request to return the intermediate HTML without rendering a PDF
+async fn convert_markdown_to_html(
+    mut payload: web::Json<MarkdownRequest>,
+    config: web::Data<Config>,
+    limiter: web::Data<ConversionLimiter>,
+) -> Result<HttpResponse> {
+    // Reject unsupported rendering options
+    if let Err(message) = payload.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+
+    // Wait for a free conversion slot
+    let _permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(e) => return Ok(conversion_error_response(e.into())),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_html(&payload).await))
+}
+
+/// Handles the POST request to check the markdown for likely mistakes without rendering it
+async fn validate_markdown(
+    mut payload: web::Json<MarkdownRequest>,
+    config: web::Data<Config>,
+    limiter: web::Data<ConversionLimiter>,
+) -> Result<HttpResponse> {
+    // Unusable options are errors; problems in the markdown itself are only warnings
+    if let Err(message) = payload.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+
+    // Wait for a free conversion slot
+    let _permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(e) => return Ok(conversion_error_response(e.into())),
+    };
+
+    let mut warnings = Vec::new();
+    if payload.input_format() != "html" {
+        let options = payload.comrak_options();
+        let sources = payload.markdown_sources();
+        for (index, source) in sources.iter().enumerate() {
+            let section = payload.sections.is_some().then_some(index + 1);
+            warnings.extend(
+                lint::check(source, &options)
+                    .into_iter()
+                    .map(|warning| lint::Warning { section, ..warning }),
+            );
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(ValidationResponse {
+        valid: true,
+        warnings,
+    }))
+}
+
+/// Handles the POST request to predict the size of a conversion without rendering it
+async fn estimate_conversion(
+    mut payload: web::Json<MarkdownRequest>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    if let Err(message) = payload.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+    if payload.input_format() == "html" {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(
+            "Estimates are only supported for markdown input".to_string(),
+        )));
+    }
+
+    if let Err(e) = resolve_source(&mut payload, &config).await {
+        return Ok(conversion_error_response(e));
+    }
+
+    // Blank sections are skipped when rendering, so they add no pages
+    let mut sources = payload.markdown_sources();
+    if payload.sections.is_some() {
+        sources.retain(|source| !source.trim().is_empty());
+    }
+    let estimate = estimate::estimate(&sources, &payload.comrak_options(), payload.text_layout());
+    let exceeds_max_pages = config
+        .max_pages
+        .map(|max_pages| estimate.pages.min > max_pages);
+
+    Ok(HttpResponse::Ok().json(EstimateResponse {
+        estimate,
+        exceeds_max_pages,
+    }))
+}
+
+/// Handles the POST request to convert several markdown documents into a zip of PDFs
+async fn convert_markdown_batch(
+    mut payload: web::Json<BatchRequest>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn PdfBackend>,
+    limiter: web::Data<ConversionLimiter>,
+    metrics: web::Data<Metrics>,
+    cache: web::Data<PdfCache>,
+) -> Result<HttpResponse> {
+    if payload.documents.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(
+            "documents must contain at least one document".to_string(),
+        )));
+    }
+    if payload.documents.len() > config.max_batch_documents {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "documents may contain at most {} documents, got {}",
+            config.max_batch_documents,
+            payload.documents.len()
+        ))));
+    }
+
+    // Sources are downloaded up front, so fetching does not hold a conversion slot
+    let prepared = join_all(payload.documents.iter_mut().map(|document| async {
+        document
+            .request
+            .validate()
+            .and_then(|()| forbid_upload_url(&document.request))
+            .map_err(anyhow::Error::msg)?;
+        resolve_source(&mut document.request, &config).await
+    }))
+    .await;
+
+    // At most as many documents are in flight as there are conversion slots, so a large
+    // batch does not queue its tail behind the queue timeout. Every document still waits for
+    // its own slot, so the global concurrency limit applies across requests, unless an
+    // identical document in this or another request is converting already
+    let max_pages = config.max_pages;
+    let documents = payload.documents.iter().zip(prepared).enumerate();
+    let mut results: Vec<_> = stream::iter(documents.map(|(index, (document, prepared))| {
+        let limiter = &limiter;
+        let metrics = &metrics;
+        let backend = &backend;
+        let cache = &cache;
+        async move {
+            let result = async {
+                prepared?;
+                convert_shared(
+                    &document.request,
+                    cache,
+                    limiter,
+                    metrics.track(render_pdf(&document.request, backend.get_ref(), max_pages)),
+                )
+                .await
+            }
+            .await;
+            (index, result)
+        }
+    }))
+    .buffer_unordered(config.max_concurrent_conversions)
+    .collect()
+    .await;
+    // Documents finish in any order; the archive lists them in request order
+    results.sort_by_key(|(index, _)| *index);
+    let results = results.into_iter().map(|(_, result)| result).collect();
+
+    match batch::write_zip(&payload.documents, results) {
+        Ok(zip_bytes) => Ok(HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header(ContentDisposition::attachment("documents.zip"))
+            .body(zip_bytes)),
+        Err(e) => Ok(conversion_error_response(e)),
+    }
+}
+
+/// Serves the OpenAPI description of the API
+async fn openapi_spec() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(&*openapi::SPEC))
+}
+
+/// Lists the supported option values and their defaults
+async fn capabilities_endpoint(backend: web::Data<dyn PdfBackend>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(capabilities::describe(backend.name())))
+}
+
+/// Exposes conversion metrics in the Prometheus text format
+async fn metrics_endpoint(metrics: web::Data<Metrics>) -> Result<HttpResponse> {
+    match metrics.encode() {
+        Ok((content_type, body)) => Ok(HttpResponse::Ok().content_type(content_type).body(body)),
         Err(e) => {
-            eprintln!("Error converting to PDF: {}", e);
+            error!(error = %e, "Failed to encode metrics");
             Ok(HttpResponse::InternalServerError().finish())
         }
     }
 }
 
-/// Health check endpoint that verifies the service and its dependencies are working
-async fn health_check() -> Result<HttpResponse> {
-    // Check if wkhtmltopdf is available
-    match Command::new("wkhtmltopdf").arg("--version").output() {
-        Ok(_) => Ok(HttpResponse::Ok().json(HealthResponse {
+/// Liveness probe that only shows the server is answering requests, without checking the backend
+async fn liveness_check() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(LivenessResponse {
+        status: "alive",
+        version: env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+/// Readiness check that verifies the service and its dependencies are working; also served as
+/// `/health`
+async fn health_check(backend: web::Data<dyn PdfBackend>) -> Result<HttpResponse> {
+    // Check if the backend's dependencies are available
+    match backend.check() {
+        Ok(()) => Ok(HttpResponse::Ok().json(HealthResponse {
             status: "healthy".to_string(),
             version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: backend.name().to_string(),
+            backend_version: backend.version(),
+            pdf_a_available: pdfa::is_available(),
         })),
-        Err(_) => Ok(HttpResponse::ServiceUnavailable().json(HealthResponse {
-            status: "unhealthy - wkhtmltopdf not found".to_string(),
+        Err(reason) => Ok(HttpResponse::ServiceUnavailable().json(HealthResponse {
+            status: format!("unhealthy - {}", reason),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: backend.name().to_string(),
+            backend_version: backend.version(),
+            pdf_a_available: pdfa::is_available(),
+        })),
+    }
+}
+
+/// Health check that performs a real conversion to catch backends that start but cannot render
+async fn deep_health_check(backend: web::Data<dyn PdfBackend>) -> Result<HttpResponse> {
+    let request = MarkdownRequest {
+        markdown: Some("# Health check".to_string()),
+        ..Default::default()
+    };
+
+    let outcome = match convert_document(&request, backend.get_ref(), None).await {
+        Ok(pdf_bytes) if pdf_bytes.starts_with(b"%PDF") => Ok(()),
+        Ok(pdf_bytes) if pdf_bytes.is_empty() => Err("backend produced an empty file".to_string()),
+        Ok(_) => Err("backend output is not a PDF".to_string()),
+        Err(e) => Err(format!("{:#}", e)),
+    };
+
+    match outcome {
+        Ok(()) => Ok(HttpResponse::Ok().json(HealthResponse {
+            status: "healthy".to_string(),
             version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: backend.name().to_string(),
+            backend_version: backend.version(),
+            pdf_a_available: pdfa::is_available(),
         })),
+        Err(reason) => {
+            error!(error = %reason, "Deep health check failed");
+            Ok(HttpResponse::ServiceUnavailable().json(HealthResponse {
+                status: format!("unhealthy - {}", reason),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                backend: backend.name().to_string(),
+                backend_version: backend.version(),
+                pdf_a_available: pdfa::is_available(),
+            }))
+        }
     }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Check if wkhtmltopdf is installed
-    if let Err(_) = Command::new("wkhtmltopdf").arg("--version").output() {
-        eprintln!("Error: wkhtmltopdf is not installed. Please install it first.");
+    let cli = Cli::parse();
+
+    // Log at info level unless RUST_LOG says otherwise; one-off conversions only log problems
+    let default_level = match cli.command {
+        Some(Command::Convert(_) | Command::Selftest) => "warn",
+        _ => "info",
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level)),
+        )
+        .init();
+
+    let config = Config::from_env().unwrap_or_else(|e| {
+        error!("{}", e);
         std::process::exit(1);
+    });
+
+    if let Some(html_template) = &config.html_template {
+        template::install(html_template.clone());
+    }
+    if let Some(path) = &config.default_css_path {
+        match template::load_css(path) {
+            Ok(css) => {
+                info!(
+                    path = %path.display(),
+                    bytes = css.len(),
+                    "Applying the DEFAULT_CSS_PATH stylesheet to every document"
+                );
+                template::install_css(css);
+            }
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
     }
 
-    println!(
-        "Starting rust-md-to-pdf v{} at http://0.0.0.0:8080",
-        env!("CARGO_PKG_VERSION")
-    );
+    if let Err(e) = config::prepare_temp_dir(&config.temp_dir) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    mermaid::install_temp_dir(config.temp_dir.clone());
+
+    let backend = backend::from_config(&config).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+
+    match cli.command {
+        Some(Command::Convert(args)) => match cli::convert(args, backend.as_ref()).await {
+            Ok(output) => {
+                println!("Wrote {}", output.display());
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Selftest) => match cli::selftest(backend.as_ref()).await {
+            Ok(summary) => {
+                println!("{}", summary);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Self-test failed: {:#}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Serve) | None => serve(config, backend).await,
+    }
+}
+
+/// Runs the HTTP server until it is shut down
+async fn serve(config: Config, backend: Arc<dyn PdfBackend>) -> std::io::Result<()> {
+    // Refuse to start when the backend's dependencies (e.g. wkhtmltopdf) cannot run, rather
+    // than failing every conversion
+    if let Err(reason) = backend.check() {
+        error!("{} backend is unavailable: {}", backend.name(), reason);
+        std::process::exit(1);
+    }
+    info!("Using the {} PDF backend", backend.name());
+    let backend = web::Data::from(backend);
+    let bind_address = (config.host.clone(), config.port);
+    let max_markdown_bytes = config.max_markdown_bytes;
+    let shutdown_grace_period = config.shutdown_grace_period;
+    let pdf_cache_size = config.pdf_cache_size;
+    let limiter = web::Data::new(ConversionLimiter::new(
+        config.max_concurrent_conversions,
+        config.queue_timeout,
+        config.conversion_timeout,
+    ));
+    if config.api_key.is_some() {
+        info!("API key authentication is enabled for /convert routes");
+    }
+    match &config.allowed_origins {
+        Some(origins) => {
+            info!(origins = %origins.join(","), "CORS is restricted to ALLOWED_ORIGINS")
+        }
+        None => {
+            warn!("ALLOWED_ORIGINS is not set, so any website may call this API from a browser")
+        }
+    }
+    let rate_limiter = web::Data::new(RateLimiter::new(config.rate_limit_per_minute));
+    if rate_limiter.is_enabled() {
+        info!(
+            requests_per_minute = config.rate_limit_per_minute,
+            "Rate limiting is enabled for /convert routes"
+        );
+    }
+    let jobs = web::Data::new(JobStore::new(config.job_ttl));
+    let config = web::Data::new(config);
+    let metrics = web::Data::new(Metrics::new().unwrap_or_else(|e| {
+        error!("Failed to register metrics: {}", e);
+        std::process::exit(1);
+    }));
+    let cache = PdfCache::new(pdf_cache_size)
+        .and_then(|cache| {
+            cache.register(metrics.registry())?;
+            if cache.is_enabled() {
+                info!(entries = pdf_cache_size, "PDF cache is enabled");
+            }
+            Ok(web::Data::new(cache))
+        })
+        .unwrap_or_else(|e| {
+            error!("Failed to register metrics: {}", e);
+            std::process::exit(1);
+        });
 
-    HttpServer::new(|| {
-        // Configure CORS middleware with permissive settings
-        let cors = Cors::permissive();
+    info!(
+        "Starting rust-md-to-pdf v{} at http://{}:{}",
+        env!("CARGO_PKG_VERSION"),
+        bind_address.0,
+        bind_address.1
+    );
 
+    let shutdown_metrics = metrics.clone();
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(cors)
+            .wrap(from_fn(request_id::assign))
+            .wrap(cors(config.allowed_origins.as_deref()))
+            // Outermost, so it compresses the final body after the other middleware ran
+            .wrap(Compress::default())
+            .app_data(json_config(max_markdown_bytes))
+            .app_data(query_config())
+            .app_data(config.clone())
+            .app_data(backend.clone())
+            .app_data(limiter.clone())
+            .app_data(metrics.clone())
+            .app_data(cache.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(jobs.clone())
+            .route("/livez", web::get().to(liveness_check))
+            .route("/readyz", web::get().to(health_check))
             .route("/health", web::get().to(health_check))
-            .route("/convert", web::post().to(convert_markdown_to_pdf))
+            .route("/health/deep", web::get().to(deep_health_check))
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .route("/openapi.json", web::get().to(openapi_spec))
+            .route("/capabilities", web::get().to(capabilities_endpoint))
+            .service(
+                web::scope("/convert")
+                    .wrap(from_fn(auth::require_api_key))
+                    // Outside authentication, so clients guessing keys are limited as well
+                    .wrap(from_fn(ratelimit::limit))
+                    .route("", web::post().to(convert_markdown_to_pdf))
+                    .route("/upload", web::post().to(convert_markdown_upload))
+                    .route("/text", web::post().to(convert_markdown_text))
+                    .route("/archive", web::post().to(convert_markdown_archive))
+                    .route("/json", web::post().to(convert_markdown_to_json))
+                    .route("/html", web::post().to(convert_markdown_to_html))
+                    .route("/estimate", web::post().to(estimate_conversion))
+                    .route("/png", web::post().to(convert_markdown_to_png))
+                    .route("/batch", web::post().to(convert_markdown_batch))
+                    .route("/async", web::post().to(convert_markdown_async)),
+            )
+            .service(
+                web::scope("/jobs")
+                    .wrap(from_fn(auth::require_api_key))
+                    .wrap(from_fn(ratelimit::limit))
+                    .route("/{id}", web::get().to(job_status)),
+            )
+            .service(
+                web::scope("/validate")
+                    .wrap(from_fn(auth::require_api_key))
+                    .wrap(from_fn(ratelimit::limit))
+                    .route("", web::post().to(validate_markdown)),
+            )
     })
-    .bind("0.0.0.0:8080")?
-    .run()
-    .await
+    // Signals are handled by shutdown::drain_on_signal so conversions can finish first
+    .disable_signals()
+    .shutdown_timeout(RESPONSE_FLUSH_TIMEOUT_SECS)
+    .bind(bind_address)?
+    .run();
+
+    actix_web::rt::spawn(shutdown::drain_on_signal(
+        server.handle(),
+        shutdown_metrics,
+        shutdown_grace_period,
+    ));
+
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A markdown request with the given extensions, for tests throughout the crate
+    pub(crate) fn request(markdown: &str, extensions: &[&str]) -> MarkdownRequest {
+        MarkdownRequest {
+            markdown: Some(markdown.to_string()),
+            extensions: Some(extensions.iter().map(|name| name.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    /// Renders markdown with the given extensions to an HTML fragment
+    pub(crate) fn render(markdown: &str, extensions: &[&str]) -> String {
+        let request = request(markdown, extensions);
+        render_markdown(&request, request.theme())
+    }
+
+    /// A pipe table with a header row and `rows` body rows, long enough to need several pages
+    pub(crate) fn tall_table(rows: usize) -> String {
+        let body: String = (1..=rows)
+            .map(|row| format!("| Item {row} | {} |\n", row * 3))
+            .collect();
+        format!("| Item | Quantity |\n|------|---------:|\n{body}")
+    }
+
+    #[test]
+    fn headings_get_ids_that_internal_links_point_to() {
+        let html = render(
+            "# Getting Started\n\nText.\n\n## Getting Started\n\n[see above](#getting-started)\n",
+            &[],
+        );
+
+        assert!(html.contains(r#"id="getting-started""#), "{html}");
+        assert!(html.contains(r#"id="getting-started-1""#), "{html}");
+        assert!(
+            html.contains(r##"<a href="#getting-started">see above</a>"##),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn definition_lists_render_terms_and_indented_definitions() {
+        let markdown = "\
+Markdown
+
+: A lightweight markup language.
+
+PDF
+
+: Portable Document Format.
+
+    Pages look the same everywhere.
+
+Not a term
+: without the blank line
+";
+
+        let html = render(markdown, &["description_lists"]);
+        assert_eq!(html.matches("<dl>").count(), 1, "{html}");
+        assert_eq!(html.matches("<dt>").count(), 2, "{html}");
+        assert_eq!(html.matches("<dd>").count(), 2, "{html}");
+        assert!(html.contains("<dt>PDF</dt>"), "{html}");
+        assert!(
+            html.contains(
+                "<p>Portable Document Format.</p>\n<p>Pages look the same everywhere.</p>\n</dd>"
+            ),
+            "{html}"
+        );
+        assert!(
+            html.contains("<p>Not a term\n: without the blank line</p>"),
+            "{html}"
+        );
+
+        let document = markdown_to_html_converter(&request(markdown, &["description_lists"]), "");
+        assert!(
+            document.contains("dd { margin: 0.25em 0 0.5em 2em; }"),
+            "{document}"
+        );
+
+        let plain = render(markdown, &[]);
+        assert!(!plain.contains("<dl>"), "{plain}");
+    }
+
+    #[test]
+    fn long_tables_repeat_their_header_row_on_every_page() {
+        let markdown = tall_table(120);
+
+        // Tables need no extension unless the request asks for strict CommonMark
+        let html = render(&markdown, &[]);
+        assert!(html.contains("<thead>\n<tr>\n<th>Item</th>"), "{html}");
+        assert_eq!(html.matches("<thead>").count(), 1);
+        assert_eq!(html.matches("<tr>").count(), 121);
+
+        let request = request(&markdown, &[]);
+        let document = markdown_to_html_converter(&request, "");
+        assert!(document.contains("thead { display: table-header-group; }"));
+        assert!(document.contains("tr { page-break-inside: avoid; break-inside: avoid; }"));
+
+        let strict = MarkdownRequest {
+            flavor: Some("commonmark".to_string()),
+            ..request
+        };
+        assert!(!render_markdown(&strict, strict.theme()).contains("<table>"));
+    }
+
+    /// A zip archive holding the given files
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn archives_with_different_files_are_not_coalesced() {
+        let markdown = "# Chart\n\n![chart](chart.svg)\n";
+        let red = r#"<svg xmlns="http://www.w3.org/2000/svg"><rect fill="red"/></svg>"#;
+        let blue = r#"<svg xmlns="http://www.w3.org/2000/svg"><rect fill="blue"/></svg>"#;
+        let first = archive::extract(
+            &archive(&[("index.md", markdown), ("chart.svg", red)]),
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        let second = archive::extract(
+            &archive(&[("index.md", markdown), ("chart.svg", blue)]),
+            &std::env::temp_dir(),
+        )
+        .unwrap();
+        let archive_request = |extracted: &archive::Extracted| MarkdownRequest {
+            base_dir: Some(extracted.dir().to_path_buf()),
+            inline_images: true,
+            ..request(&extracted.markdown, &[])
+        };
+        let (first, second) = (archive_request(&first), archive_request(&second));
+
+        let cache = PdfCache::new(0).unwrap();
+        let limiter = ConversionLimiter::new(2, Duration::from_secs(5), Duration::from_secs(5));
+        // Stands in for the backend, so the output shows which files the document embedded
+        let convert = |request| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(render_html(request).await.into_bytes())
+        };
+        let (first_pdf, second_pdf) = tokio::join!(
+            convert_shared(&first, &cache, &limiter, convert(&first)),
+            convert_shared(&second, &cache, &limiter, convert(&second)),
+        );
+
+        let (first_pdf, second_pdf) = (first_pdf.unwrap(), second_pdf.unwrap());
+        let embedded = |svg: &str| STANDARD.encode(svg);
+        let first_pdf = String::from_utf8(first_pdf).unwrap();
+        let second_pdf = String::from_utf8(second_pdf).unwrap();
+        assert!(first_pdf.contains(&embedded(red)), "{first_pdf}");
+        assert!(!first_pdf.contains(&embedded(blue)), "{first_pdf}");
+        assert!(second_pdf.contains(&embedded(blue)), "{second_pdf}");
+        assert!(!second_pdf.contains(&embedded(red)), "{second_pdf}");
+    }
+
+    #[tokio::test]
+    async fn cached_pdfs_are_served_without_a_conversion_slot() {
+        let cache = PdfCache::new(4).unwrap();
+        let limiter = ConversionLimiter::new(1, Duration::from_millis(10), Duration::from_secs(5));
+        let cached = request("# Cached", &[]);
+        cache.insert(PdfCache::key(&cached), b"%PDF-cached");
+
+        // Every slot is taken by a conversion that is still running
+        let _running = limiter.acquire().await.unwrap();
+        let hit = convert_shared(&cached, &cache, &limiter, async {
+            panic!("cache hits are not converted")
+        })
+        .await;
+        assert_eq!(hit.unwrap(), b"%PDF-cached");
+
+        let missed = request("# Not cached", &[]);
+        let miss = convert_shared(&missed, &cache, &limiter, async { Ok(Vec::new()) }).await;
+        assert!(miss.unwrap_err().downcast_ref::<QueueTimeout>().is_some());
+    }
 }
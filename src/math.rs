@@ -0,0 +1,521 @@
+//! Renders TeX math written as `$...$` (inline) or `$$...$$` (display) to SVG with MathJax's
+//! `tex2svg` command.
+//!
+//! Formulas are taken out of the markdown before it is parsed, so TeX such as `a_1 * b_2` is
+//! not read as emphasis, and put back as `<span class="math inline|display">` elements holding
+//! the source. [`render_formulas`] then replaces their content with an `<img>` that has a
+//! `data:` URI; formulas that cannot be rendered are shown as TeX source, as are formulas
+//! past the per-document limit or left over when the document's time runs out.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::Instant;
+use tracing::warn;
+
+const TEX2SVG: &str = "tex2svg";
+/// Maximum time a single formula may take to render
+const RENDER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum time all formulas of one document may take to render together
+const DOCUMENT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Maximum number of distinct formulas rendered per document
+pub const MAX_FORMULAS: usize = 200;
+
+/// Delimits the placeholder that stands in for a formula while the markdown is parsed
+const PLACEHOLDER_MARKER: char = '\u{E001}';
+/// The formula index is written with private use characters from here on, so placeholders
+/// in headings do not add digits to the heading ids
+const PLACEHOLDER_DIGIT_ZERO: u32 = 0xE010;
+
+static PLACEHOLDER_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("\u{E001}([\u{E010}-\u{E019}]+)\u{E001}").unwrap());
+
+/// Matches a formula element as emitted by [`insert`]
+static FORMULA_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<span class="math (inline|display)">([^<]*)</span>"#).unwrap());
+
+/// Matches the root element of an SVG document
+static SVG_ROOT_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<svg\b[^>]*>").unwrap());
+
+/// The size attributes MathJax sets on the root element, in `ex` relative to the text
+static SVG_SIZE_PATTERNS: LazyLock<[(&str, Regex); 3]> = LazyLock::new(|| {
+    [
+        ("width", Regex::new(r#"\swidth="([^"]+)""#).unwrap()),
+        ("height", Regex::new(r#"\sheight="([^"]+)""#).unwrap()),
+        (
+            "vertical-align",
+            Regex::new(r"vertical-align:\s*([^;\x22]+)").unwrap(),
+        ),
+    ]
+});
+
+/// A formula taken out of the markdown by [`extract`]
+pub struct Formula {
+    tex: String,
+    display: bool,
+}
+
+impl Formula {
+    fn mode(&self) -> &'static str {
+        if self.display {
+            "display"
+        } else {
+            "inline"
+        }
+    }
+}
+
+/// Replaces the formulas in `markdown` with placeholders, adding them to `formulas`.
+///
+/// Dollar signs in fenced and indented code blocks, code spans and after a backslash are left
+/// alone, as are amounts like `$5 and $10`: an opening `$` must be followed by a non-space
+/// character, and a closing `$` must follow one and not be followed by a digit.
+pub fn extract(markdown: &str, formulas: &mut Vec<Formula>) -> String {
+    let mut output = String::with_capacity(markdown.len());
+    let mut prose = String::new();
+    let mut fence: Option<(char, usize)> = None;
+    let mut indented_code = false;
+    // Indented lines continue a list item rather than start a code block
+    let mut in_list = false;
+    let mut previous_blank = true;
+
+    for line in markdown.split_inclusive('\n') {
+        if let Some((marker, length)) = fence {
+            output.push_str(line);
+            if closes_fence(line, marker, length) {
+                fence = None;
+            }
+            continue;
+        }
+
+        let blank = line.trim().is_empty();
+        let code_indent = indent_width(line) >= 4;
+        if (indented_code && (blank || code_indent))
+            || (code_indent && !blank && previous_blank && !in_list)
+        {
+            output.push_str(&replace_formulas(&prose, formulas));
+            prose.clear();
+            output.push_str(line);
+            indented_code = true;
+            previous_blank = blank;
+            continue;
+        }
+
+        indented_code = false;
+        if !blank && !code_indent {
+            if starts_list_item(line) {
+                in_list = true;
+            } else if previous_blank {
+                in_list = false;
+            }
+        }
+        previous_blank = blank;
+        match opens_fence(line) {
+            Some(opened) => {
+                output.push_str(&replace_formulas(&prose, formulas));
+                prose.clear();
+                output.push_str(line);
+                fence = Some(opened);
+            }
+            None => prose.push_str(line),
+        }
+    }
+    output.push_str(&replace_formulas(&prose, formulas));
+    output
+}
+
+/// Turns the placeholders left by [`extract`] into formula elements holding the TeX source
+pub fn insert(html: &str, formulas: &[Formula]) -> String {
+    PLACEHOLDER_PATTERN
+        .replace_all(html, |captures: &Captures| {
+            let index = captures[1]
+                .chars()
+                .map(|digit| (digit as u32 - PLACEHOLDER_DIGIT_ZERO) as usize)
+                .fold(0, |index, digit| index * 10 + digit);
+            let formula = formulas.get(index);
+            match formula {
+                Some(formula) => format!(
+                    r#"<span class="math {}">{}</span>"#,
+                    formula.mode(),
+                    crate::escape_html(&formula.tex)
+                ),
+                None => String::new(),
+            }
+        })
+        .into_owned()
+}
+
+/// Replaces the TeX source in every formula element with the rendered formula.
+///
+/// When `tex2svg` is not installed all formulas are left as source, with one warning per
+/// document.
+pub async fn render_formulas(html: &str) -> String {
+    let deadline = Instant::now() + DOCUMENT_TIMEOUT;
+    let mut rendered: HashMap<(String, String), Option<String>> = HashMap::new();
+    for captures in FORMULA_PATTERN.captures_iter(html) {
+        let key = (captures[1].to_string(), captures[2].to_string());
+        if rendered.contains_key(&key) {
+            continue;
+        }
+        if rendered.len() == MAX_FORMULAS {
+            warn!(
+                limit = MAX_FORMULAS,
+                "Too many formulas, leaving the rest as TeX source"
+            );
+            break;
+        }
+        let tex = crate::unescape_html(&key.1);
+        let render = render_svg(&tex, key.0 == "display");
+        let Ok(result) = tokio::time::timeout_at(deadline, render).await else {
+            warn!(
+                rendered = rendered.len(),
+                "Math did not finish within {} seconds, leaving the rest as TeX source",
+                DOCUMENT_TIMEOUT.as_secs()
+            );
+            break;
+        };
+        match result {
+            Ok(svg) => {
+                // The escaped source doubles as the image's alternative text
+                rendered.insert(key, Some(image(&svg, &captures[2])));
+            }
+            Err(RenderError::NotInstalled) => {
+                warn!("tex2svg is not installed, leaving math as TeX source");
+                break;
+            }
+            Err(RenderError::Failed(e)) => {
+                warn!(error = format!("{:#}", e), "Failed to render math");
+                rendered.insert(key, None);
+            }
+        }
+    }
+
+    FORMULA_PATTERN
+        .replace_all(html, |captures: &Captures| {
+            let key = (captures[1].to_string(), captures[2].to_string());
+            match rendered.get(&key) {
+                Some(Some(image)) => {
+                    format!(r#"<span class="math {}">{}</span>"#, &captures[1], image)
+                }
+                _ => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Builds an `<img>` for the SVG, sized in `ex` so the formula scales with the text
+fn image(svg: &str, alt: &str) -> String {
+    let root = SVG_ROOT_PATTERN
+        .find(svg)
+        .map(|root| root.as_str())
+        .unwrap_or_default();
+    let style: Vec<String> = SVG_SIZE_PATTERNS
+        .iter()
+        .filter_map(|(property, pattern)| {
+            pattern
+                .captures(root)
+                .map(|captures| format!("{}: {}", property, captures[1].trim()))
+        })
+        .collect();
+    format!(
+        r#"<img src="data:image/svg+xml;base64,{}" alt="{}" style="{}">"#,
+        STANDARD.encode(svg),
+        alt,
+        style.join("; ")
+    )
+}
+
+enum RenderError {
+    NotInstalled,
+    Failed(anyhow::Error),
+}
+
+/// Runs `tex2svg` on one formula and returns the SVG it produced
+async fn render_svg(tex: &str, display: bool) -> Result<String, RenderError> {
+    let mut command = Command::new(TEX2SVG);
+    if !display {
+        command.arg("--inline");
+    }
+    // `--` stops formulas such as `-x` from being read as options
+    let child = command
+        .arg("--")
+        .arg(tex)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => RenderError::NotInstalled,
+            _ => RenderError::Failed(anyhow::Error::new(e).context("Failed to start tex2svg")),
+        })?;
+
+    let output = tokio::time::timeout(RENDER_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            RenderError::Failed(anyhow::anyhow!(
+                "tex2svg did not finish within {} seconds",
+                RENDER_TIMEOUT.as_secs()
+            ))
+        })?
+        .map_err(|e| RenderError::Failed(anyhow::Error::new(e).context("Failed to run tex2svg")))?;
+
+    let svg = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !svg.trim_start().starts_with("<svg") {
+        return Err(RenderError::Failed(anyhow::anyhow!(
+            "tex2svg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(svg.trim().to_string())
+}
+
+/// Recognises an opening code fence, returning its marker character and length
+fn opens_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = strip_fence_indent(line)?;
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let length = trimmed.chars().take_while(|&c| c == marker).count();
+    // Backtick fences cannot have backticks in their info string
+    let info = &trimmed[length..];
+    (length >= 3 && !(marker == '`' && info.contains('`'))).then_some((marker, length))
+}
+
+fn closes_fence(line: &str, marker: char, length: usize) -> bool {
+    strip_fence_indent(line).is_some_and(|trimmed| {
+        let run = trimmed.chars().take_while(|&c| c == marker).count();
+        run >= length && trimmed[run..].trim().is_empty()
+    })
+}
+
+/// Columns of leading whitespace, with tabs advancing to the next multiple of four
+fn indent_width(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 4 - width % 4,
+            _ => break,
+        }
+    }
+    width
+}
+
+/// Recognises the marker of a bullet or ordered list item at the start of a line
+fn starts_list_item(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    let digits = trimmed.len()
+        - trimmed
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let rest = if digits > 0 {
+        trimmed[digits..].strip_prefix(['.', ')'])
+    } else {
+        trimmed.strip_prefix(['-', '*', '+'])
+    };
+    rest.is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t', '\n', '\r']))
+}
+
+/// Fences may be indented by up to three spaces
+fn strip_fence_indent(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    (line.len() - trimmed.len() <= 3).then_some(trimmed)
+}
+
+/// Replaces the formulas in text outside code blocks with placeholders
+fn replace_formulas(text: &str, formulas: &mut Vec<Formula>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(index) = rest.find(['$', '`', '\\']) {
+        output.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let consumed = match rest.as_bytes()[0] {
+            b'\\' => 1 + rest[1..].chars().next().map_or(0, char::len_utf8),
+            b'`' => code_span_len(rest),
+            _ => match formula_at(rest) {
+                Some((length, formula)) => {
+                    output.push(PLACEHOLDER_MARKER);
+                    output.extend(formulas.len().to_string().chars().map(|digit| {
+                        char::from_u32(PLACEHOLDER_DIGIT_ZERO + digit as u32 - '0' as u32)
+                            .expect("private use characters are valid")
+                    }));
+                    output.push(PLACEHOLDER_MARKER);
+                    formulas.push(formula);
+                    rest = &rest[length..];
+                    continue;
+                }
+                None if rest.starts_with("$$") => 2,
+                None => 1,
+            },
+        };
+        output.push_str(&rest[..consumed]);
+        rest = &rest[consumed..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Returns the length of the code span starting at `text`, or of its opening backticks when
+/// it is never closed
+fn code_span_len(text: &str) -> usize {
+    let ticks = text.len() - text.trim_start_matches('`').len();
+    let paragraph = &text[..paragraph_end(text)];
+    let mut offset = ticks;
+    while let Some(start) = paragraph[offset..].find('`') {
+        let start = offset + start;
+        let run = paragraph[start..].len() - paragraph[start..].trim_start_matches('`').len();
+        if run == ticks {
+            return start + run;
+        }
+        offset = start + run;
+    }
+    ticks
+}
+
+/// Parses the formula starting at `text`, returning its length in bytes
+fn formula_at(text: &str) -> Option<(usize, Formula)> {
+    let paragraph = &text[..paragraph_end(text)];
+
+    if let Some(body) = paragraph.strip_prefix("$$") {
+        let end = body.find("$$")?;
+        let tex = body[..end].trim();
+        return (!tex.is_empty()).then(|| {
+            (
+                end + 4,
+                Formula {
+                    tex: tex.to_string(),
+                    display: true,
+                },
+            )
+        });
+    }
+
+    let body = &paragraph[1..];
+    if body.chars().next().is_none_or(char::is_whitespace) {
+        return None;
+    }
+    let mut previous = ' ';
+    let mut chars = body.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            // Code spans take precedence, so inline formulas cannot contain them
+            '`' => return None,
+            '$' if !previous.is_whitespace()
+                && !body[index + 1..].starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                return Some((
+                    index + 2,
+                    Formula {
+                        tex: body[..index].to_string(),
+                        display: false,
+                    },
+                ));
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+/// Formulas and code spans cannot continue past a blank line
+fn paragraph_end(text: &str) -> usize {
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if offset > 0 && line.trim().is_empty() {
+            return offset;
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Extracts the formulas and puts them back, as rendering a section does
+    fn round_trip(markdown: &str) -> (String, Vec<Formula>) {
+        let mut formulas = Vec::new();
+        let extracted = extract(markdown, &mut formulas);
+        (insert(&extracted, &formulas), formulas)
+    }
+
+    #[test]
+    fn inline_and_display_formulas_are_extracted() {
+        let (text, formulas) =
+            round_trip("Euler: $e^{i\\pi} + 1 = 0$\n\n$$\n\\sum_{n=1}^\\infty a_n\n$$\n");
+
+        assert_eq!(formulas.len(), 2);
+        assert_eq!(formulas[0].tex, "e^{i\\pi} + 1 = 0");
+        assert!(!formulas[0].display);
+        assert_eq!(formulas[1].tex, "\\sum_{n=1}^\\infty a_n");
+        assert!(formulas[1].display);
+        assert!(
+            text.contains(r#"<span class="math inline">e^{i\pi} + 1 = 0</span>"#),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn amounts_escapes_and_code_spans_are_not_formulas() {
+        let markdown = "Costs $5 and $10.\n\nEscaped \\$x$, code `$y$` and spaced $ z$.\n";
+        let (text, formulas) = round_trip(markdown);
+
+        assert!(formulas.is_empty());
+        assert_eq!(text, markdown);
+    }
+
+    #[test]
+    fn code_blocks_are_left_alone() {
+        let markdown = "```sh\necho $HOME$\n```\n\nText\n\n    let cost = $a$;\n\n\tprice = $b$\n\nAfter $c$\n";
+        let (text, formulas) = round_trip(markdown);
+
+        assert_eq!(formulas.len(), 1, "{text}");
+        assert_eq!(formulas[0].tex, "c");
+        assert!(text.contains("echo $HOME$\n"), "{text}");
+        assert!(text.contains("    let cost = $a$;\n"), "{text}");
+        assert!(text.contains("\tprice = $b$\n"), "{text}");
+    }
+
+    #[test]
+    fn indented_lines_in_lists_and_paragraphs_keep_their_formulas() {
+        let markdown = "- Item\n\n    continued with $x$\n\nA paragraph\n    wrapped onto $y$\n";
+        let (_, formulas) = round_trip(markdown);
+
+        let tex: Vec<&str> = formulas
+            .iter()
+            .map(|formula| formula.tex.as_str())
+            .collect();
+        assert_eq!(tex, ["x", "y"]);
+    }
+
+    #[test]
+    fn formulas_are_not_read_as_markdown() {
+        let request = crate::MarkdownRequest {
+            markdown: Some(
+                "# Energy $E = mc^2$\n\nIndex $a_1 * b_2$ and $c_3 * d_4$\n".to_string(),
+            ),
+            render_math: true,
+            ..Default::default()
+        };
+        let html = crate::render_markdown(&request, request.theme());
+
+        assert!(
+            html.contains(r#"<span class="math inline">a_1 * b_2</span>"#),
+            "{html}"
+        );
+        assert!(!html.contains("<em>"), "{html}");
+        assert!(
+            html.contains(r#"Energy <span class="math inline">E = mc^2</span></h1>"#),
+            "{html}"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Renders ` ```mermaid ` code blocks to SVG with mermaid-cli (`mmdc`).
+//!
+//! Each diagram replaces its code block as an `<img>` with a `data:` URI, so the PDF
+//! renderer needs no JavaScript. Diagrams that cannot be rendered keep their code block, as do
+//! diagrams past the per-document limit or left over when the document's time runs out.
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::{Captures, Regex};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{LazyLock, OnceLock};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::Instant;
+use tracing::warn;
+use uuid::Uuid;
+
+const MMDC: &str = "mmdc";
+/// Maximum time a single diagram may take to render
+const RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum time all diagrams of one document may take to render together
+const DOCUMENT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Maximum number of diagrams rendered per document
+pub const MAX_DIAGRAMS: usize = 20;
+
+/// Directory for the diagram files, `TEMP_DIR` once the server has started
+static TEMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Labels are drawn as SVG text, since wkhtmltopdf cannot show `foreignObject` HTML labels
+const MERMAID_CONFIG: &str = r#"{"htmlLabels": false, "flowchart": {"htmlLabels": false}}"#;
+
+/// Matches a mermaid code block as emitted by comrak
+static MERMAID_BLOCK_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)<pre><code class="language-mermaid">(.*?)</code></pre>"#).unwrap()
+});
+
+/// Writes the files of every diagram to `dir`
+pub fn install_temp_dir(dir: PathBuf) {
+    let _ = TEMP_DIR.set(dir);
+}
+
+/// Replaces every mermaid code block in the HTML with the rendered diagram.
+///
+/// When `mmdc` is not installed all blocks are left as code, with one warning per document.
+pub async fn render_diagrams(html: &str) -> String {
+    let sources: Vec<String> = MERMAID_BLOCK_PATTERN
+        .captures_iter(html)
+        .map(|captures| crate::unescape_html(&captures[1]))
+        .collect();
+    if sources.len() > MAX_DIAGRAMS {
+        warn!(
+            diagrams = sources.len(),
+            limit = MAX_DIAGRAMS,
+            "Too many mermaid diagrams, leaving the rest as code blocks"
+        );
+    }
+
+    let deadline = Instant::now() + DOCUMENT_TIMEOUT;
+    let mut diagrams = Vec::with_capacity(sources.len());
+    for source in sources.iter().take(MAX_DIAGRAMS) {
+        let Ok(rendered) = tokio::time::timeout_at(deadline, render_svg(source)).await else {
+            warn!(
+                rendered = diagrams.len(),
+                "Mermaid diagrams did not finish within {} seconds, leaving the rest as code blocks",
+                DOCUMENT_TIMEOUT.as_secs()
+            );
+            break;
+        };
+        match rendered {
+            Ok(svg) => diagrams.push(Some(svg)),
+            Err(RenderError::NotInstalled) => {
+                warn!("mmdc is not installed, leaving mermaid diagrams as code blocks");
+                diagrams.resize(sources.len(), None);
+                break;
+            }
+            Err(RenderError::Failed(e)) => {
+                warn!(
+                    error = format!("{:#}", e),
+                    "Failed to render mermaid diagram"
+                );
+                diagrams.push(None);
+            }
+        }
+    }
+
+    let mut diagrams = diagrams.into_iter();
+    MERMAID_BLOCK_PATTERN
+        .replace_all(html, |captures: &Captures| match diagrams.next().flatten() {
+            Some(svg) => format!(
+                r#"<p class="mermaid"><img src="data:image/svg+xml;base64,{}" alt="Diagram"></p>"#,
+                STANDARD.encode(svg)
+            ),
+            None => captures[0].to_string(),
+        })
+        .into_owned()
+}
+
+enum RenderError {
+    NotInstalled,
+    Failed(anyhow::Error),
+}
+
+/// Runs `mmdc` on one diagram and returns the SVG it produced
+async fn render_svg(source: &str) -> Result<Vec<u8>, RenderError> {
+    let files = match TEMP_DIR.get() {
+        Some(dir) => DiagramFiles::new(dir),
+        None => DiagramFiles::new(&std::env::temp_dir()),
+    };
+    for (path, content) in [(&files.input, source), (&files.config, MERMAID_CONFIG)] {
+        tokio::fs::write(path, content)
+            .await
+            .context("Failed to write mermaid input")
+            .map_err(RenderError::Failed)?;
+    }
+
+    let child = Command::new(MMDC)
+        .arg("--input")
+        .arg(&files.input)
+        .arg("--output")
+        .arg(&files.output)
+        .arg("--configFile")
+        .arg(&files.config)
+        .arg("--quiet")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => RenderError::NotInstalled,
+            _ => RenderError::Failed(anyhow::Error::new(e).context("Failed to start mmdc")),
+        })?;
+
+    let output = tokio::time::timeout(RENDER_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            RenderError::Failed(anyhow::anyhow!(
+                "mmdc did not finish within {} seconds",
+                RENDER_TIMEOUT.as_secs()
+            ))
+        })?
+        .context("Failed to run mmdc")
+        .map_err(RenderError::Failed)?;
+    if !output.status.success() {
+        return Err(RenderError::Failed(anyhow::anyhow!(
+            "mmdc failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    tokio::fs::read(&files.output)
+        .await
+        .context("Failed to read mmdc output")
+        .map_err(RenderError::Failed)
+}
+
+/// Temporary files for one diagram, deleted when dropped
+struct DiagramFiles {
+    input: PathBuf,
+    output: PathBuf,
+    config: PathBuf,
+}
+
+impl DiagramFiles {
+    fn new(dir: &Path) -> Self {
+        let stem = dir.join(format!("mermaid-{}", Uuid::new_v4()));
+        Self {
+            input: stem.with_extension("mmd"),
+            output: stem.with_extension("svg"),
+            config: stem.with_extension("json"),
+        }
+    }
+}
+
+impl Drop for DiagramFiles {
+    fn drop(&mut self) {
+        for path in [&self.input, &self.output, &self.config] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+use std::future::Future;
+
+/// Prometheus collectors describing conversion activity
+pub struct Metrics {
+    registry: Registry,
+    conversions: IntCounter,
+    failures: IntCounter,
+    duration: Histogram,
+    in_flight: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let conversions = IntCounter::new(
+            "pdf_conversions_total",
+            "Total number of markdown to PDF conversions attempted",
+        )?;
+        let failures = IntCounter::new(
+            "pdf_conversion_failures_total",
+            "Total number of markdown to PDF conversions that failed",
+        )?;
+        let duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "pdf_conversion_duration_seconds",
+                "Time spent converting markdown to PDF",
+            )
+            .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        )?;
+        let in_flight = IntGauge::new(
+            "pdf_conversions_in_flight",
+            "Number of conversions currently running",
+        )?;
+
+        registry.register(Box::new(conversions.clone()))?;
+        registry.register(Box::new(failures.clone()))?;
+        registry.register(Box::new(duration.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+
+        Ok(Self {
+            registry,
+            conversions,
+            failures,
+            duration,
+            in_flight,
+        })
+    }
+
+    /// Runs a conversion while recording its duration, outcome and in-flight count
+    pub async fn track<T>(
+        &self,
+        conversion: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.conversions.inc();
+        let _in_flight = InFlight::new(&self.in_flight);
+        let timer = self.duration.start_timer();
+
+        let result = conversion.await;
+
+        timer.observe_duration();
+        if result.is_err() {
+            self.failures.inc();
+        }
+        result
+    }
+
+    /// Registry that further collectors can be added to
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Returns the number of conversions currently running
+    pub fn in_flight(&self) -> i64 {
+        self.in_flight.get()
+    }
+
+    /// Renders all collectors in the Prometheus text exposition format
+    pub fn encode(&self) -> prometheus::Result<(String, Vec<u8>)> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        Ok((encoder.format_type().to_string(), buffer))
+    }
+}
+
+/// Keeps the in-flight gauge accurate even if the request is cancelled mid-conversion
+struct InFlight<'a>(&'a IntGauge);
+
+impl<'a> InFlight<'a> {
+    fn new(gauge: &'a IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
@@ -0,0 +1,534 @@
+//! OpenAPI 3 description of the conversion API, served at `/openapi.json`.
+//!
+//! The document is written by hand, but enumerations, ranges and patterns are taken from
+//! the constants that request validation uses, so the constraints cannot drift apart.
+
+use crate::{
+    fonts, logo, themes, COLUMNS_RANGE, DEFAULT_BLEED, DEFAULT_COLUMN_GAP, DEFAULT_DPI,
+    DEFAULT_FILENAME, DEFAULT_FONT_SIZE, DEFAULT_IMAGE_DPI, DEFAULT_IMAGE_QUALITY,
+    DEFAULT_INPUT_FORMAT, DEFAULT_MARGIN_BOTTOM, DEFAULT_MARGIN_TOP, DEFAULT_ORIENTATION,
+    DEFAULT_PAGE_NUMBER_STYLE, DEFAULT_PAGE_SIZE, DEFAULT_TIMESTAMP_FORMAT,
+    DEFAULT_WATERMARK_OPACITY, DEFAULT_WATERMARK_ROTATION, DEFAULT_ZOOM, DIRECTIONS, DPI_RANGE,
+    EXTENSIONS, FLAVORS, FONT_SIZE_PATTERN, IMAGE_DPI_RANGE, IMAGE_QUALITY_RANGE, INPUT_FORMATS,
+    LANG_PATTERN, LENGTH_PATTERN, MAX_CUSTOM_CSS_BYTES, MAX_WATERMARK_CHARS, ORIENTATIONS,
+    PAGE_NUMBER_STYLES, PAGE_SIZES, RENDER_DELAY_RANGE, ZOOM_RANGE,
+};
+use serde_json::{json, Map, Value};
+use std::sync::LazyLock;
+
+/// The API description, built once on first use
+pub static SPEC: LazyLock<Value> = LazyLock::new(spec);
+
+fn spec() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "rust-md-to-pdf",
+            "description": "Converts Markdown and HTML documents to PDF.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/convert": {
+                "post": {
+                    "summary": "Convert markdown to PDF",
+                    "security": [{ "bearerAuth": [] }, { "apiKeyHeader": [] }, {}],
+                    "parameters": [{
+                        "name": "If-None-Match",
+                        "in": "header",
+                        "required": false,
+                        "description": "ETag of a PDF the client already has",
+                        "schema": { "type": "string" },
+                    }, {
+                        "name": "Accept",
+                        "in": "header",
+                        "required": false,
+                        "description": "application/json returns the PDF wrapped like /convert/json",
+                        "schema": { "type": "string", "default": "application/pdf" },
+                    }],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/MarkdownRequest" },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "The rendered PDF",
+                            "headers": {
+                                "ETag": { "schema": { "type": "string" } },
+                                "Content-Disposition": { "schema": { "type": "string" } },
+                            },
+                            "content": {
+                                "application/pdf": {
+                                    "schema": { "type": "string", "format": "binary" },
+                                },
+                                "application/json": {
+                                    "schema": {
+                                        "oneOf": [
+                                            { "$ref": "#/components/schemas/PdfJsonResponse" },
+                                            { "$ref": "#/components/schemas/UploadResponse" },
+                                        ],
+                                    },
+                                },
+                            },
+                        },
+                        "304": { "description": "The PDF matches the If-None-Match ETag" },
+                        "400": error_response("An option is invalid or the body cannot be parsed"),
+                        "401": { "description": "API_KEY is set and the request carries no matching key" },
+                        "406": error_response("The Accept header allows neither application/pdf nor application/json"),
+                        "413": error_response("The request exceeds MAX_MARKDOWN_BYTES"),
+                        "415": error_response("The body is not application/json"),
+                        "422": {
+                            "description": "The PDF has more pages than MAX_PAGES allows",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/PageLimitResponse" },
+                                },
+                            },
+                        },
+                        "429": error_response("The client exceeded RATE_LIMIT_PER_MINUTE"),
+                        "500": error_response("PDF generation failed"),
+                        "502": error_response("source_url could not be downloaded or the upload_url upload failed"),
+                        "503": error_response("No conversion slot became free in time"),
+                        "504": error_response("The renderer exceeded WKHTMLTOPDF_TIMEOUT_SECS"),
+                    },
+                },
+            },
+            "/convert/json": {
+                "post": {
+                    "summary": "Convert markdown to a base64-encoded PDF wrapped in JSON",
+                    "security": [{ "bearerAuth": [] }, { "apiKeyHeader": [] }, {}],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/MarkdownRequest" },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "The rendered PDF",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/PdfJsonResponse" },
+                                },
+                            },
+                        },
+                        "400": error_response("An option is invalid or the body cannot be parsed"),
+                        "500": error_response("PDF generation failed"),
+                    },
+                },
+            },
+            "/convert/estimate": {
+                "post": {
+                    "summary": "Predict the size of a conversion without rendering it",
+                    "security": [{ "bearerAuth": [] }, { "apiKeyHeader": [] }, {}],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/MarkdownRequest" },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Element counts and the predicted page range",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "$ref": "#/components/schemas/EstimateResponse" },
+                                },
+                            },
+                        },
+                        "400": error_response("An option is invalid, the body cannot be parsed or the input is HTML"),
+                        "502": error_response("source_url could not be downloaded"),
+                    },
+                },
+            },
+            "/health": {
+                "get": {
+                    "summary": "Check that the PDF backend is usable",
+                    "responses": {
+                        "200": health_response("The service can convert documents"),
+                        "503": health_response("The PDF backend is unavailable"),
+                    },
+                },
+            },
+            "/capabilities": {
+                "get": {
+                    "summary": "List the supported option values and their defaults",
+                    "responses": {
+                        "200": {
+                            "description": "Page sizes, themes, flavors and other choices, the allowed ranges of numeric options and the defaults",
+                            "content": {
+                                "application/json": { "schema": { "type": "object" } },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+        "components": {
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer" },
+                "apiKeyHeader": { "type": "apiKey", "in": "header", "name": "X-API-Key" },
+            },
+            "schemas": {
+                "MarkdownRequest": markdown_request(),
+                "Margins": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "top": length(Some(DEFAULT_MARGIN_TOP)),
+                        "bottom": length(Some(DEFAULT_MARGIN_BOTTOM)),
+                        "left": length(None),
+                        "right": length(None),
+                    },
+                },
+                "HeaderFooter": {
+                    "type": "object",
+                    "description": "Text for each slot, which may contain the placeholders [page], [topage], [title], [date], [time], [section] and [subsection]",
+                    "properties": {
+                        "left": { "type": "string" },
+                        "center": { "type": "string" },
+                        "right": { "type": "string" },
+                    },
+                },
+                "HeaderLogo": {
+                    "type": "object",
+                    "description": "Exactly one of url or data is required",
+                    "properties": {
+                        "url": { "type": "string", "format": "uri", "pattern": "^https?://" },
+                        "data": { "type": "string", "format": "byte" },
+                        "position": {
+                            "type": "string",
+                            "enum": logo::POSITIONS,
+                            "default": logo::DEFAULT_POSITION,
+                        },
+                        "height": length(Some(logo::DEFAULT_HEIGHT)),
+                    },
+                },
+                "Metadata": {
+                    "type": "object",
+                    "properties": {
+                        "title": { "type": "string" },
+                        "author": { "type": "string" },
+                        "subject": { "type": "string" },
+                        "keywords": { "type": "string" },
+                    },
+                },
+                "Encryption": {
+                    "type": "object",
+                    "required": ["user_password"],
+                    "properties": {
+                        "user_password": { "type": "string", "minLength": 1 },
+                        "owner_password": { "type": "string" },
+                        "allow_print": { "type": "boolean", "default": true },
+                        "allow_copy": { "type": "boolean", "default": true },
+                    },
+                },
+                "Watermark": {
+                    "type": "object",
+                    "required": ["text"],
+                    "properties": {
+                        "text": {
+                            "type": "string",
+                            "minLength": 1,
+                            "maxLength": MAX_WATERMARK_CHARS,
+                        },
+                        "opacity": {
+                            "type": "number",
+                            "minimum": 0,
+                            "maximum": 1,
+                            "default": DEFAULT_WATERMARK_OPACITY,
+                        },
+                        "rotation": {
+                            "type": "number",
+                            "minimum": -360,
+                            "maximum": 360,
+                            "default": DEFAULT_WATERMARK_ROTATION,
+                        },
+                    },
+                },
+                "Font": {
+                    "type": "object",
+                    "description": "Exactly one of url or data is required",
+                    "required": ["family"],
+                    "properties": {
+                        "family": { "type": "string", "pattern": fonts::FAMILY_PATTERN.as_str() },
+                        "url": { "type": "string", "format": "uri", "pattern": "^https?://" },
+                        "data": { "type": "string", "format": "byte" },
+                    },
+                },
+                "ErrorResponse": {
+                    "type": "object",
+                    "required": ["error"],
+                    "properties": {
+                        "error": { "type": "string" },
+                        "detail": { "type": "string" },
+                        "request_id": { "type": "string" },
+                    },
+                },
+                "PageLimitResponse": {
+                    "type": "object",
+                    "required": ["error", "page_count", "max_pages"],
+                    "properties": {
+                        "error": { "type": "string" },
+                        "page_count": { "type": "integer" },
+                        "max_pages": { "type": "integer" },
+                    },
+                },
+                "UploadResponse": {
+                    "type": "object",
+                    "description": "Answer to requests with an upload_url",
+                    "required": ["location", "size_bytes", "page_count"],
+                    "properties": {
+                        "location": { "type": "string", "format": "uri" },
+                        "size_bytes": { "type": "integer" },
+                        "page_count": { "type": "integer" },
+                    },
+                },
+                "PdfJsonResponse": {
+                    "type": "object",
+                    "required": ["pdf_base64", "size_bytes", "page_count", "warnings"],
+                    "properties": {
+                        "pdf_base64": { "type": "string", "format": "byte" },
+                        "size_bytes": { "type": "integer" },
+                        "page_count": { "type": "integer" },
+                        "warnings": { "type": "array", "items": { "type": "string" } },
+                    },
+                },
+                "EstimateResponse": {
+                    "type": "object",
+                    "required": [
+                        "characters", "words", "headings", "images", "tables", "code_blocks",
+                        "page_breaks", "pages", "complexity",
+                    ],
+                    "properties": {
+                        "characters": { "type": "integer" },
+                        "words": { "type": "integer" },
+                        "headings": { "type": "integer" },
+                        "images": { "type": "integer" },
+                        "tables": { "type": "integer" },
+                        "code_blocks": { "type": "integer" },
+                        "page_breaks": { "type": "integer" },
+                        "pages": {
+                            "type": "object",
+                            "required": ["min", "max"],
+                            "properties": {
+                                "min": { "type": "integer" },
+                                "max": { "type": "integer" },
+                            },
+                        },
+                        "complexity": { "type": "string", "enum": ["low", "medium", "high"] },
+                        "exceeds_max_pages": {
+                            "type": "boolean",
+                            "description": "Whether the smallest predicted page count is over MAX_PAGES; only present when it is set",
+                        },
+                    },
+                },
+                "HealthResponse": {
+                    "type": "object",
+                    "required": ["status", "version", "backend", "pdf_a_available"],
+                    "properties": {
+                        "status": { "type": "string" },
+                        "version": { "type": "string" },
+                        "backend": { "type": "string" },
+                        "backend_version": { "type": "string" },
+                        "pdf_a_available": { "type": "boolean" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// Schema of the conversion request body
+fn markdown_request() -> Value {
+    let page_sizes: Vec<&str> = PAGE_SIZES.iter().map(|(name, _, _)| *name).collect();
+    let themes: Vec<&str> = themes::THEMES.iter().map(|theme| theme.name).collect();
+    let pdf = json!({ "type": "string", "format": "byte" });
+
+    let properties = [
+        ("markdown", json!({
+            "type": "string",
+            "description": "Document source, interpreted according to input_format. Also accepted as html, content or md",
+        })),
+        ("source_url", json!({
+            "type": "string",
+            "format": "uri",
+            "description": "http(s) URL the markdown is downloaded from; the host must be in SOURCE_URL_ALLOWED_HOSTS",
+        })),
+        ("upload_url", json!({
+            "type": "string",
+            "format": "uri",
+            "description": "Pre-signed http(s) URL the PDF is uploaded to with PUT instead of being returned; the host must be in UPLOAD_URL_ALLOWED_HOSTS",
+        })),
+        ("sections", json!({
+            "type": "array",
+            "minItems": 1,
+            "items": { "type": "string" },
+            "description": "Markdown documents joined with page breaks",
+        })),
+        ("input_format", string_enum(INPUT_FORMATS, DEFAULT_INPUT_FORMAT)),
+        ("full_document", boolean("Treat HTML input as a complete document; requires input_format html and sanitize false")),
+        ("flavor", json!({ "type": "string", "enum": FLAVORS })),
+        ("extensions", json!({ "type": "array", "items": { "type": "string", "enum": EXTENSIONS } })),
+        ("page_size", string_enum(&page_sizes, DEFAULT_PAGE_SIZE)),
+        ("orientation", string_enum(ORIENTATIONS, DEFAULT_ORIENTATION)),
+        ("margins", json!({ "$ref": "#/components/schemas/Margins" })),
+        ("metadata", json!({ "$ref": "#/components/schemas/Metadata" })),
+        ("filename", json!({ "type": "string", "default": DEFAULT_FILENAME })),
+        ("response_headers", json!({
+            "type": "object",
+            "additionalProperties": { "type": "string" },
+            "description": "Cache-Control, Expires, Content-Language or X- headers other than X-Request-ID",
+        })),
+        ("custom_css", json!({ "type": "string", "maxLength": MAX_CUSTOM_CSS_BYTES })),
+        ("header", json!({ "$ref": "#/components/schemas/HeaderFooter" })),
+        ("footer", json!({ "$ref": "#/components/schemas/HeaderFooter" })),
+        ("header_logo", json!({ "$ref": "#/components/schemas/HeaderLogo" })),
+        ("toc", boolean("Insert a table of contents; markdown input only")),
+        ("inline_images", boolean("Embed images as data URIs before rendering")),
+        ("encryption", json!({ "$ref": "#/components/schemas/Encryption" })),
+        ("watermark", json!({ "$ref": "#/components/schemas/Watermark" })),
+        ("theme", string_enum(&themes, themes::DEFAULT_THEME)),
+        ("dpi", integer_range(*DPI_RANGE.start(), *DPI_RANGE.end(), DEFAULT_DPI)),
+        ("zoom", json!({
+            "type": "number",
+            "minimum": ZOOM_RANGE.start(),
+            "maximum": ZOOM_RANGE.end(),
+            "default": DEFAULT_ZOOM,
+        })),
+        ("image_quality", integer_range(
+            *IMAGE_QUALITY_RANGE.start(),
+            *IMAGE_QUALITY_RANGE.end(),
+            DEFAULT_IMAGE_QUALITY,
+        )),
+        ("image_dpi", integer_range(
+            *IMAGE_DPI_RANGE.start(),
+            *IMAGE_DPI_RANGE.end(),
+            DEFAULT_IMAGE_DPI,
+        )),
+        ("sanitize", json!({ "type": "boolean", "default": true })),
+        ("lang", json!({ "type": "string", "pattern": LANG_PATTERN.as_str() })),
+        ("dir", json!({ "type": "string", "enum": DIRECTIONS })),
+        ("fonts", json!({ "type": "array", "items": { "$ref": "#/components/schemas/Font" } })),
+        ("render_mermaid", boolean("Render mermaid code blocks as diagrams; markdown input only")),
+        ("render_math", boolean("Render $...$ and $$...$$ as math; markdown input only")),
+        ("render_emoji", boolean("Expand emoji shortcodes such as :rocket:; markdown input only")),
+        ("allow_raw_html", boolean(
+            "Pass raw HTML in the markdown through instead of omitting it; for trusted input only, \
+             as unsanitized HTML can run scripts and read local files in the renderer",
+        )),
+        ("pdf_a", boolean("Produce PDF/A-2b; cannot be combined with encryption")),
+        ("grayscale", boolean("Produce the PDF in shades of gray; merged PDFs and the builtin backend need Ghostscript")),
+        ("print_ready", boolean("Add bleed and crop marks")),
+        ("bleed", json!({
+            "type": "string",
+            "pattern": LENGTH_PATTERN.as_str(),
+            "default": DEFAULT_BLEED,
+            "description": "Requires print_ready",
+        })),
+        ("smart_shrinking", boolean("Let wkhtmltopdf shrink content to fit the page")),
+        ("enable_javascript", boolean("Run scripts in the page before rendering it")),
+        ("render_delay_ms", json!({
+            "type": "integer",
+            "minimum": RENDER_DELAY_RANGE.start(),
+            "maximum": RENDER_DELAY_RANGE.end(),
+            "description": "Milliseconds scripts may run before rendering; requires enable_javascript",
+        })),
+        ("hard_breaks", json!({
+            "type": "boolean",
+            "description": "Keep newlines inside paragraphs as line breaks; defaults to true for the gfm flavor",
+        })),
+        ("print_background", json!({
+            "type": "boolean",
+            "description": "Print CSS backgrounds; defaults to true for the dark, sepia and github themes",
+        })),
+        ("font_size", json!({
+            "type": "string",
+            "pattern": FONT_SIZE_PATTERN.as_str(),
+            "default": DEFAULT_FONT_SIZE,
+        })),
+        ("line_height", json!({ "type": "number", "exclusiveMinimum": true, "minimum": 0 })),
+        ("content_max_width", json!({
+            "type": "string",
+            "pattern": LENGTH_PATTERN.as_str(),
+            "description": "Width the body is narrowed to and centred at, such as 160mm",
+        })),
+        ("columns", integer_range(*COLUMNS_RANGE.start(), *COLUMNS_RANGE.end(), 1)),
+        ("column_gap", json!({
+            "type": "string",
+            "pattern": LENGTH_PATTERN.as_str(),
+            "default": DEFAULT_COLUMN_GAP,
+            "description": "Requires columns",
+        })),
+        ("prepend_pdf_base64", pdf.clone()),
+        ("append_pdf_base64", pdf),
+        ("allow_empty", boolean("Render a blank document instead of rejecting empty markdown")),
+        ("footer_timestamp", boolean("Print the UTC generation time in the right footer slot")),
+        ("timestamp_format", json!({ "type": "string", "default": DEFAULT_TIMESTAMP_FORMAT })),
+        ("page_offset", json!({ "type": "integer", "default": 0 })),
+        ("page_number_style", string_enum(PAGE_NUMBER_STYLES, DEFAULT_PAGE_NUMBER_STYLE)),
+    ];
+
+    json!({
+        "type": "object",
+        "description": "Exactly one of markdown, source_url or sections is required",
+        "properties": properties
+            .into_iter()
+            .map(|(name, schema)| (name.to_string(), schema))
+            .collect::<Map<String, Value>>(),
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/ErrorResponse" },
+            },
+        },
+    })
+}
+
+fn health_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/HealthResponse" },
+            },
+        },
+    })
+}
+
+fn string_enum(values: &[&str], default: &str) -> Value {
+    json!({ "type": "string", "enum": values, "default": default })
+}
+
+fn integer_range<T: Into<i64>>(minimum: T, maximum: T, default: T) -> Value {
+    json!({
+        "type": "integer",
+        "minimum": minimum.into(),
+        "maximum": maximum.into(),
+        "default": default.into(),
+    })
+}
+
+fn boolean(description: &str) -> Value {
+    json!({ "type": "boolean", "default": false, "description": description })
+}
+
+fn length(default: Option<&str>) -> Value {
+    let mut schema = json!({ "type": "string", "pattern": LENGTH_PATTERN.as_str() });
+    if let Some(default) = default {
+        schema["default"] = json!(default);
+    }
+    schema
+}
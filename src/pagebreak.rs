@@ -0,0 +1,99 @@
+//! Forced page breaks written in markdown as `<!-- pagebreak -->` or `---pagebreak---`.
+//!
+//! Directives are only recognised as top-level blocks on a line of their own, so examples
+//! inside code blocks, lists and quotes are rendered as written.
+
+use comrak::nodes::{AstNode, NodeValue};
+
+const COMMENT_DIRECTIVE: &str = "<!-- pagebreak -->";
+const LINE_DIRECTIVE: &str = "---pagebreak---";
+
+/// Stands in for a directive in comrak's output; raw HTML cannot be inserted into the tree
+/// without enabling comrak's unsafe mode for the whole document
+const PLACEHOLDER: &str = "\u{E000}pagebreak\u{E000}";
+pub const PAGE_BREAK_HTML: &str = r#"<div class="page-break"></div>"#;
+
+/// Replaces page break directives with placeholders, dropping those after the last content
+/// so the document does not end with a blank page
+pub fn mark<'a>(root: &'a AstNode<'a>) {
+    let blocks: Vec<_> = root.children().collect();
+    let last_content = blocks.iter().rposition(|node| !is_directive(node));
+
+    for (index, node) in blocks.into_iter().enumerate() {
+        if !is_directive(node) {
+            continue;
+        }
+        if last_content.is_some_and(|last| index < last) {
+            for child in node.children().collect::<Vec<_>>() {
+                child.detach();
+            }
+            node.data.borrow_mut().value = NodeValue::Text(PLACEHOLDER.to_string());
+        } else {
+            node.detach();
+        }
+    }
+}
+
+/// Turns the placeholders left by [`mark`] into page break elements
+pub fn insert(html: &str) -> String {
+    html.replace(PLACEHOLDER, PAGE_BREAK_HTML)
+}
+
+/// Whether a top-level block is a page break directive
+pub fn is_directive<'a>(node: &'a AstNode<'a>) -> bool {
+    match &node.data.borrow().value {
+        NodeValue::HtmlBlock(block) => block.literal.trim() == COMMENT_DIRECTIVE,
+        NodeValue::Paragraph => {
+            let mut children = node.children();
+            match (children.next(), children.next()) {
+                (Some(child), None) => matches!(
+                    &child.data.borrow().value,
+                    NodeValue::Text(text) if text.trim() == LINE_DIRECTIVE
+                ),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comrak::{format_html, parse_document, Arena, ComrakOptions};
+
+    fn to_html(markdown: &str) -> String {
+        let arena = Arena::new();
+        let options = ComrakOptions::default();
+        let root = parse_document(&arena, markdown, &options);
+        mark(root);
+        let mut html = Vec::new();
+        format_html(root, &options, &mut html).unwrap();
+        insert(&String::from_utf8(html).unwrap())
+    }
+
+    #[test]
+    fn both_directives_become_page_breaks() {
+        let html = to_html("One\n\n<!-- pagebreak -->\n\nTwo\n\n---pagebreak---\n\nThree\n");
+
+        assert_eq!(html.matches(PAGE_BREAK_HTML).count(), 2, "{html}");
+        assert!(!html.contains("pagebreak"), "{html}");
+    }
+
+    #[test]
+    fn directives_in_code_and_lists_are_left_as_written() {
+        let html = to_html("```\n<!-- pagebreak -->\n```\n\n- ---pagebreak---\n\nEnd\n");
+
+        assert!(!html.contains(PAGE_BREAK_HTML), "{html}");
+        assert!(html.contains("&lt;!-- pagebreak --&gt;"), "{html}");
+        assert!(html.contains("<li>---pagebreak---</li>"), "{html}");
+    }
+
+    #[test]
+    fn trailing_directives_are_dropped() {
+        let html = to_html("Text\n\n<!-- pagebreak -->\n\n---pagebreak---\n");
+
+        assert!(!html.contains(PAGE_BREAK_HTML), "{html}");
+        assert!(!html.contains(PLACEHOLDER), "{html}");
+    }
+}
@@ -0,0 +1,298 @@
+//! Page numbers in styles wkhtmltopdf cannot write itself.
+//!
+//! wkhtmltopdf only substitutes decimal numbers for `[page]` and `[topage]` in headers and
+//! footers. For roman numerals the slots containing them are left out of the wkhtmltopdf
+//! arguments, and [`stamp`] draws them onto the rendered pages instead, in Helvetica at
+//! wkhtmltopdf's default size and roughly where wkhtmltopdf would have placed them. The other
+//! placeholders in those slots are filled in the way wkhtmltopdf would, with the sections
+//! taken from the outline wkhtmltopdf builds from the headings.
+
+use crate::pdf::{self, Align, Heading, PageText};
+use crate::{length_to_points, MarkdownRequest, DEFAULT_MARGIN_SIDE};
+use chrono::Local;
+
+/// Placeholders replaced with page numbers
+const PLACEHOLDERS: &[&str] = &["[page]", "[topage]"];
+/// wkhtmltopdf's header and footer font size, in points
+const FONT_SIZE: f32 = 12.0;
+/// Roman numerals with their values, largest first
+const NUMERALS: &[(usize, &str)] = &[
+    (1000, "m"),
+    (900, "cm"),
+    (500, "d"),
+    (400, "cd"),
+    (100, "c"),
+    (90, "xc"),
+    (50, "l"),
+    (40, "xl"),
+    (10, "x"),
+    (9, "ix"),
+    (5, "v"),
+    (4, "iv"),
+    (1, "i"),
+];
+
+/// Returns whether the page numbers are drawn by [`stamp`] instead of wkhtmltopdf
+pub fn is_drawn(request: &MarkdownRequest) -> bool {
+    request.page_number_style() == "roman"
+}
+
+/// Returns whether a header or footer slot contains a page number placeholder
+pub fn has_page_number(text: &str) -> bool {
+    PLACEHOLDERS
+        .iter()
+        .any(|placeholder| text.contains(placeholder))
+}
+
+/// Draws the header and footer slots that contain page numbers onto the pages
+pub fn stamp(pdf: Vec<u8>, request: &MarkdownRequest) -> anyhow::Result<Vec<u8>> {
+    let slots: Vec<(bool, &str, &str)> = [(true, &request.header), (false, &request.footer)]
+        .into_iter()
+        .filter_map(|(is_header, band)| Some((is_header, band.as_ref()?)))
+        .flat_map(|(is_header, band)| {
+            band.slots()
+                .into_iter()
+                .filter_map(move |(slot, text)| Some((is_header, slot, text?)))
+        })
+        .filter(|(_, _, text)| has_page_number(text))
+        .collect();
+    if slots.is_empty() {
+        return Ok(pdf);
+    }
+
+    let margins = request.margins.as_ref();
+    let margin = |side: Option<&String>, default: &str| {
+        length_to_points(side.map_or(default, String::as_str))
+    };
+    let top = margin(
+        margins.and_then(|m| m.top.as_ref()),
+        crate::DEFAULT_MARGIN_TOP,
+    );
+    let bottom = margin(
+        margins.and_then(|m| m.bottom.as_ref()),
+        crate::DEFAULT_MARGIN_BOTTOM,
+    );
+    let left = margin(margins.and_then(|m| m.left.as_ref()), DEFAULT_MARGIN_SIDE);
+    let right = margin(margins.and_then(|m| m.right.as_ref()), DEFAULT_MARGIN_SIDE);
+    // Validation rejects negative offsets for roman numerals
+    let offset = usize::try_from(request.page_offset()).unwrap_or_default();
+
+    // wkhtmltopdf falls back to the page template's title
+    let title = request.title().unwrap_or_else(|| "Document".to_string());
+    let now = Local::now();
+    let (date, time) = (now.format("%x").to_string(), now.format("%X").to_string());
+    let headings = if slots.iter().any(|(_, _, text)| text.contains("section]")) {
+        pdf::outline(&pdf)?
+    } else {
+        Vec::new()
+    };
+
+    pdf::draw_text(&pdf, FONT_SIZE, |index, count, area| {
+        let [page_left, page_bottom, page_right, page_top] = area;
+        let page = roman(index + 1 + offset);
+        let last_page = roman(count + offset);
+        let (section, subsection) = sections(&headings, index + 1);
+        slots
+            .iter()
+            .map(|&(is_header, slot, text)| {
+                // wkhtmltopdf sets headers just above the content and footers just below it
+                let y = if is_header {
+                    page_top - top + FONT_SIZE * 0.3
+                } else {
+                    page_bottom + bottom - FONT_SIZE
+                };
+                let (x, align) = match slot {
+                    "left" => (page_left + left, Align::Left),
+                    "center" => ((page_left + left + page_right - right) / 2.0, Align::Center),
+                    _ => (page_right - right, Align::Right),
+                };
+                PageText {
+                    x,
+                    y,
+                    align,
+                    text: text
+                        .replace("[page]", &page)
+                        .replace("[topage]", &last_page)
+                        .replace("[title]", &title)
+                        .replace("[date]", &date)
+                        .replace("[time]", &time)
+                        .replace("[section]", section)
+                        .replace("[subsection]", subsection),
+                }
+            })
+            .collect()
+    })
+}
+
+/// Returns the titles of the top-level and second-level headings in effect on `page`
+fn sections(headings: &[Heading], page: usize) -> (&str, &str) {
+    let mut section = "";
+    let mut subsection = "";
+    for heading in headings.iter().take_while(|heading| heading.page <= page) {
+        match heading.level {
+            1 => {
+                section = &heading.title;
+                subsection = "";
+            }
+            2 => subsection = &heading.title,
+            _ => {}
+        }
+    }
+    (section, subsection)
+}
+
+/// Writes a positive number as lowercase roman numerals
+fn roman(mut number: usize) -> String {
+    let mut numerals = String::new();
+    for &(value, numeral) in NUMERALS {
+        while number >= value {
+            numerals.push_str(numeral);
+            number -= value;
+        }
+    }
+    numerals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HeaderFooter, Metadata};
+    use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+
+    /// Builds three blank pages outlined as wkhtmltopdf would for `# Introduction` on page 1,
+    /// `## Scope` on page 2 and `# Results` on page 3
+    fn outlined_pdf() -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let page_ids: Vec<ObjectId> = (0..3)
+            .map(|_| {
+                let content_id = document.add_object(Stream::new(dictionary! {}, Vec::new()));
+                document.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "Contents" => content_id,
+                })
+            })
+            .collect();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => page_ids.iter().map(|&id| id.into()).collect::<Vec<Object>>(),
+                "Count" => 3,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+
+        let [outlines, introduction, scope, results] = [(); 4].map(|()| document.new_object_id());
+        let entry = |title: &str, page: ObjectId, parent: ObjectId| {
+            dictionary! {
+                "Title" => Object::string_literal(title),
+                "Parent" => parent,
+                "Dest" => vec![page.into(), "Fit".into()],
+            }
+        };
+        let mut intro = entry("Introduction", page_ids[0], outlines);
+        intro.set("Next", results);
+        intro.set("First", scope);
+        intro.set("Last", scope);
+        intro.set("Count", 1);
+        let mut last = entry("Results", page_ids[2], outlines);
+        last.set("Prev", introduction);
+        for (id, item) in [
+            (introduction, intro),
+            (scope, entry("Scope", page_ids[1], introduction)),
+            (results, last),
+            (
+                outlines,
+                dictionary! { "Type" => "Outlines", "First" => introduction, "Last" => results },
+            ),
+        ] {
+            document.objects.insert(id, Object::Dictionary(item));
+        }
+
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "Outlines" => outlines,
+        });
+        document.trailer.set("Root", catalog_id);
+        let mut output = Vec::new();
+        document.save_to(&mut output).unwrap();
+        output
+    }
+
+    /// Returns the text drawn onto each page
+    fn stamped_text(pdf: &[u8]) -> Vec<String> {
+        let document = Document::load_mem(pdf).unwrap();
+        document
+            .get_pages()
+            .into_values()
+            .map(|page| String::from_utf8_lossy(&document.get_page_content(page)).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn roman_slots_fill_the_other_placeholders() {
+        let request = MarkdownRequest {
+            page_number_style: Some("roman".to_string()),
+            footer: Some(HeaderFooter {
+                center: Some("[title]: [section] / [subsection], [page] of [topage]".to_string()),
+                ..Default::default()
+            }),
+            metadata: Some(Metadata {
+                title: Some("Report".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let pages = stamped_text(&stamp(outlined_pdf(), &request).unwrap());
+
+        assert!(
+            pages[0].contains("(Report: Introduction / , i of iii)"),
+            "{}",
+            pages[0]
+        );
+        assert!(
+            pages[1].contains("(Report: Introduction / Scope, ii of iii)"),
+            "{}",
+            pages[1]
+        );
+        assert!(
+            pages[2].contains("(Report: Results / , iii of iii)"),
+            "{}",
+            pages[2]
+        );
+    }
+
+    #[test]
+    fn date_and_time_are_filled_and_slots_without_page_numbers_are_left_to_wkhtmltopdf() {
+        let request = MarkdownRequest {
+            page_number_style: Some("roman".to_string()),
+            header: Some(HeaderFooter {
+                left: Some("[date] [time]".to_string()),
+                right: Some("[page] on [date]".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let pages = stamped_text(&stamp(outlined_pdf(), &request).unwrap());
+
+        let date = Local::now().format("%x").to_string();
+        assert!(pages[0].contains(&format!("(i on {date})")), "{}", pages[0]);
+        assert!(!pages[0].contains("[date]"), "{}", pages[0]);
+        assert_eq!(pages[0].matches(" Tj").count(), 1, "{}", pages[0]);
+    }
+
+    #[test]
+    fn sections_follow_the_headings_up_to_the_page() {
+        let headings = pdf::outline(&outlined_pdf()).unwrap();
+
+        assert_eq!(sections(&headings, 1), ("Introduction", ""));
+        assert_eq!(sections(&headings, 2), ("Introduction", "Scope"));
+        assert_eq!(sections(&headings, 3), ("Results", ""));
+        assert_eq!(sections(&[], 1), ("", ""));
+    }
+}
@@ -0,0 +1,517 @@
+use anyhow::Context;
+use lopdf::encryption::crypt_filters::{Aes128CryptFilter, CryptFilter};
+use lopdf::{
+    text_string, Dictionary, Document, EncryptionState, EncryptionVersion, LoadOptions, Object,
+    ObjectId, Permissions, Stream, StringFormat,
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Writes the given key/value pairs into the document Info dictionary of a PDF
+pub fn set_info(pdf: &[u8], entries: &[(&str, &str)]) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::load_mem(pdf).context("Failed to parse generated PDF")?;
+
+    // Reuse the existing Info dictionary if wkhtmltopdf wrote one
+    let info_id = match document.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) => id,
+        Err(_) => {
+            let id = document.add_object(Dictionary::new());
+            document.trailer.set("Info", id);
+            id
+        }
+    };
+
+    let info = document
+        .get_object_mut(info_id)
+        .and_then(Object::as_dict_mut)
+        .context("PDF Info entry is not a dictionary")?;
+    for (key, value) in entries {
+        info.set(*key, text_string(value));
+    }
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .context("Failed to write PDF with metadata")?;
+    Ok(output)
+}
+
+/// Returned when a rendered document has more pages than `MAX_PAGES` allows
+#[derive(Debug)]
+pub struct PageLimitExceeded {
+    pub page_count: usize,
+    pub max_pages: usize,
+}
+
+impl std::fmt::Display for PageLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Document has {} pages, more than the {} allowed by MAX_PAGES",
+            self.page_count, self.max_pages
+        )
+    }
+}
+
+impl std::error::Error for PageLimitExceeded {}
+
+/// Counts the pages of a PDF, using the user password for encrypted output
+pub fn page_count(pdf: &[u8], password: Option<&str>) -> anyhow::Result<usize> {
+    let options = password.map_or_else(LoadOptions::default, LoadOptions::with_password);
+    let document =
+        Document::load_mem_with_options(pdf, options).context("Failed to parse generated PDF")?;
+    Ok(document.get_pages().len())
+}
+
+/// An entry of a PDF's outline, which wkhtmltopdf builds from the document's headings
+#[derive(Debug)]
+pub struct Heading {
+    /// Nesting depth, 1 for top-level entries
+    pub level: usize,
+    /// 1-based number of the page the heading is on
+    pub page: usize,
+    pub title: String,
+}
+
+/// Returns the outline entries in document order, or none when the PDF has no outline
+pub fn outline(pdf: &[u8]) -> anyhow::Result<Vec<Heading>> {
+    let document = Document::load_mem(pdf).context("Failed to parse generated PDF")?;
+    let Ok(toc) = document.get_toc() else {
+        return Ok(Vec::new());
+    };
+    Ok(toc
+        .toc
+        .into_iter()
+        .map(|entry| Heading {
+            level: entry.level,
+            page: entry.page,
+            title: entry.title,
+        })
+        .collect())
+}
+
+/// Fails with [`PageLimitExceeded`] if the PDF has more than `max_pages` pages
+pub fn check_page_limit(pdf: &[u8], max_pages: usize) -> anyhow::Result<()> {
+    let page_count = page_count(pdf, None)?;
+    if page_count > max_pages {
+        return Err(PageLimitExceeded {
+            page_count,
+            max_pages,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Length of the crop marks, drawn outside the bleed area (5mm)
+const CROP_MARK_LENGTH: f32 = 5.0 * 72.0 / 25.4;
+/// Crop marks use a hairline in registration black so they appear on every plate
+const CROP_MARK_STYLE: &str = "0.25 w 0 0 0 1 K";
+
+/// Page attributes that a page may inherit from its ancestors in the page tree
+const INHERITED_PAGE_ATTRIBUTES: &[&[u8]] = &[b"MediaBox", b"CropBox", b"Resources", b"Rotate"];
+
+/// Resource name of the font [`draw_text`] adds to each page
+const TEXT_FONT: &str = "FDrawText";
+/// Helvetica advance widths of the printable ASCII characters, in 1/1000 em
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+/// Width assumed for characters outside printable ASCII
+const HELVETICA_DEFAULT_WIDTH: u32 = 556;
+
+/// Checks that client-supplied bytes are an unencrypted PDF with at least one page
+pub fn validate_attachment(pdf: &[u8]) -> Result<(), String> {
+    let document = Document::load_mem(pdf).map_err(|e| format!("is not a valid PDF: {}", e))?;
+    if document.was_encrypted() || document.is_encrypted() {
+        return Err("must not be encrypted".to_string());
+    }
+    if document.get_pages().is_empty() {
+        return Err("has no pages".to_string());
+    }
+    Ok(())
+}
+
+/// Inserts the pages of `prepend` before and those of `append` after the pages of `pdf`.
+///
+/// The generated document stays the base, so its bookmarks and internal links keep
+/// pointing at the right pages; bookmarks of the inserted documents are not carried over.
+pub fn merge(pdf: &[u8], prepend: Option<&[u8]>, append: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::load_mem(pdf).context("Failed to parse generated PDF")?;
+    let pages_id = document
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Pages"))
+        .and_then(Object::as_reference)
+        .context("Generated PDF has no page tree")?;
+
+    let before = match prepend {
+        Some(bytes) => import_pages(&mut document, bytes, pages_id)?,
+        None => Vec::new(),
+    };
+    let after = match append {
+        Some(bytes) => import_pages(&mut document, bytes, pages_id)?,
+        None => Vec::new(),
+    };
+    let added = (before.len() + after.len()) as i64;
+
+    let pages = document
+        .get_object_mut(pages_id)
+        .and_then(Object::as_dict_mut)
+        .context("PDF page tree is not a dictionary")?;
+    let mut kids: Vec<Object> = before.into_iter().map(Object::Reference).collect();
+    kids.extend(
+        pages
+            .get(b"Kids")
+            .and_then(Object::as_array)
+            .cloned()
+            .unwrap_or_default(),
+    );
+    kids.extend(after.into_iter().map(Object::Reference));
+    let count = pages
+        .get(b"Count")
+        .and_then(Object::as_i64)
+        .unwrap_or_default();
+    pages.set("Kids", kids);
+    pages.set("Count", count + added);
+
+    // The inserted documents' own catalogs and page trees are no longer referenced
+    document.prune_objects();
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .context("Failed to write merged PDF")?;
+    Ok(output)
+}
+
+/// Copies every object of another PDF into `document` and returns its pages in order,
+/// reparented to `parent` with their inherited attributes made explicit
+fn import_pages(
+    document: &mut Document,
+    pdf: &[u8],
+    parent: ObjectId,
+) -> anyhow::Result<Vec<ObjectId>> {
+    let mut other = Document::load_mem(pdf).context("Failed to parse attached PDF")?;
+    other.renumber_objects_with(document.max_id + 1);
+    document.max_id = other.max_id;
+
+    let page_ids: Vec<ObjectId> = other.get_pages().into_values().collect();
+    for &page_id in &page_ids {
+        let inherited = inherited_attributes(&other, page_id);
+        let page = other
+            .get_object_mut(page_id)
+            .and_then(Object::as_dict_mut)
+            .context("PDF page is not a dictionary")?;
+        for (key, value) in inherited {
+            if !page.has(key) {
+                page.set(key.to_vec(), value);
+            }
+        }
+        page.set("Parent", parent);
+    }
+
+    document.objects.extend(other.objects);
+    Ok(page_ids)
+}
+
+/// Collects the inheritable attributes a page would otherwise get from its ancestors
+fn inherited_attributes(document: &Document, page_id: ObjectId) -> Vec<(&'static [u8], Object)> {
+    let mut attributes = Vec::new();
+    let mut node = document.get_dictionary(page_id).ok();
+    while let Some(dictionary) = node {
+        for &key in INHERITED_PAGE_ATTRIBUTES {
+            if let Ok(value) = dictionary.get(key) {
+                if !attributes.iter().any(|(existing, _)| *existing == key) {
+                    attributes.push((key, value.clone()));
+                }
+            }
+        }
+        node = dictionary
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|parent| document.get_dictionary(parent))
+            .ok();
+    }
+    attributes
+}
+
+/// Prepares every page for professional printing.
+///
+/// The page's visible area becomes its TrimBox. The page grows by `bleed` points on each
+/// side, recorded as the BleedBox, and again by the length of the crop marks drawn at the
+/// corners of the trim area.
+pub fn add_print_marks(pdf: &[u8], bleed: f32) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::load_mem(pdf).context("Failed to parse generated PDF")?;
+
+    for page_id in document.get_pages().into_values() {
+        let trim = visible_area(&document, page_id)?;
+        let outset = |by: f32| [trim[0] - by, trim[1] - by, trim[2] + by, trim[3] + by];
+
+        append_content(&mut document, page_id, crop_marks(trim, bleed))?;
+        let page = document
+            .get_object_mut(page_id)
+            .and_then(Object::as_dict_mut)
+            .context("PDF page is not a dictionary")?;
+        page.set(
+            "MediaBox",
+            rectangle_object(outset(bleed + CROP_MARK_LENGTH)),
+        );
+        page.set("BleedBox", rectangle_object(outset(bleed)));
+        page.set("TrimBox", rectangle_object(trim));
+        page.remove(b"CropBox");
+    }
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .context("Failed to write PDF with print marks")?;
+    Ok(output)
+}
+
+/// Builds the content stream that draws crop marks outside the bleed at each trim corner
+fn crop_marks(trim: [f32; 4], bleed: f32) -> String {
+    let [left, bottom, right, top] = trim;
+    let mut content = format!("q {}\n", CROP_MARK_STYLE);
+    for (x, dx) in [(left, -1.0), (right, 1.0)] {
+        for (y, dy) in [(bottom, -1.0), (top, 1.0)] {
+            let (near, far) = (bleed, bleed + CROP_MARK_LENGTH);
+            content.push_str(&format!(
+                "{} {} m {} {} l S\n{} {} m {} {} l S\n",
+                x + dx * near,
+                y,
+                x + dx * far,
+                y,
+                x,
+                y + dy * near,
+                x,
+                y + dy * far
+            ));
+        }
+    }
+    content.push_str("Q\n");
+    content
+}
+
+/// Horizontal alignment of a [`PageText`] relative to its anchor
+#[derive(Debug, Clone, Copy)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A line of text drawn onto a page by [`draw_text`]
+#[derive(Debug)]
+pub struct PageText {
+    /// Anchor on the baseline, in points from the bottom left corner of the page
+    pub x: f32,
+    pub y: f32,
+    pub align: Align,
+    pub text: String,
+}
+
+/// Draws lines of Helvetica text onto every page.
+///
+/// `texts` is called with the 0-based page index, the number of pages and the page's visible
+/// area as `[left, bottom, right, top]`. Characters outside Latin-1 are drawn as `?`.
+pub fn draw_text(
+    pdf: &[u8],
+    font_size: f32,
+    texts: impl Fn(usize, usize, [f32; 4]) -> Vec<PageText>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::load_mem(pdf).context("Failed to parse generated PDF")?;
+    let mut font = Dictionary::new();
+    font.set("Type", Object::Name(b"Font".to_vec()));
+    font.set("Subtype", Object::Name(b"Type1".to_vec()));
+    font.set("BaseFont", Object::Name(b"Helvetica".to_vec()));
+    font.set("Encoding", Object::Name(b"WinAnsiEncoding".to_vec()));
+    let font = document.add_object(font);
+
+    let page_ids: Vec<ObjectId> = document.get_pages().into_values().collect();
+    for (index, &page_id) in page_ids.iter().enumerate() {
+        let area = visible_area(&document, page_id)?;
+        let mut content = format!("q 0 g BT /{} {} Tf\n", TEXT_FONT, font_size);
+        for text in texts(index, page_ids.len(), area) {
+            let width = helvetica_width(&text.text) * font_size;
+            let x = match text.align {
+                Align::Left => text.x,
+                Align::Center => text.x - width / 2.0,
+                Align::Right => text.x - width,
+            };
+            content.push_str(&format!(
+                "1 0 0 1 {} {} Tm ({}) Tj\n",
+                x,
+                text.y,
+                pdf_string(&text.text)
+            ));
+        }
+        content.push_str("ET Q\n");
+
+        add_font(&mut document, page_id, font)?;
+        append_content(&mut document, page_id, content)?;
+    }
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .context("Failed to write PDF with page text")?;
+    Ok(output)
+}
+
+/// Returns the page's CropBox, or its MediaBox when it has none
+fn visible_area(document: &Document, page_id: ObjectId) -> anyhow::Result<[f32; 4]> {
+    let inherited = inherited_attributes(document, page_id);
+    ["CropBox", "MediaBox"]
+        .iter()
+        .find_map(|name| {
+            inherited
+                .iter()
+                .find(|(key, _)| *key == name.as_bytes())
+                .and_then(|(_, value)| rectangle(value))
+        })
+        .context("PDF page has no MediaBox")
+}
+
+/// Draws `content` over the page.
+///
+/// The existing content is wrapped in a saved graphics state so that it cannot affect the
+/// new content.
+fn append_content(
+    document: &mut Document,
+    page_id: ObjectId,
+    content: String,
+) -> anyhow::Result<()> {
+    let save = document.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let appended = document.add_object(Stream::new(
+        Dictionary::new(),
+        format!("Q\n{}", content).into_bytes(),
+    ));
+
+    let page = document
+        .get_object_mut(page_id)
+        .and_then(Object::as_dict_mut)
+        .context("PDF page is not a dictionary")?;
+    let mut contents = vec![Object::Reference(save)];
+    match page.get(b"Contents") {
+        Ok(Object::Array(streams)) => contents.extend(streams.iter().cloned()),
+        Ok(stream) => contents.push(stream.clone()),
+        Err(_) => {}
+    }
+    contents.push(Object::Reference(appended));
+    page.set("Contents", contents);
+    Ok(())
+}
+
+/// Makes the font available to the page's content as [`TEXT_FONT`]
+fn add_font(document: &mut Document, page_id: ObjectId, font: ObjectId) -> anyhow::Result<()> {
+    let resolve = |object: Option<Object>| match object {
+        Some(Object::Reference(id)) => document.get_dictionary(id).cloned().ok(),
+        Some(Object::Dictionary(dictionary)) => Some(dictionary),
+        _ => None,
+    };
+    // The resources may be shared with other pages, so the page gets its own copy
+    let mut resources = resolve(
+        inherited_attributes(document, page_id)
+            .into_iter()
+            .find(|(key, _)| *key == b"Resources")
+            .map(|(_, value)| value),
+    )
+    .unwrap_or_default();
+    let mut fonts = resolve(resources.get(b"Font").ok().cloned()).unwrap_or_default();
+    fonts.set(TEXT_FONT, font);
+    resources.set("Font", fonts);
+
+    document
+        .get_object_mut(page_id)
+        .and_then(Object::as_dict_mut)
+        .context("PDF page is not a dictionary")?
+        .set("Resources", resources);
+    Ok(())
+}
+
+/// Width of the text in Helvetica at a font size of 1
+fn helvetica_width(text: &str) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match u32::from(c) {
+            code @ 0x20..=0x7e => u32::from(HELVETICA_WIDTHS[(code - 0x20) as usize]),
+            _ => HELVETICA_DEFAULT_WIDTH,
+        })
+        .sum();
+    units as f32 / 1000.0
+}
+
+/// Encodes the text as the contents of a PDF literal string in WinAnsiEncoding
+fn pdf_string(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{}", c),
+            ' '..='~' => c.to_string(),
+            '\u{a0}'..='\u{ff}' => format!("\\{:03o}", u32::from(c)),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+/// Reads a PDF rectangle as `[left, bottom, right, top]`
+fn rectangle(object: &Object) -> Option<[f32; 4]> {
+    let values: Vec<f32> = object
+        .as_array()
+        .ok()?
+        .iter()
+        .map(Object::as_float)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let [x1, y1, x2, y2] = <[f32; 4]>::try_from(values).ok()?;
+    Some([x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)])
+}
+
+fn rectangle_object(rectangle: [f32; 4]) -> Object {
+    Object::Array(rectangle.iter().map(|&value| Object::Real(value)).collect())
+}
+
+/// Encrypts a PDF with AES-128 so that it can only be opened with the user password.
+///
+/// The owner password unlocks the operations that `permissions` does not grant.
+pub fn encrypt(
+    pdf: &[u8],
+    user_password: &str,
+    owner_password: &str,
+    permissions: Permissions,
+) -> anyhow::Result<Vec<u8>> {
+    let mut document = Document::load_mem(pdf).context("Failed to parse generated PDF")?;
+
+    // The encryption key is derived from the file identifier, which wkhtmltopdf omits
+    if document.trailer.get(b"ID").is_err() {
+        let id = Object::String(
+            Uuid::new_v4().as_bytes().to_vec(),
+            StringFormat::Hexadecimal,
+        );
+        document.trailer.set("ID", vec![id.clone(), id]);
+    }
+
+    let crypt_filter: Arc<dyn CryptFilter> = Arc::new(Aes128CryptFilter);
+    let state = EncryptionState::try_from(EncryptionVersion::V4 {
+        document: &document,
+        encrypt_metadata: true,
+        crypt_filters: BTreeMap::from([(b"StdCF".to_vec(), crypt_filter)]),
+        stream_filter: b"StdCF".to_vec(),
+        string_filter: b"StdCF".to_vec(),
+        owner_password,
+        user_password,
+        permissions,
+    })
+    .context("Failed to set up PDF encryption")?;
+    document.encrypt(&state).context("Failed to encrypt PDF")?;
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .context("Failed to write encrypted PDF")?;
+    Ok(output)
+}
@@ -0,0 +1,147 @@
+//! Post-processes rendered PDFs with Ghostscript (`gs`): conversion to PDF/A-2b, since
+//! wkhtmltopdf cannot produce archival PDFs itself, and to grayscale for output the renderer
+//! cannot draw in gray.
+//!
+//! For PDF/A, Ghostscript is asked to stop with an error rather than write a document that
+//! does not conform, so a clean exit means the output is PDF/A.
+
+use crate::backend::RenderFailed;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::error;
+
+const GHOSTSCRIPT: &str = "gs";
+/// Maximum time a single conversion may take
+const CONVERSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Maximum length of the error output returned to clients
+const MAX_DETAIL_CHARS: usize = 500;
+
+/// Returned when output that needs Ghostscript is requested but it is not installed
+#[derive(Debug)]
+pub struct GhostscriptMissing {
+    pub output: &'static str,
+}
+
+impl std::fmt::Display for GhostscriptMissing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} requires Ghostscript (gs), which is not installed on this server",
+            self.output
+        )
+    }
+}
+
+impl std::error::Error for GhostscriptMissing {}
+
+/// Reports whether Ghostscript can be started, for the health check
+pub fn is_available() -> bool {
+    std::process::Command::new(GHOSTSCRIPT)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Rewrites the PDF as PDF/A-2b, keeping its document information
+pub async fn convert(pdf: &[u8]) -> anyhow::Result<Vec<u8>> {
+    run(
+        &[
+            "-dPDFA=2",
+            // Abort instead of silently producing a non-conforming file
+            "-dPDFACompatibilityPolicy=2",
+            "-sColorConversionStrategy=RGB",
+            "-sProcessColorModel=DeviceRGB",
+        ],
+        pdf,
+        "PDF/A output",
+        "PDF/A conversion",
+    )
+    .await
+}
+
+/// Rewrites the PDF with every colour, including those of images, converted to gray
+pub async fn grayscale(pdf: &[u8]) -> anyhow::Result<Vec<u8>> {
+    run(
+        &[
+            "-sColorConversionStrategy=Gray",
+            "-dProcessColorModel=/DeviceGray",
+        ],
+        pdf,
+        "grayscale output",
+        "Grayscale conversion",
+    )
+    .await
+}
+
+/// Pipes the PDF through Ghostscript's `pdfwrite` device with the extra `args`. `requested`
+/// names the output in the error when Ghostscript is missing, and `task` the step in
+/// the error when it fails.
+async fn run(
+    args: &[&str],
+    pdf: &[u8],
+    requested: &'static str,
+    task: &'static str,
+) -> anyhow::Result<Vec<u8>> {
+    let mut child = Command::new(GHOSTSCRIPT)
+        .args(args)
+        .args([
+            "-dBATCH",
+            "-dNOPAUSE",
+            "-dNOOUTERSAVE",
+            "-dQUIET",
+            "-sDEVICE=pdfwrite",
+            // Messages go to stderr so stdout only carries the PDF
+            "-sstdout=%stderr",
+            "-sOutputFile=-",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::Error::new(GhostscriptMissing { output: requested })
+            }
+            _ => anyhow::Error::new(e).context("Failed to start Ghostscript"),
+        })?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = pdf.to_vec();
+    // Feed the input concurrently so a full stdout pipe cannot deadlock the conversion
+    let writer = tokio::spawn(async move { stdin.write_all(&input).await });
+
+    let output = tokio::time::timeout(CONVERSION_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Ghostscript did not finish within {} seconds",
+                CONVERSION_TIMEOUT.as_secs()
+            )
+        })??;
+    // Ghostscript may exit before reading all input when the PDF is broken; the exit status
+    // below reports that case
+    let _ = writer.await;
+
+    if !output.status.success() || !output.stdout.starts_with(b"%PDF") {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!(status = %output.status, stderr = %stderr, "{} failed", task);
+        return Err(RenderFailed {
+            detail: format!(
+                "{} failed: {}",
+                task,
+                crate::redact_paths(stderr.trim())
+                    .chars()
+                    .take(MAX_DETAIL_CHARS)
+                    .collect::<String>()
+            ),
+        }
+        .into());
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,118 @@
+//! Per-client rate limiting for the conversion, job and validation routes.
+//!
+//! Every client IP gets a token bucket holding up to a minute's worth of requests, refilled
+//! continuously at `RATE_LIMIT_PER_MINUTE`. Requests without a token are answered with
+//! `429 Too Many Requests` and a `Retry-After` header.
+
+use crate::ErrorResponse;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often buckets of clients that have been idle long enough to refill are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    last_pruned: Instant,
+}
+
+/// Token buckets keyed by client IP; a limit of zero disables rate limiting
+pub struct RateLimiter {
+    capacity: f64,
+    tokens_per_second: f64,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            capacity: f64::from(requests_per_minute),
+            tokens_per_second: f64::from(requests_per_minute) / 60.0,
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0.0
+    }
+
+    /// Takes a token from the client's bucket, or returns how long until one is available
+    fn acquire(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if now.duration_since(buckets.last_pruned) >= PRUNE_INTERVAL {
+            // A full bucket is the same as a missing one, so it does not need to be kept
+            buckets
+                .clients
+                .retain(|_, bucket| self.refilled(bucket, now) < self.capacity);
+            buckets.last_pruned = now;
+        }
+
+        let bucket = buckets.clients.entry(client).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.tokens_per_second,
+            ))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.tokens_per_second).min(self.capacity)
+    }
+}
+
+/// Rejects requests from clients that exceeded `RATE_LIMIT_PER_MINUTE`, when it is configured
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .filter(|limiter| limiter.is_enabled());
+    // Requests without a peer address, e.g. over a Unix socket, are not limited
+    let client = req.peer_addr().map(|addr| addr.ip());
+
+    if let Some((limiter, client)) = limiter.zip(client) {
+        if let Err(wait) = limiter.acquire(client) {
+            // Retry-After is in whole seconds, so round up to avoid an early retry
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after))
+                .json(ErrorResponse::new(format!(
+                    "Rate limit exceeded; retry in {} seconds",
+                    retry_after
+                )));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
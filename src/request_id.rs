@@ -0,0 +1,91 @@
+//! Correlation IDs for tracing a request across the gateway and this service.
+//!
+//! Each request takes its ID from the `X-Request-ID` header, or gets a new UUID. The ID is
+//! recorded on the request's tracing span, echoed in the response header and added to JSON
+//! error bodies as `request_id`.
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// Longer client-supplied IDs are replaced rather than logged
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Assigns the request ID and applies it to the span, the response and error bodies
+pub async fn assign(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path()
+    );
+    let response = next.call(req).instrument(span).await?;
+
+    let mut response = if is_json_error(&response) {
+        add_to_error_body(response, &request_id).await?
+    } else {
+        response.map_into_boxed_body()
+    };
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+/// Accepts IDs made of printable ASCII without spaces, as used by common gateways
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn is_json_error<B>(response: &ServiceResponse<B>) -> bool {
+    let status = response.status();
+    (status.is_client_error() || status.is_server_error())
+        && response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// Adds `request_id` to a JSON error object, leaving other bodies unchanged
+async fn add_to_error_body(
+    response: ServiceResponse<impl MessageBody + 'static>,
+    request_id: &str,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let (req, response) = response.into_parts();
+    let (head, body) = response.into_parts();
+    let bytes = body::to_bytes(body).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        actix_web::error::ErrorInternalServerError(e.to_string())
+    })?;
+
+    let bytes = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut object)) => {
+            object.insert("request_id".to_string(), request_id.into());
+            serde_json::to_vec(&object).unwrap_or_else(|_| bytes.to_vec())
+        }
+        _ => bytes.to_vec(),
+    };
+
+    let mut rebuilt = HttpResponse::with_body(head.status(), bytes).map_into_boxed_body();
+    *rebuilt.headers_mut() = head.headers().clone();
+    // The body length changed, so the old header must not be reused
+    rebuilt.headers_mut().remove(header::CONTENT_LENGTH);
+    Ok(ServiceResponse::new(req, rebuilt))
+}
@@ -0,0 +1,24 @@
+use std::collections::HashSet;
+
+/// Tags allowed on top of ammonia's defaults, needed for comrak's task lists and footnotes
+const EXTRA_TAGS: &[&str] = &["input", "section"];
+
+/// Attributes allowed on every tag, used by heading anchors, the table of contents and
+/// code highlighting
+const EXTRA_GENERIC_ATTRIBUTES: &[&str] = &["id", "class", "style"];
+
+/// Inline style properties the syntax highlighter emits; anything else, including `url()`
+/// values that would load external resources, is dropped
+const STYLE_PROPERTIES: &[&str] = &["color", "font-weight", "font-style", "text-decoration"];
+
+/// Strips scripts, event handlers and disallowed tags from rendered markdown
+pub fn sanitize_html(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_tags(EXTRA_TAGS)
+        .add_tag_attributes("input", &["type", "checked", "disabled"])
+        .add_generic_attributes(EXTRA_GENERIC_ATTRIBUTES)
+        .filter_style_properties(STYLE_PROPERTIES.iter().copied().collect::<HashSet<_>>())
+        .add_url_schemes(&["data"])
+        .clean(html)
+        .to_string()
+}
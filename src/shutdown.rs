@@ -0,0 +1,63 @@
+use crate::metrics::Metrics;
+use actix_web::dev::ServerHandle;
+use actix_web::web;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits for SIGTERM or SIGINT, then drains in-flight conversions and stops the server.
+///
+/// New connections are refused immediately. Conversions still running when the grace
+/// period runs out are cancelled, which also kills their wkhtmltopdf processes.
+pub async fn drain_on_signal(
+    server: ServerHandle,
+    metrics: web::Data<Metrics>,
+    grace_period: Duration,
+) {
+    wait_for_signal().await;
+
+    let in_flight = metrics.in_flight();
+    info!(
+        in_flight,
+        grace_period_secs = grace_period.as_secs(),
+        "Shutdown signal received, draining in-flight conversions"
+    );
+    server.pause().await;
+
+    let deadline = Instant::now() + grace_period;
+    while metrics.in_flight() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    let remaining = metrics.in_flight();
+    if remaining == 0 {
+        info!(drained = in_flight, "All in-flight conversions finished");
+    } else {
+        warn!(
+            drained = (in_flight - remaining).max(0),
+            cancelled = remaining,
+            "Grace period elapsed, cancelling remaining conversions"
+        );
+    }
+
+    // Finish writing completed responses unless conversions have to be cut off
+    server.stop(remaining == 0).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,103 @@
+//! Downloads the markdown for requests that name a `source_url` instead of sending content.
+//!
+//! Only hosts listed in `SOURCE_URL_ALLOWED_HOSTS` may be fetched, and redirects are not
+//! followed, so clients cannot use the service to reach internal addresses.
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("the HTTP client configuration is valid")
+});
+
+/// Returned when a `source_url` is not permitted by the server configuration
+#[derive(Debug)]
+pub struct SourceNotAllowed(pub String);
+
+impl std::fmt::Display for SourceNotAllowed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SourceNotAllowed {}
+
+/// Returned when a permitted `source_url` could not be downloaded
+#[derive(Debug)]
+pub struct SourceFetchFailed(anyhow::Error);
+
+impl std::fmt::Display for SourceFetchFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to fetch source_url: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for SourceFetchFailed {}
+
+/// Downloads the markdown at `url`, reading at most `limit` bytes.
+///
+/// `allowed_hosts` lists the hosts that may be fetched, with `*` allowing any host; `None`
+/// means `source_url` is disabled.
+pub async fn fetch_markdown(
+    url: &str,
+    allowed_hosts: Option<&[String]>,
+    limit: usize,
+) -> anyhow::Result<String> {
+    let allowed_hosts = allowed_hosts.ok_or_else(|| {
+        SourceNotAllowed(
+            "source_url is disabled on this server; set SOURCE_URL_ALLOWED_HOSTS to enable it"
+                .to_string(),
+        )
+    })?;
+
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| SourceNotAllowed(format!("Invalid source_url '{}': {}", url, e)))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(SourceNotAllowed(format!(
+            "Invalid source_url '{}'. Only http and https URLs are supported",
+            url
+        ))
+        .into());
+    }
+    let host = parsed.host_str().unwrap_or_default();
+    if !is_allowed_host(allowed_hosts, host) {
+        return Err(SourceNotAllowed(format!(
+            "source_url host '{}' is not in SOURCE_URL_ALLOWED_HOSTS",
+            host
+        ))
+        .into());
+    }
+
+    let bytes = download(parsed, limit).await.map_err(SourceFetchFailed)?;
+    String::from_utf8(bytes)
+        .map_err(|_| SourceFetchFailed(anyhow::anyhow!("the response is not valid UTF-8")).into())
+}
+
+/// Whether `host` is in a list of allowed hosts, where `*` allows any host
+pub fn is_allowed_host(allowed_hosts: &[String], host: &str) -> bool {
+    allowed_hosts
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(host))
+}
+
+async fn download(url: reqwest::Url, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let mut response = CLIENT.get(url).send().await?.error_for_status()?;
+    if response.status().is_redirection() {
+        anyhow::bail!("redirects are not followed (status {})", response.status());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > limit {
+            anyhow::bail!("response exceeds the {} byte limit", limit);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
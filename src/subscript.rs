@@ -0,0 +1,77 @@
+//! Subscripts written as `~text~`, enabled with the `subscript` extension.
+//!
+//! comrak has no subscript syntax, so after parsing the text of the document is searched for
+//! tilde pairs around text without spaces, as in `H~2~O`. Code spans and blocks are separate
+//! nodes and stay untouched. Matches are replaced with placeholders, which [`insert`] turns
+//! into `<sub>` elements once comrak has rendered the document.
+
+use comrak::nodes::{AstNode, NodeValue};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Delimits the placeholders; private use characters cannot clash with document text
+const MARKER: char = '\u{E003}';
+
+static SUBSCRIPT_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"~([^~\s]+)~").expect("valid regex"));
+
+static PLACEHOLDER_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("\u{E003}([^\u{E003}]*)\u{E003}").expect("valid regex"));
+
+/// Replaces every `~text~` in the document's text with a placeholder
+pub fn mark<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        if let NodeValue::Text(text) = &mut node.data.borrow_mut().value {
+            if SUBSCRIPT_PATTERN.is_match(text) {
+                *text = SUBSCRIPT_PATTERN
+                    .replace_all(text, format!("{MARKER}${{1}}{MARKER}"))
+                    .into_owned();
+            }
+        }
+    }
+}
+
+/// Turns the placeholders left by [`mark`] into `<sub>` elements
+pub fn insert(html: &str) -> String {
+    PLACEHOLDER_PATTERN
+        .replace_all(html, "<sub>${1}</sub>")
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::render;
+
+    #[test]
+    fn chemical_formulas_and_superscript_markers_render() {
+        let html = render(
+            "Water is H~2~O and sulfuric acid H~2~SO~4~.\n\nAs reported earlier^1^, x^2^ grows.\n",
+            &["subscript", "superscript"],
+        );
+
+        assert!(html.contains("H<sub>2</sub>O"), "{html}");
+        assert!(html.contains("H<sub>2</sub>SO<sub>4</sub>"), "{html}");
+        assert!(html.contains("earlier<sup>1</sup>,"), "{html}");
+        assert!(html.contains("x<sup>2</sup> grows"), "{html}");
+    }
+
+    #[test]
+    fn code_and_spaced_tildes_are_not_subscripts() {
+        let html = render(
+            "Use `H~2~O` in code, ~ not this ~, and\n\n```\nCO~2~\n```\n",
+            &["subscript"],
+        );
+
+        assert!(!html.contains("<sub>"), "{html}");
+        assert!(html.contains("<code>H~2~O</code>"), "{html}");
+        assert!(html.contains("~ not this ~"), "{html}");
+        assert!(html.contains("CO~2~\n"), "{html}");
+    }
+
+    #[test]
+    fn extensions_must_be_enabled() {
+        let html = render("H~2~O and x^2^\n", &[]);
+
+        assert!(html.contains("<p>H~2~O and x^2^</p>"), "{html}");
+    }
+}
@@ -0,0 +1,74 @@
+//! Server-wide page customisation: a template supplied with `HTML_TEMPLATE_PATH` in place
+//! of the built-in one, and a stylesheet from `DEFAULT_CSS_PATH` applied to every document.
+//!
+//! A template is a complete HTML document with placeholders: `{{content}}`, which is
+//! required, for the rendered document body, `{{title}}` for the escaped document title and
+//! `{{css}}` for the generated stylesheet, without `<style>` tags. Placeholders are replaced in
+//! a single pass, so text in the document that looks like a placeholder is left alone.
+
+use regex::{Captures, Regex};
+use std::path::Path;
+use std::sync::{LazyLock, OnceLock};
+
+/// Placeholder for the rendered document body, which every template must contain
+pub const CONTENT: &str = "{{content}}";
+
+static PLACEHOLDER_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{(content|title|css)\}\}").unwrap());
+
+/// The template installed at startup, if any
+static CUSTOM: OnceLock<String> = OnceLock::new();
+/// The stylesheet installed at startup, if any
+static DEFAULT_CSS: OnceLock<String> = OnceLock::new();
+
+/// Reads a template file and checks that it has a [`CONTENT`] placeholder
+pub fn load(path: &Path) -> anyhow::Result<String> {
+    let template = std::fs::read_to_string(path).map_err(|e| {
+        anyhow::anyhow!("Cannot read HTML_TEMPLATE_PATH '{}': {}", path.display(), e)
+    })?;
+    if !template.contains(CONTENT) {
+        anyhow::bail!(
+            "HTML_TEMPLATE_PATH '{}' has no {} placeholder",
+            path.display(),
+            CONTENT
+        );
+    }
+    Ok(template)
+}
+
+/// Reads the stylesheet applied to every document
+pub fn load_css(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read DEFAULT_CSS_PATH '{}': {}", path.display(), e))
+}
+
+/// Makes `template` the page template of every conversion
+pub fn install(template: String) {
+    let _ = CUSTOM.set(template);
+}
+
+/// Returns the installed template, or `None` to use the built-in one
+pub fn custom() -> Option<&'static str> {
+    CUSTOM.get().map(String::as_str)
+}
+
+/// Adds `css` to the stylesheet of every conversion
+pub fn install_css(css: String) {
+    let _ = DEFAULT_CSS.set(css);
+}
+
+/// Returns the installed stylesheet, or an empty one
+pub fn default_css() -> &'static str {
+    DEFAULT_CSS.get().map_or("", String::as_str)
+}
+
+/// Fills in the placeholders of a template
+pub fn fill(template: &str, title: &str, css: &str, content: &str) -> String {
+    PLACEHOLDER_PATTERN
+        .replace_all(template, |captures: &Captures| match &captures[1] {
+            "content" => content.to_string(),
+            "title" => title.to_string(),
+            _ => css.to_string(),
+        })
+        .into_owned()
+}
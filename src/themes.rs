@@ -0,0 +1,89 @@
+/// A named stylesheet that can be selected per request
+pub struct Theme {
+    pub name: &'static str,
+    /// Colours and typography layered on top of the base page layout
+    pub css: &'static str,
+    /// syntect theme used to highlight fenced code blocks
+    pub code_theme: &'static str,
+    /// Whether the theme relies on background colours, which are otherwise not printed
+    pub print_background: bool,
+}
+
+pub const DEFAULT_THEME: &str = "light";
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "light",
+        css: r#"
+        body {
+            font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, "Helvetica Neue", Arial, sans-serif;
+            line-height: 1.6;
+        }"#,
+        code_theme: "InspiredGitHub",
+        print_background: false,
+    },
+    Theme {
+        name: "dark",
+        css: r#"
+        html, body {
+            background: #0d1117;
+        }
+        body {
+            font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Roboto, "Helvetica Neue", Arial, sans-serif;
+            line-height: 1.6;
+            color: #c9d1d9;
+        }
+        h1, h2, h3, h4, h5, h6 { color: #e6edf3; }
+        a { color: #58a6ff; }
+        pre, code { background: #161b22; }
+        pre { padding: 0.8em; border-radius: 6px; }
+        blockquote { color: #8b949e; border-left: 0.25em solid #30363d; margin-left: 0; padding-left: 1em; }
+        table, th, td { border-color: #30363d; }"#,
+        code_theme: "base16-ocean.dark",
+        print_background: true,
+    },
+    Theme {
+        name: "sepia",
+        css: r#"
+        html, body {
+            background: #f4ecd8;
+        }
+        body {
+            font-family: Georgia, "Times New Roman", serif;
+            line-height: 1.7;
+            color: #5b4636;
+        }
+        h1, h2, h3, h4, h5, h6 { color: #433422; }
+        a { color: #8b5a2b; }
+        pre, code { background: #ebe0c5; }
+        pre { padding: 0.8em; }
+        blockquote { border-left: 0.25em solid #c8b48f; margin-left: 0; padding-left: 1em; }"#,
+        code_theme: "InspiredGitHub",
+        print_background: true,
+    },
+    Theme {
+        name: "github",
+        css: r#"
+        body {
+            font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", "Noto Sans", Helvetica, Arial, sans-serif;
+            line-height: 1.5;
+            color: #1f2328;
+        }
+        h1, h2 { padding-bottom: 0.3em; border-bottom: 1px solid #d1d9e0; }
+        a { color: #0969da; }
+        code { background: #eff1f3; padding: 0.2em 0.4em; border-radius: 6px; }
+        pre { background: #f6f8fa; padding: 1em; border-radius: 6px; }
+        pre code { background: none; padding: 0; }
+        blockquote { color: #59636e; border-left: 0.25em solid #d1d9e0; margin-left: 0; padding-left: 1em; }
+        table { border-collapse: collapse; }
+        th, td { border: 1px solid #d1d9e0; padding: 6px 13px; }
+        tr:nth-child(2n) { background: #f6f8fa; }"#,
+        code_theme: "InspiredGitHub",
+        print_background: true,
+    },
+];
+
+/// Looks up a theme by name
+pub fn find(name: &str) -> Option<&'static Theme> {
+    THEMES.iter().find(|theme| theme.name == name)
+}
@@ -0,0 +1,87 @@
+use comrak::nodes::{AstNode, NodeCode, NodeValue};
+use comrak::Anchorizer;
+
+/// Deepest heading level included in the table of contents
+const MAX_LEVEL: u8 = 3;
+
+/// Builds a nested, linked list of the h1–h3 headings of one or more documents.
+///
+/// Anchors are generated exactly as comrak's `header_ids` extension generates them,
+/// including the numeric suffixes for duplicate headings, so every link resolves. Each
+/// root is rendered separately by comrak, so each gets its own suffix numbering.
+pub fn table_of_contents<'a>(roots: &[&'a AstNode<'a>]) -> String {
+    let mut entries = Vec::new();
+
+    for root in roots {
+        let mut anchorizer = Anchorizer::new();
+        for node in root.descendants() {
+            if let NodeValue::Heading(heading) = node.data.borrow().value {
+                let mut text = String::new();
+                collect_text(node, &mut text);
+
+                // Every heading is anchorized so suffixes match the rendered ids
+                let id = anchorizer.anchorize(text.clone());
+                if heading.level <= MAX_LEVEL {
+                    entries.push((heading.level, text, id));
+                }
+            }
+        }
+    }
+
+    render(&entries)
+}
+
+/// Collects the plain text of a heading the same way comrak does for its ids
+fn collect_text<'a>(node: &'a AstNode<'a>, output: &mut String) {
+    match node.data.borrow().value {
+        NodeValue::Text(ref literal) | NodeValue::Code(NodeCode { ref literal, .. }) => {
+            output.push_str(literal)
+        }
+        NodeValue::LineBreak | NodeValue::SoftBreak => output.push(' '),
+        _ => {
+            for child in node.children() {
+                collect_text(child, output);
+            }
+        }
+    }
+}
+
+fn render(entries: &[(u8, String, String)]) -> String {
+    let Some(base_level) = entries.iter().map(|(level, _, _)| *level).min() else {
+        return String::new();
+    };
+
+    let mut html = String::from("<nav class=\"toc\">\n");
+    let mut depth = 0;
+
+    for (level, text, id) in entries {
+        let target = usize::from(level - base_level) + 1;
+
+        if target > depth {
+            while depth < target {
+                html.push_str("<ol>\n");
+                depth += 1;
+            }
+        } else {
+            html.push_str("</li>\n");
+            while depth > target {
+                html.push_str("</ol>\n</li>\n");
+                depth -= 1;
+            }
+        }
+
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            id,
+            crate::escape_html(text)
+        ));
+    }
+
+    html.push_str("</li>\n");
+    while depth > 1 {
+        html.push_str("</ol>\n</li>\n");
+        depth -= 1;
+    }
+    html.push_str("</ol>\n</nav>\n");
+    html
+}
@@ -0,0 +1,139 @@
+//! Reads `multipart/form-data` uploads and raw markdown bodies into a conversion request.
+//!
+//! For uploads the markdown comes from the `file` field, or from the zip archive in it, and
+//! every other field is treated as a request option of the same name. Raw bodies take their options from the query string
+//! the same way, so both accept exactly what the JSON endpoints accept.
+
+use crate::MarkdownRequest;
+use actix_multipart::Multipart;
+use actix_web::web;
+use futures::{StreamExt, TryStreamExt};
+use serde_json::{Map, Value};
+
+/// Name of the form field carrying the markdown source
+const FILE_FIELD: &str = "file";
+
+/// Reasons an upload cannot be turned into a request
+#[derive(Debug)]
+pub enum UploadError {
+    /// The form is malformed, has no file or has content that is not UTF-8
+    Invalid(String),
+    /// The form exceeds the byte limit shared with the JSON endpoints
+    TooLarge,
+}
+
+/// Collects the form into a request, reading at most `limit` bytes of field content
+pub async fn read_request(
+    multipart: Multipart,
+    limit: usize,
+) -> Result<MarkdownRequest, UploadError> {
+    let (file, options) = read_form(multipart, limit).await?;
+    let file = file.ok_or_else(|| {
+        UploadError::Invalid(format!("Missing '{}' field with the markdown", FILE_FIELD))
+    })?;
+    let markdown = String::from_utf8(file)
+        .map_err(|_| UploadError::Invalid(format!("Field '{}' is not valid UTF-8", FILE_FIELD)))?;
+    form_request(options, markdown).map_err(UploadError::Invalid)
+}
+
+/// Reads a form whose `file` field is a zip archive, returning the archive bytes and the
+/// other fields as options for [`form_request`]
+pub async fn read_archive_form(
+    multipart: Multipart,
+    limit: usize,
+) -> Result<(Vec<u8>, Map<String, Value>), UploadError> {
+    let (file, options) = read_form(multipart, limit).await?;
+    let archive = file.ok_or_else(|| {
+        UploadError::Invalid(format!(
+            "Missing '{}' field with the zip archive",
+            FILE_FIELD
+        ))
+    })?;
+    Ok((archive, options))
+}
+
+/// Builds a request from form options and the markdown taken from the upload, returning a
+/// client-facing message on failure
+pub fn form_request(
+    mut options: Map<String, Value>,
+    markdown: String,
+) -> Result<MarkdownRequest, String> {
+    options.insert("markdown".to_string(), Value::String(markdown));
+    serde_json::from_value(Value::Object(options)).map_err(|e| format!("Invalid form field: {}", e))
+}
+
+/// Reads every field of the form, returning the raw `file` field and the other fields as
+/// options
+async fn read_form(
+    mut multipart: Multipart,
+    limit: usize,
+) -> Result<(Option<Vec<u8>>, Map<String, Value>), UploadError> {
+    let mut options = Map::new();
+    let mut file = None;
+    let mut total_bytes = 0;
+
+    while let Some(mut field) = multipart.try_next().await.map_err(invalid_form)? {
+        let name = field.name().unwrap_or_default().to_string();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(invalid_form)? {
+            total_bytes += chunk.len();
+            if total_bytes > limit {
+                return Err(UploadError::TooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        if name == FILE_FIELD {
+            file = Some(bytes);
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| {
+                UploadError::Invalid(format!("Field '{}' is not valid UTF-8", name))
+            })?;
+            options.insert(name, form_value(text));
+        }
+    }
+    Ok((file, options))
+}
+
+/// Collects a raw markdown body into a request, taking the options from the query pairs
+pub async fn read_text_request(
+    mut payload: web::Payload,
+    query: Vec<(String, String)>,
+    limit: usize,
+) -> Result<MarkdownRequest, UploadError> {
+    let mut bytes = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk =
+            chunk.map_err(|e| UploadError::Invalid(format!("Invalid request body: {}", e)))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(UploadError::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let markdown = String::from_utf8(bytes.to_vec())
+        .map_err(|_| UploadError::Invalid("Request body is not valid UTF-8".to_string()))?;
+
+    let mut options: Map<String, Value> = query
+        .into_iter()
+        .map(|(name, value)| (name, form_value(value)))
+        .collect();
+    options.insert("markdown".to_string(), Value::String(markdown));
+
+    serde_json::from_value(Value::Object(options))
+        .map_err(|e| UploadError::Invalid(format!("Invalid query parameter: {}", e)))
+}
+
+/// Reads a form value as JSON when it is a boolean, number, object or array, so options like
+/// `toc=true` or `margins={"top":"10mm"}` work; everything else is taken as a plain string
+fn form_value(text: String) -> Value {
+    match serde_json::from_str(&text) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_) | Value::Object(_) | Value::Array(_))) => {
+            value
+        }
+        _ => Value::String(text),
+    }
+}
+
+fn invalid_form(e: actix_multipart::MultipartError) -> UploadError {
+    UploadError::Invalid(format!("Invalid multipart body: {}", e))
+}